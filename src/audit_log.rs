@@ -0,0 +1,385 @@
+//! The audit log of a guild, read from the api directly since serenity only knows about a few
+//! kinds of entries
+//!
+//! Audit log buffers are created like pin buffers and load their first page when they are
+//! opened, entering `more` in the buffer loads the next older page.
+use crate::{
+    discord::{self, account, rest},
+    on_main, plugin_print, utils,
+    utils::BufferExt,
+    weechat_utils::MessageManager,
+    Discord,
+};
+use json::JsonValue;
+use serenity::{model::prelude::*, prelude::Context};
+use std::collections::HashMap;
+use weechat::Weechat;
+
+/// Entries fetched per page, the most Discord allows
+const PAGE_SIZE: u64 = 100;
+
+/// Names of the audit log action types, as accepted by `-action`
+const ACTION_TYPES: &[(u64, &str)] = &[
+    (1, "guild_update"),
+    (10, "channel_create"),
+    (11, "channel_update"),
+    (12, "channel_delete"),
+    (13, "channel_overwrite_create"),
+    (14, "channel_overwrite_update"),
+    (15, "channel_overwrite_delete"),
+    (20, "member_kick"),
+    (21, "member_prune"),
+    (22, "member_ban_add"),
+    (23, "member_ban_remove"),
+    (24, "member_update"),
+    (25, "member_role_update"),
+    (26, "member_move"),
+    (27, "member_disconnect"),
+    (28, "bot_add"),
+    (30, "role_create"),
+    (31, "role_update"),
+    (32, "role_delete"),
+    (40, "invite_create"),
+    (41, "invite_update"),
+    (42, "invite_delete"),
+    (50, "webhook_create"),
+    (51, "webhook_update"),
+    (52, "webhook_delete"),
+    (60, "emoji_create"),
+    (61, "emoji_update"),
+    (62, "emoji_delete"),
+    (72, "message_delete"),
+    (73, "message_bulk_delete"),
+    (74, "message_pin"),
+    (75, "message_unpin"),
+    (80, "integration_create"),
+    (81, "integration_update"),
+    (82, "integration_delete"),
+];
+
+/// The entries loaded into an audit log buffer, kept on the buffer
+#[derive(Debug, Default)]
+struct AuditLog {
+    /// Entries of all loaded pages, newest first
+    entries: Vec<AuditLogEntry>,
+    /// Whether a page is being fetched, so `more` does not load the same page twice
+    loading: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Change {
+    key: String,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct AuditLogEntry {
+    id: AuditLogEntryId,
+    user: String,
+    action_type: u64,
+    target: Option<String>,
+    changes: Vec<Change>,
+    reason: Option<String>,
+}
+
+impl AuditLogEntry {
+    fn from_json(
+        ctx: &Context,
+        entry: &JsonValue,
+        users: &HashMap<String, String>,
+    ) -> Option<AuditLogEntry> {
+        let user_name = |id: &str| users.get(id).cloned().unwrap_or_else(|| id.to_owned());
+        let action_type = entry["action_type"].as_u64()?;
+
+        let target = entry["target_id"].as_str().map(|id| {
+            if users.contains_key(id) {
+                return user_name(id);
+            }
+            let id_number = id.parse().unwrap_or(0);
+            let cache = ctx.cache.read();
+            if let Some(channel) = cache.channels.get(&ChannelId(id_number)) {
+                return format!("#{}", channel.read().name);
+            }
+            let role = cache
+                .guilds
+                .values()
+                .find_map(|guild| guild.read().roles.get(&RoleId(id_number)).cloned());
+            match role {
+                Some(role) => format!("@{}", role.name),
+                None => id.to_owned(),
+            }
+        });
+
+        Some(AuditLogEntry {
+            id: AuditLogEntryId(entry["id"].as_str()?.parse().ok()?),
+            user: entry["user_id"]
+                .as_str()
+                .map(user_name)
+                .unwrap_or_else(|| "Unknown".to_owned()),
+            action_type,
+            target,
+            changes: entry["changes"]
+                .members()
+                .filter_map(|change| {
+                    Some(Change {
+                        key: change["key"].as_str()?.to_owned(),
+                        old: format_value(&change["old_value"]),
+                        new: format_value(&change["new_value"]),
+                    })
+                })
+                .collect(),
+            reason: entry["reason"]
+                .as_str()
+                .filter(|reason| !reason.is_empty())
+                .map(ToOwned::to_owned),
+        })
+    }
+}
+
+/// Format the value of a change, roles that were added or removed are listed by name
+fn format_value(value: &JsonValue) -> Option<String> {
+    if value.is_null() {
+        return None;
+    }
+    if value.is_array() {
+        let items: Vec<_> = value
+            .members()
+            .map(|item| match item["name"].as_str() {
+                Some(name) => name.to_owned(),
+                None => item.dump(),
+            })
+            .collect();
+        return Some(items.join(", "));
+    }
+    Some(match value.as_str() {
+        Some(value) => value.to_owned(),
+        None => value.dump(),
+    })
+}
+
+pub fn parse_action_type(name: &str) -> Option<u64> {
+    let name = name.to_lowercase();
+    ACTION_TYPES
+        .iter()
+        .find(|(_, action)| *action == name)
+        .map(|(action_type, _)| *action_type)
+        .or_else(|| name.parse().ok())
+}
+
+fn action_name(action_type: u64) -> String {
+    ACTION_TYPES
+        .iter()
+        .find(|(it, _)| *it == action_type)
+        .map(|(_, name)| name.replace('_', " "))
+        .unwrap_or_else(|| format!("action {}", action_type))
+}
+
+pub fn action_type_names() -> impl Iterator<Item = &'static str> {
+    ACTION_TYPES.iter().map(|(_, name)| *name)
+}
+
+pub fn buffer_id_for_audit_log(guild_id: GuildId) -> String {
+    account::namespaced(format!("AuditLog.{}", guild_id.0))
+}
+
+pub fn audit_log_for_guild(buffer: &weechat::Buffer) -> Option<GuildId> {
+    buffer
+        .get_localvar("audit_log_for_guild")
+        .and_then(|id| id.parse().ok())
+        .map(GuildId)
+}
+
+/// Create an audit log buffer, or change the filters of an existing one
+pub fn create_audit_log_buffer(
+    weecord: &Discord,
+    guild_id: GuildId,
+    guild_name: &str,
+    user: Option<UserId>,
+    action_type: Option<u64>,
+) {
+    let buffer_name = buffer_id_for_audit_log(guild_id);
+    let buffer = weecord.buffer_manager.get_or_create_buffer(&buffer_name);
+
+    buffer.set_title(&format!("Audit log of {}", guild_name));
+    buffer.set_full_name(&format!("Audit log of {}", guild_name));
+    buffer.set_short_name(&format!("{} audit log", guild_name));
+    buffer.set_localvar("type", "audit_log");
+    buffer.set_localvar("audit_log_for_guild", &guild_id.0.to_string());
+    buffer.set_localvar(
+        "audit_log_user",
+        &user.map(|id| id.0.to_string()).unwrap_or_default(),
+    );
+    buffer.set_localvar(
+        "audit_log_action",
+        &action_type.map(|t| t.to_string()).unwrap_or_default(),
+    );
+    // Loaded before switching so the buffer switch does not load it a second time
+    load_audit_log_buffer_history(&buffer);
+    buffer.switch_to();
+}
+
+/// Load the first page of an audit log buffer
+pub fn load_audit_log_buffer_history(buffer: &MessageManager) {
+    buffer.set_history_loaded();
+    buffer.clear();
+    buffer.set_list(AuditLog::default());
+    load_page(buffer);
+}
+
+/// Handle input in an audit log buffer, "more" loads the next older page
+pub fn audit_log_buffer_input(weecord: &Discord, buffer: &weechat::Buffer, text: &str) {
+    if text != "more" {
+        buffer.print(&format!(
+            "{}\tEnter \"more\" to load older entries",
+            weecord.get_prefix("error")
+        ));
+        return;
+    }
+    if let Some(buffer) = weecord.buffer_manager.get_buffer(&*buffer.get_name()) {
+        load_page(&buffer);
+    }
+}
+
+fn load_page(buffer: &MessageManager) {
+    let guild_id = match audit_log_for_guild(buffer) {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
+    let buffer_name = buffer.get_name().into_owned();
+    if buffer.with_list(|log: &mut AuditLog| log.loading) != Some(false) {
+        return;
+    }
+    let before = buffer
+        .with_list(|log: &mut AuditLog| {
+            log.loading = true;
+            log.entries.last().map(|entry| entry.id)
+        })
+        .flatten();
+
+    let mut path = format!("/guilds/{}/audit-logs?limit={}", guild_id.0, PAGE_SIZE);
+    for (param, localvar) in &[
+        ("user_id", "audit_log_user"),
+        ("action_type", "audit_log_action"),
+    ] {
+        if let Some(value) = buffer.get_localvar(localvar).filter(|v| !v.is_empty()) {
+            path.push_str(&format!("&{}={}", param, value));
+        }
+    }
+    if let Some(before) = before {
+        path.push_str(&format!("&before={}", before.0));
+    }
+
+    account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let log = match rest::get(ctx, &path) {
+            Ok(log) => log,
+            Err(e) => {
+                plugin_print(&format!("Unable to fetch the audit log: {}", e));
+                on_main(move |weecord| {
+                    if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                        buffer.with_list(|log: &mut AuditLog| log.loading = false);
+                    }
+                });
+                return;
+            },
+        };
+
+        let users: HashMap<_, _> = log["users"]
+            .members()
+            .filter_map(|user| {
+                let name = user["username"].as_str()?;
+                let name = match user["discriminator"].as_str() {
+                    Some(discriminator) if discriminator != "0" => {
+                        format!("{}#{}", name, discriminator)
+                    },
+                    _ => name.to_owned(),
+                };
+                Some((user["id"].as_str()?.to_owned(), name))
+            })
+            .collect();
+        let entries: Vec<_> = log["audit_log_entries"]
+            .members()
+            .filter_map(|entry| AuditLogEntry::from_json(ctx, entry, &users))
+            .collect();
+
+        on_main(move |weecord| {
+            let buffer = match weecord.buffer_manager.get_buffer(&buffer_name) {
+                Some(buffer) => buffer,
+                None => return,
+            };
+            let has_more = entries.len() as u64 == PAGE_SIZE;
+            let all_entries = buffer.with_list(|log: &mut AuditLog| {
+                // The buffer was reloaded while this page was fetched
+                if log.entries.last().map(|entry| entry.id) != before {
+                    return None;
+                }
+                log.loading = false;
+                for entry in entries {
+                    if !log.entries.iter().any(|it| it.id == entry.id) {
+                        log.entries.push(entry);
+                    }
+                }
+                Some(log.entries.clone())
+            });
+            if let Some(all_entries) = all_entries.flatten() {
+                draw_entries(weecord, &buffer, &all_entries, has_more);
+            }
+        });
+    });
+}
+
+fn draw_entries(
+    weechat: &Weechat,
+    buffer: &weechat::Buffer,
+    entries: &[AuditLogEntry],
+    has_more: bool,
+) {
+    buffer.clear();
+    if entries.is_empty() {
+        buffer.print("There are no audit log entries");
+        return;
+    }
+
+    if has_more {
+        buffer.print("Enter \"more\" to load older entries");
+    }
+    for entry in entries.iter().rev() {
+        let date = entry.id.created_at().timestamp();
+        for line in format_entry(weechat, entry) {
+            buffer.print_tags_dated(date, "notify_none", &line);
+        }
+    }
+}
+
+fn format_entry(weechat: &Weechat, entry: &AuditLogEntry) -> Vec<String> {
+    let mut title = format!(
+        "{}\t{}",
+        utils::format_nick_color(weechat, &entry.user),
+        action_name(entry.action_type)
+    );
+    if let Some(target) = &entry.target {
+        title.push_str(&format!(
+            " {}",
+            utils::colorize_string(weechat, "bold", target)
+        ));
+    }
+    if let Some(reason) = &entry.reason {
+        title.push_str(&format!(" (reason: {})", reason));
+    }
+
+    let mut lines = vec![title];
+    for change in &entry.changes {
+        let line = match (&change.old, &change.new) {
+            (Some(old), Some(new)) => format!("{}: {} -> {}", change.key, old, new),
+            (None, Some(new)) => format!("{}: {}", change.key, new),
+            (Some(old), None) => format!("{}: {} (removed)", change.key, old),
+            (None, None) => continue,
+        };
+        lines.push(format!("\t  {}", line));
+    }
+    lines
+}
@@ -1,4 +1,4 @@
-use crate::utils::BufferExt;
+use crate::{discord::account, utils, utils::BufferExt};
 use serenity::model::id::{ChannelId, GuildId};
 use std::borrow::Cow;
 use weechat::{bar::BarItem, ConfigOption, Weechat};
@@ -7,7 +7,10 @@ pub struct BarHandles {
     _guild_name: BarItem<()>,
     _channel_name: BarItem<()>,
     _full_name: BarItem<()>,
+    _category: BarItem<()>,
     _typing_indicator: BarItem<()>,
+    _dm_status: BarItem<()>,
+    _status: BarItem<()>,
 }
 
 pub fn init(weechat: &Weechat) -> BarHandles {
@@ -50,12 +53,28 @@ pub fn init(weechat: &Weechat) -> BarHandles {
         None,
     );
 
+    let _category = weechat.new_bar_item(
+        "buffer_discord_category",
+        |_, _, buffer| {
+            buffer
+                .get_localvar("category")
+                .map(Cow::into_owned)
+                .unwrap_or_default()
+        },
+        None,
+    );
+
     let _typing_indicator = weechat.new_bar_item(
         "discord_typing",
         |_, _, buffer| {
+            let _account = account::enter(&account::for_buffer(&buffer));
             if let Some(channel_id) = buffer.channel_id() {
                 let weechat = buffer.get_weechat();
-                let config = &crate::upgrade_plugin(&weechat).config;
+                let weecord = match crate::upgrade_plugin(&weechat) {
+                    Some(weecord) => weecord,
+                    None => return "".into(),
+                };
+                let config = &weecord.config;
                 let max_users = config.user_typing_list_max.value() as usize;
                 let expanded = config.user_typing_list_expanded.value();
                 let guild_id = buffer.guild_id();
@@ -72,11 +91,53 @@ pub fn init(weechat: &Weechat) -> BarHandles {
         None,
     );
 
+    let _dm_status = weechat.new_bar_item(
+        "discord_dm_status",
+        |_, _, buffer| {
+            let _account = account::enter(&account::for_buffer(&buffer));
+            // Guild channels have no single partner to show the status of
+            if buffer.guild_id().is_some() {
+                return "".into();
+            }
+            let channel_id = match buffer.channel_id() {
+                Some(channel_id) => channel_id,
+                None => return "".into(),
+            };
+            let ctx = &match crate::discord::get_ctx() {
+                Some(ctx) => ctx,
+                None => return "".into(),
+            };
+
+            let cache = ctx.cache.read();
+            match cache.private_channels.get(&channel_id) {
+                Some(channel) => {
+                    let recipient = channel.read().recipient.read().id;
+                    utils::format_presence(&buffer.get_weechat(), cache.presences.get(&recipient))
+                },
+                None => "".into(),
+            }
+        },
+        None,
+    );
+
+    let _status = weechat.new_bar_item(
+        "discord_status",
+        |_, _, buffer| {
+            let _account = account::enter(&account::for_buffer(&buffer));
+            let state = crate::discord::connection::state();
+            utils::colorize_string(&buffer.get_weechat(), state.color(), &state.short_name())
+        },
+        None,
+    );
+
     BarHandles {
         _guild_name,
         _channel_name,
         _full_name,
+        _category,
         _typing_indicator,
+        _dm_status,
+        _status,
     }
 }
 
@@ -122,7 +183,12 @@ fn get_users_for_typing_list(
     guild_id: Option<GuildId>,
     max_names: usize,
 ) -> (Vec<String>, bool) {
-    let mut users = crate::discord::TYPING_EVENTS
+    let session = match account::session() {
+        Some(session) => session,
+        None => return (Vec::new(), false),
+    };
+    let mut users = session
+        .typing
         .lock()
         .entries
         .iter()
@@ -0,0 +1,364 @@
+//! Personal bookmarks of messages, kept in a local file instead of on Discord
+//!
+//! Each bookmark keeps a snapshot of the message so it can be listed without fetching it again,
+//! the bookmarks buffer lists them with notes and jumps to their channels like the pins buffer.
+use crate::{
+    buffers,
+    discord::{self, account},
+    on_main, plugin_print, utils,
+    utils::BufferExt,
+    weechat_utils::MessageManager,
+    Discord,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use json::JsonValue;
+use serenity::model::prelude::*;
+use std::{fs, io, path::PathBuf};
+use weechat::Weechat;
+
+const BOOKMARKS_FILE: &str = "weecord_bookmarks.json";
+
+#[derive(Debug, Clone)]
+pub struct Bookmark {
+    /// The account the message was bookmarked from
+    pub account: String,
+    pub guild_id: Option<GuildId>,
+    pub channel_id: ChannelId,
+    pub message_id: MessageId,
+    pub author: String,
+    pub content: String,
+    pub note: Option<String>,
+    pub saved_at: DateTime<Utc>,
+}
+
+impl Bookmark {
+    fn from_json(bookmark: &JsonValue) -> Option<Bookmark> {
+        Some(Bookmark {
+            account: bookmark["account"].as_str().unwrap_or_default().to_owned(),
+            guild_id: match bookmark["guild_id"].as_str() {
+                Some(id) => Some(GuildId(id.parse().ok()?)),
+                None => None,
+            },
+            channel_id: ChannelId(bookmark["channel_id"].as_str()?.parse().ok()?),
+            message_id: MessageId(bookmark["message_id"].as_str()?.parse().ok()?),
+            author: bookmark["author"].as_str()?.to_owned(),
+            content: bookmark["content"].as_str()?.to_owned(),
+            note: bookmark["note"].as_str().map(ToOwned::to_owned),
+            saved_at: Utc
+                .timestamp_opt(bookmark["saved_at"].as_i64()?, 0)
+                .single()?,
+        })
+    }
+
+    // Ids are stored as strings like the api does, they do not fit in a json number
+    fn to_json(&self) -> JsonValue {
+        json::object! {
+            "account" => self.account.as_str(),
+            "guild_id" => self.guild_id.map(|id| id.0.to_string()),
+            "channel_id" => self.channel_id.0.to_string(),
+            "message_id" => self.message_id.0.to_string(),
+            "author" => self.author.as_str(),
+            "content" => self.content.as_str(),
+            "note" => self.note.as_deref(),
+            "saved_at" => self.saved_at.timestamp(),
+        }
+    }
+}
+
+/// The bookmarks file, in the weechat data directory
+fn bookmarks_path(weechat: &Weechat) -> PathBuf {
+    let dir = weechat
+        .info_get("weechat_data_dir", "")
+        .or_else(|| weechat.info_get("weechat_dir", ""))
+        .map(|dir| dir.into_owned())
+        .unwrap_or_else(|| ".".to_owned());
+    PathBuf::from(dir).join(BOOKMARKS_FILE)
+}
+
+/// Read the saved bookmarks, oldest first
+///
+/// A missing file means there are no bookmarks yet, any other error is returned so the file is
+/// not overwritten by saving the bookmarks that could be read.
+pub fn load(weechat: &Weechat) -> Result<Vec<Bookmark>, String> {
+    let contents = match fs::read_to_string(bookmarks_path(weechat)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Unable to read bookmarks: {}", e)),
+    };
+    match json::parse(&contents) {
+        Ok(bookmarks) => Ok(bookmarks
+            .members()
+            .filter_map(Bookmark::from_json)
+            .collect()),
+        Err(e) => Err(format!("Unable to read bookmarks: {}", e)),
+    }
+}
+
+/// Write the bookmarks to a temporary file first, so a failed write leaves the old file intact
+fn save(weechat: &Weechat, bookmarks: &[Bookmark]) -> io::Result<()> {
+    let bookmarks = JsonValue::Array(bookmarks.iter().map(Bookmark::to_json).collect());
+    let path = bookmarks_path(weechat);
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, bookmarks.pretty(2))?;
+    fs::rename(&tmp_path, &path)
+}
+
+/// Bookmark a message of a channel buffer by `[n|id]`, fetching it for the snapshot
+pub fn add(buffer: &MessageManager, arg: Option<&str>, note: Option<String>) {
+    let channel_id = match buffer.channel_id() {
+        Some(channel_id) => channel_id,
+        None => {
+            plugin_print("bookmark must be run in a channel buffer");
+            return;
+        },
+    };
+    let guild_id = buffer.guild_id();
+    let message_id = match utils::message_id_for_arg(buffer, arg) {
+        Some(message_id) => message_id,
+        None => {
+            plugin_print("Could not find that message");
+            return;
+        },
+    };
+    let account = account::for_buffer(buffer);
+
+    account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let msg = match channel_id.message(ctx, message_id) {
+            Ok(msg) => msg,
+            Err(e) => {
+                plugin_print(&format!("Unable to fetch the message: {}", e));
+                return;
+            },
+        };
+
+        let bookmark = Bookmark {
+            account,
+            guild_id,
+            channel_id,
+            message_id,
+            author: msg.author.name.clone(),
+            content: msg.content.clone(),
+            note,
+            saved_at: Utc::now(),
+        };
+        on_main(move |weecord| {
+            let mut bookmarks = match load(weecord) {
+                Ok(bookmarks) => bookmarks,
+                Err(e) => {
+                    plugin_print(&format!("{}, not saving the bookmark", e));
+                    return;
+                },
+            };
+            bookmarks.retain(|it| it.message_id != bookmark.message_id);
+            bookmarks.push(bookmark);
+            match save(weecord, &bookmarks) {
+                Ok(()) => plugin_print("Message bookmarked"),
+                Err(e) => plugin_print(&format!("Unable to save bookmarks: {}", e)),
+            }
+            refresh_bookmarks_buffer(weecord);
+        });
+    });
+}
+
+pub fn buffer_id_for_bookmarks() -> String {
+    // Bookmarks of every account are kept together, so the buffer is not namespaced
+    "Bookmarks".to_owned()
+}
+
+/// Open the bookmarks buffer
+pub fn open_bookmarks(weecord: &Discord) {
+    let buffer = buffers::create_list_buffer(
+        weecord,
+        &buffer_id_for_bookmarks(),
+        "bookmarks",
+        "Bookmarks",
+        "Bookmarked messages",
+        &[],
+    );
+
+    draw_bookmarks(weecord, &buffer);
+}
+
+/// Redraw the bookmarks buffer, if it is open
+pub fn refresh_bookmarks_buffer(weecord: &Discord) {
+    if let Some(buffer) = weecord
+        .buffer_manager
+        .get_buffer(&buffer_id_for_bookmarks())
+    {
+        draw_bookmarks(weecord, &buffer);
+    }
+}
+
+fn draw_bookmarks(weecord: &Discord, buffer: &MessageManager) {
+    buffer.clear();
+    let bookmarks = match load(weecord) {
+        Ok(bookmarks) => bookmarks,
+        Err(e) => {
+            buffer.print(&format!("{}\t{}", weecord.get_prefix("error"), e));
+            return;
+        },
+    };
+    if bookmarks.is_empty() {
+        buffer.print(
+            "There are no bookmarks, add one with /discord bookmark [<n>|<message id>] [<note>]",
+        );
+        return;
+    }
+
+    // Numbered like pins, counting back from the newest
+    for (i, bookmark) in bookmarks.iter().enumerate() {
+        let number = bookmarks.len() - i;
+        for line in format_bookmark(weecord, number, bookmark) {
+            buffer.print_tags_dated(
+                bookmark.message_id.created_at().timestamp(),
+                "notify_none",
+                &line,
+            );
+        }
+    }
+    buffer.print("");
+    buffer.print(
+        "Enter \"jump <n>\" to open the channel of a bookmark or \"delete <n>\" to remove it",
+    );
+}
+
+fn format_bookmark(weechat: &Weechat, number: usize, bookmark: &Bookmark) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut content = bookmark.content.lines();
+    lines.push(format!(
+        "{}\t{}",
+        utils::format_nick_color(weechat, &bookmark.author),
+        content.next().unwrap_or_default()
+    ));
+    lines.extend(content.map(|line| format!("\t{}", line)));
+    if let Some(note) = &bookmark.note {
+        lines.push(format!(
+            "\t{} {}",
+            utils::colorize_string(weechat, "bold", "Note:"),
+            note
+        ));
+    }
+    let reference = format!(
+        "[{}] in {}, message {}, saved {} (\"jump {}\" to open the channel)",
+        number,
+        channel_name(bookmark),
+        bookmark.message_id.0,
+        bookmark
+            .saved_at
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M"),
+        number
+    );
+    lines.push(format!(
+        "\t{}",
+        utils::colorize_string(weechat, "8", &reference)
+    ));
+    lines
+}
+
+/// The name of the channel of a bookmark, if its account knows about it
+fn channel_name(bookmark: &Bookmark) -> String {
+    let _account = account::enter(&bookmark.account);
+    discord::get_ctx()
+        .and_then(|ctx| bookmark.channel_id.to_channel_cached(&ctx.cache))
+        .map(|channel| match channel {
+            Channel::Guild(_) => format!("#{}", channel.name()),
+            _ => channel.name(),
+        })
+        .unwrap_or_else(|| format!("channel {}", bookmark.channel_id.0))
+}
+
+/// Handle input in the bookmarks buffer, which jumps to or deletes bookmarks
+pub fn bookmarks_buffer_input(weecord: &Discord, buffer: &weechat::Buffer, text: &str) {
+    let mut words = text.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("jump"), arg) => jump_to_bookmark(weecord, buffer, arg),
+        (Some("delete"), arg) => delete_bookmark(weecord, buffer, arg),
+        _ => buffer.print(&format!(
+            "{}\tEnter \"jump <n>\" or \"delete <n>\"",
+            weecord.get_prefix("error")
+        )),
+    }
+}
+
+/// The index of the bookmark numbered `arg`, printing an error if there is none
+fn bookmark_index(
+    weecord: &Discord,
+    buffer: &weechat::Buffer,
+    bookmarks: &[Bookmark],
+    arg: Option<&str>,
+) -> Option<usize> {
+    match arg.and_then(|n| n.parse::<usize>().ok()) {
+        Some(n) if n >= 1 && n <= bookmarks.len() => Some(bookmarks.len() - n),
+        _ => {
+            buffer.print(&format!(
+                "{}\tNo bookmark {:?}, enter the number of a bookmark",
+                weecord.get_prefix("error"),
+                arg.unwrap_or_default()
+            ));
+            None
+        },
+    }
+}
+
+/// Switch to the channel of a bookmark and show which message it was
+fn jump_to_bookmark(weecord: &Discord, buffer: &weechat::Buffer, arg: Option<&str>) {
+    let bookmarks = match load(weecord) {
+        Ok(bookmarks) => bookmarks,
+        Err(e) => {
+            buffer.print(&format!("{}\t{}", weecord.get_prefix("error"), e));
+            return;
+        },
+    };
+    let bookmark = match bookmark_index(weecord, buffer, &bookmarks, arg) {
+        Some(index) => &bookmarks[index],
+        None => return,
+    };
+
+    let _account = account::enter(&bookmark.account);
+    match buffers::channel_buffer(weecord, bookmark.channel_id) {
+        Some(channel_buffer) => {
+            let date = Utc
+                .timestamp_opt(bookmark.message_id.created_at().timestamp(), 0)
+                .single()
+                .map(|date| {
+                    date.with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string()
+                })
+                .unwrap_or_default();
+            channel_buffer.switch_to();
+            channel_buffer.print(&format!(
+                "{}\tBookmarked message by {} from {}, message {}",
+                weecord.get_prefix("network"),
+                bookmark.author,
+                date,
+                bookmark.message_id.0
+            ));
+        },
+        None => plugin_print("The channel of this bookmark is not open"),
+    }
+}
+
+fn delete_bookmark(weecord: &Discord, buffer: &weechat::Buffer, arg: Option<&str>) {
+    let mut bookmarks = match load(weecord) {
+        Ok(bookmarks) => bookmarks,
+        Err(e) => {
+            buffer.print(&format!("{}\t{}", weecord.get_prefix("error"), e));
+            return;
+        },
+    };
+    let index = match bookmark_index(weecord, buffer, &bookmarks, arg) {
+        Some(index) => index,
+        None => return,
+    };
+    bookmarks.remove(index);
+    if let Err(e) = save(weecord, &bookmarks) {
+        plugin_print(&format!("Unable to save bookmarks: {}", e));
+    }
+    refresh_bookmarks_buffer(weecord);
+}
@@ -1,4 +1,5 @@
 use crate::{
+    discord::{account, folders, member_list},
     on_main,
     sync::on_main_blocking,
     utils,
@@ -20,16 +21,16 @@ use std::{
 };
 use weechat::{buffer::HotlistPriority, Buffer, ConfigOption, NickArgs, Weechat};
 
-const OFFLINE_GROUP_NAME: &str = "99999|Offline";
-const ONLINE_GROUP_NAME: &str = "99998|Online";
-const BOT_GROUP_NAME: &str = "99997|Bot";
+// Hoisted roles are sorted above these by `role_group_name`
+pub const OFFLINE_GROUP_NAME: &str = "99999|Offline";
+pub const ONLINE_GROUP_NAME: &str = "99998|Online";
 
 pub fn init(weechat: &Weechat) -> BufferManager {
     BufferManager::new(Weechat::from_ptr(weechat.as_ptr()))
 }
 
 pub fn create_buffers(ready_data: &Ready) {
-    let ctx = match crate::discord::get_ctx() {
+    let ctx = &match crate::discord::get_ctx() {
         Some(ctx) => ctx,
         _ => return,
     };
@@ -46,8 +47,19 @@ pub fn create_buffers(ready_data: &Ready) {
 
     let mut sorted_guilds = VecDeque::new();
 
+    let folders = folders::cached();
+    // Folders hold every guild in client order, older accounts may only have positions
+    let guild_positions: Vec<_> = if folders.is_empty() {
+        ready_data.user_settings.guild_positions.clone()
+    } else {
+        folders
+            .iter()
+            .flat_map(|folder| folder.guild_ids.iter().cloned())
+            .collect()
+    };
+
     // Add the guilds ordered from the client
-    for guild_id in &ready_data.user_settings.guild_positions {
+    for guild_id in &guild_positions {
         if let Some(guild) = map.remove(&guild_id) {
             sorted_guilds.push_back(guild);
         }
@@ -79,19 +91,29 @@ pub fn create_buffers(ready_data: &Ready) {
             format!("@{}", current_user.name)
         };
         let channels = guild.id.channels(ctx).expect("Unable to fetch channels");
-        let mut channels = channels.values().collect::<Vec<_>>();
-        channels.sort_by_key(|g| g.position);
-        for channel in channels {
+        let sorted_channels = utils::sort_guild_channels(&channels);
+        if let Some(session) = account::session() {
+            session
+                .channel_order
+                .lock()
+                .insert(guild.id, sorted_channels.iter().map(|ch| ch.id).collect());
+        }
+        for channel in sorted_channels {
             let is_muted =
                 guild_muted || channel_muted.get(&channel.id).cloned().unwrap_or_default();
             create_buffer_from_channel(&ctx.cache, &guild.name, &channel, &nick, is_muted);
         }
+
+        set_folder_localvars(ctx, guild.id, channels.keys().cloned().collect());
+
+        print_channel_tree(guild.id);
+        crate::voice::print_guild_voice_overview(guild.id);
     }
 }
 
 // TODO: Merge these functions
 pub fn create_autojoin_buffers(_ready: &Ready) {
-    let ctx = match crate::discord::get_ctx() {
+    let ctx = &match crate::discord::get_ctx() {
         Some(ctx) => ctx,
         _ => return,
     };
@@ -99,9 +121,11 @@ pub fn create_autojoin_buffers(_ready: &Ready) {
     let current_user = ctx.cache.read().user.clone();
 
     // TODO: Add sorting
-    let mut autojoin_items: Vec<_> = on_main_blocking(|weecord| weecord.config.autojoin_channels());
+    let mut autojoin_items: Vec<_> =
+        on_main_blocking(|weecord| weecord.config.autojoin_channels()).unwrap_or_default();
 
-    let watched_items: Vec<_> = on_main_blocking(|weecord| weecord.config.watched_channels());
+    let watched_items: Vec<_> =
+        on_main_blocking(|weecord| weecord.config.watched_channels()).unwrap_or_default();
 
     let watched_channels = utils::flatten_guilds(&ctx, &watched_items);
 
@@ -161,8 +185,8 @@ pub fn create_buffers_from_flat_items(
 
                 for channel in channels {
                     // TODO: Muting
-                    let () = on_main_blocking(move |_| {
-                        let ctx = match crate::discord::get_ctx() {
+                    let _: Option<()> = on_main_blocking(move |_| {
+                        let ctx = &match crate::discord::get_ctx() {
                             Some(ctx) => ctx,
                             _ => return,
                         };
@@ -192,7 +216,7 @@ pub fn create_buffers_from_flat_items(
                 }
             },
             (None, channels) => {
-                let ctx = match crate::discord::get_ctx() {
+                let ctx = &match crate::discord::get_ctx() {
                     Some(ctx) => ctx,
                     _ => return,
                 };
@@ -210,7 +234,7 @@ pub fn create_buffers_from_flat_items(
 
                     match channel {
                         channel @ Channel::Private(_) => on_main(move |weecord| {
-                            let ctx = match crate::discord::get_ctx() {
+                            let ctx = &match crate::discord::get_ctx() {
                                 Some(ctx) => ctx,
                                 _ => return,
                             };
@@ -218,7 +242,7 @@ pub fn create_buffers_from_flat_items(
                         }),
 
                         channel @ Channel::Group(_) => on_main(move |weecord| {
-                            let ctx = match crate::discord::get_ctx() {
+                            let ctx = &match crate::discord::get_ctx() {
                                 Some(ctx) => ctx,
                                 _ => return,
                             };
@@ -244,18 +268,364 @@ fn user_online(cache: &Cache, user_id: UserId) -> bool {
 }
 
 pub fn create_guild_buffer(id: GuildId, name: &str) {
-    let guild_name_id = utils::buffer_id_for_guild(id);
-    let () = on_main_blocking(move |weecord| {
-        let buffer = weecord.buffer_manager.get_or_create_buffer(&guild_name_id);
-
-        buffer.set_localvar("guild_name", name);
-        buffer.set_localvar("server", name);
-        buffer.set_short_name(name);
-        buffer.set_localvar("guildid", &id.0.to_string());
-        buffer.set_localvar("type", "server");
+    let _: Option<()> = on_main_blocking(move |weecord| setup_guild_buffer(weecord, id, name));
+}
+
+fn setup_guild_buffer(weecord: &Discord, id: GuildId, name: &str) {
+    let buffer = weecord
+        .buffer_manager
+        .get_or_create_buffer(&utils::buffer_id_for_guild(id));
+
+    buffer.set_localvar("guild_name", name);
+    buffer.set_localvar("server", name);
+    buffer.set_short_name(name);
+    buffer.set_localvar("guildid", &id.0.to_string());
+    buffer.set_localvar("type", "server");
+}
+
+/// The nick of the current user in a guild
+pub fn guild_nick(ctx: &Context, guild_id: GuildId) -> String {
+    let current_user = ctx.cache.read().user.clone();
+    if let Ok(current_member) = guild_id.member(ctx, current_user.id) {
+        format!("@{}", current_member.display_name())
+    } else {
+        format!("@{}", current_user.name)
+    }
+}
+
+/// Whether a channel that became visible should get a buffer, in irc mode only channels of
+/// autojoined guilds and autojoined channels are opened
+fn should_open_channel(weecord: &Discord, guild_id: GuildId, channel_id: ChannelId) -> bool {
+    if !weecord.config.irc_mode.value() {
+        return true;
+    }
+
+    weecord
+        .config
+        .autojoin_channels()
+        .iter()
+        .any(|item| *item == guild_id || *item == channel_id)
+}
+
+/// Bring the buffer of a channel up to date after it was created or changed, opening it if it
+/// became visible and closing it if access was lost
+pub fn refresh_channel(ctx: &Context, channel: &GuildChannel) {
+    refresh_channels(ctx, channel.guild_id, vec![channel.clone()]);
+}
+
+/// Refresh every channel of a guild, used when roles change what the user can access
+pub fn refresh_guild_channels(ctx: &Context, guild_id: GuildId) {
+    let channels: Vec<_> = match guild_id.to_guild_cached(&ctx.cache) {
+        Some(guild) => guild
+            .read()
+            .channels
+            .values()
+            .map(|channel| channel.read().clone())
+            .collect(),
+        None => return,
+    };
+
+    refresh_channels(ctx, guild_id, channels);
+    reorder_channel_buffers(ctx, guild_id);
+}
+
+/// Refresh the buffers of some channels of a guild
+///
+/// Called from gateway events, so everything is read from the cache first and the buffers are
+/// updated in a single job on the main thread without waiting for it
+pub fn refresh_channels(ctx: &Context, guild_id: GuildId, channels: Vec<GuildChannel>) {
+    let guild_name = match guild_id.to_guild_cached(&ctx.cache) {
+        Some(guild) => guild.read().name.clone(),
+        None => return,
+    };
+    let nick = guild_nick(ctx, guild_id);
+    let channels: Vec<_> = channels
+        .into_iter()
+        .filter(|channel| match channel.kind {
+            ChannelType::Text | ChannelType::News => true,
+            _ => false,
+        })
+        .map(|channel| {
+            let visible = utils::current_user_permissions(&ctx.cache, &channel)
+                .map(|permissions| permissions.read_messages())
+                .unwrap_or(true);
+            let info = ChannelBufferInfo::new(&ctx.cache, &guild_name, &channel, &nick);
+            (channel, visible, info)
+        })
+        .collect();
+    if channels.is_empty() {
+        return;
+    }
+
+    on_main(move |weecord| {
+        for (channel, visible, info) in channels {
+            let buffer_id = utils::buffer_id_for_channel(Some(guild_id), channel.id);
+            let buffer = weecord.buffer_manager.get_buffer(&buffer_id);
+            let exists = buffer.is_some();
+            let muted = buffer
+                .map(|buffer| utils::buffer_is_muted(&buffer))
+                .unwrap_or(false);
+
+            if exists && !visible {
+                close_channel_buffer_now(
+                    weecord,
+                    guild_id,
+                    channel.id,
+                    &format!("Lost access to #{}, closing its buffer", channel.name),
+                );
+                continue;
+            }
+            if !exists && !(visible && should_open_channel(weecord, guild_id, channel.id)) {
+                continue;
+            }
+
+            if let Some(info) = info {
+                if !exists {
+                    setup_guild_buffer(weecord, guild_id, &guild_name);
+                }
+                info.apply(weecord, muted);
+            }
+        }
+    });
+}
+
+/// Close the buffer of a channel, printing why to the guild buffer
+pub fn close_channel_buffer(guild_id: GuildId, channel_id: ChannelId, notice: String) {
+    on_main(move |weecord| close_channel_buffer_now(weecord, guild_id, channel_id, &notice));
+}
+
+fn close_channel_buffer_now(
+    weecord: &Discord,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    notice: &str,
+) {
+    let buffer_id = utils::buffer_id_for_channel(Some(guild_id), channel_id);
+    if weecord.buffer_manager.get_buffer(&buffer_id).is_none() {
+        return;
+    }
+    if let Some(buffer) = weecord.buffer_search("weecord", &utils::buffer_id_for_guild(guild_id)) {
+        buffer.print(&format!("{}\t{}", weecord.get_prefix("network"), notice));
+    }
+    weecord.buffer_manager.close_buffer(&buffer_id);
+}
+
+/// Close the guild buffer and all channel buffers of a guild
+pub fn close_guild_buffers(guild_id: GuildId) {
+    if let Some(session) = account::session() {
+        session.channel_order.lock().remove(&guild_id);
+    }
+    on_main(move |weecord| {
+        for buffer in weecord.buffer_manager.buffers() {
+            if buffer.guild_id() == Some(guild_id) {
+                let name = buffer.get_name().into_owned();
+                weecord.buffer_manager.close_buffer(&name);
+            }
+        }
+    });
+}
+
+/// Update the guild name of all buffers of a guild
+pub fn rename_guild(guild_id: GuildId, name: String) {
+    on_main(move |weecord| {
+        for buffer in weecord.buffer_manager.buffers() {
+            if buffer.guild_id() != Some(guild_id) {
+                continue;
+            }
+            buffer.set_localvar("guild_name", &name);
+            buffer.set_localvar("server", &name);
+            if buffer.channel_id().is_none() {
+                buffer.set_short_name(&name);
+            }
+        }
+    });
+}
+
+/// Move the open channel buffers of a guild so they match the current channel order
+///
+/// Buffers are moved relative to their current position, which relies on the buffers of a guild
+/// being next to each other, as they are when created
+pub fn reorder_channel_buffers(ctx: &Context, guild_id: GuildId) {
+    let channels: HashMap<_, _> = match guild_id.to_guild_cached(&ctx.cache) {
+        Some(guild) => guild
+            .read()
+            .channels
+            .iter()
+            .map(|(id, channel)| (*id, channel.read().clone()))
+            .collect(),
+        None => return,
+    };
+    let new_order: Vec<_> = utils::sort_guild_channels(&channels)
+        .iter()
+        .map(|channel| channel.id)
+        .collect();
+    let session = match account::session() {
+        Some(session) => session,
+        None => return,
+    };
+    let old_order = match session
+        .channel_order
+        .lock()
+        .insert(guild_id, new_order.clone())
+    {
+        Some(old_order) => old_order,
+        None => return,
+    };
+    if old_order == new_order {
+        return;
+    }
+
+    on_main(move |weecord| {
+        let is_open = |channel_id: &ChannelId| {
+            weecord
+                .buffer_manager
+                .get_buffer(&utils::buffer_id_for_channel(Some(guild_id), *channel_id))
+                .is_some()
+        };
+        let mut current: Vec<_> = old_order.into_iter().filter(|id| is_open(id)).collect();
+        // Channels that are new to the order were opened at the end
+        for channel_id in &new_order {
+            if !current.contains(channel_id) && is_open(channel_id) {
+                current.push(*channel_id);
+            }
+        }
+        let target: Vec<_> = new_order.into_iter().filter(|id| is_open(id)).collect();
+
+        for (index, channel_id) in target.iter().enumerate() {
+            let current_index = match current.iter().position(|id| id == channel_id) {
+                Some(current_index) => current_index,
+                None => continue,
+            };
+            if current_index == index {
+                continue;
+            }
+
+            if let Some(buffer) = weecord
+                .buffer_manager
+                .get_buffer(&utils::buffer_id_for_channel(Some(guild_id), *channel_id))
+            {
+                let _ = buffer.run_command(&format!("/buffer move -{}", current_index - index));
+            }
+            let channel_id = current.remove(current_index);
+            current.insert(index, channel_id);
+        }
+    });
+}
+
+/// Mark the guild buffer and channel buffers of a guild with the guild folder it is in
+fn set_folder_localvars(ctx: &Context, guild_id: GuildId, channels: Vec<ChannelId>) {
+    let folder = folders::folder_for_guild(guild_id);
+    // Guilds that left their folder have the localvars emptied
+    let folder_name = folder
+        .as_ref()
+        .map(|folder| folder.display_name(&ctx.cache))
+        .unwrap_or_default();
+    let folder_color = folder
+        .and_then(|folder| folder.color)
+        .map(|color| utils::rgb_to_ansi(serenity::utils::Colour::new(color as u32)).to_string())
+        .unwrap_or_default();
+
+    on_main(move |weecord| {
+        let buffer_ids = std::iter::once(utils::buffer_id_for_guild(guild_id)).chain(
+            channels
+                .iter()
+                .map(|channel| utils::buffer_id_for_channel(Some(guild_id), *channel)),
+        );
+        for buffer_id in buffer_ids {
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_id) {
+                buffer.set_localvar("folder", &folder_name);
+                buffer.set_localvar("folder_color", &folder_color);
+            }
+        }
     });
 }
 
+/// Update the folder localvars of every guild after the guild folders changed
+pub fn refresh_folder_localvars(ctx: &Context) {
+    let guilds: Vec<_> = ctx
+        .cache
+        .read()
+        .guilds
+        .iter()
+        .map(|(id, guild)| (*id, guild.read().channels.keys().cloned().collect()))
+        .collect();
+    for (guild_id, channels) in guilds {
+        set_folder_localvars(ctx, guild_id, channels);
+    }
+}
+
+/// Print the channels of a guild grouped by category to the guild buffer, marking unread ones
+pub fn print_channel_tree(guild_id: GuildId) {
+    on_main(move |weecord| {
+        let buffer = match weecord.buffer_search("weecord", &utils::buffer_id_for_guild(guild_id)) {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        print_channel_tree_to(weecord, &buffer, guild_id);
+    });
+}
+
+pub fn print_channel_tree_to(weechat: &Weechat, buffer: &Buffer, guild_id: GuildId) {
+    let ctx = &match crate::discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+    let channels: HashMap<_, _> = match guild_id.to_guild_cached(&ctx.cache) {
+        Some(guild) => guild
+            .read()
+            .channels
+            .iter()
+            .map(|(id, channel)| (*id, channel.read().clone()))
+            .collect(),
+        None => return,
+    };
+
+    let visible = |channel: &GuildChannel| {
+        utils::current_user_permissions(&ctx.cache, channel)
+            .map(|permissions| permissions.read_messages())
+            .unwrap_or(true)
+    };
+    let channels: Vec<_> = utils::sort_guild_channels(&channels)
+        .into_iter()
+        .filter(|channel| channel.kind == ChannelType::Category || visible(channel))
+        .collect();
+
+    let prefix = weechat.get_prefix("network").into_owned();
+    buffer.print(&format!("{}\tChannels:", prefix));
+
+    let cache = ctx.cache.read();
+    for channel in channels {
+        let line = match channel.kind {
+            ChannelType::Category => format!(
+                "  {}",
+                utils::colorize_string(weechat, "bold", &channel.name.to_uppercase())
+            ),
+            ChannelType::Text | ChannelType::News => {
+                let unread = channel.last_message_id.is_some()
+                    && cache
+                        .read_state
+                        .get(&channel.id)
+                        .map(|rs| rs.last_message_id)
+                        != channel.last_message_id;
+                let marker = if unread {
+                    format!(" {}", utils::colorize_string(weechat, "yellow", "*"))
+                } else {
+                    String::new()
+                };
+                let indent = if channel.category_id.is_some() {
+                    "    "
+                } else {
+                    "  "
+                };
+                format!("{}#{}{}", indent, channel.name, marker)
+            },
+            // Voice channels are listed separately along with who is connected
+            _ => continue,
+        };
+        buffer.print(&format!("{}\t{}", prefix, line));
+    }
+}
+
 pub fn create_buffer_from_channel(
     cache: &CacheRwLock,
     guild_name: &str,
@@ -263,32 +633,68 @@ pub fn create_buffer_from_channel(
     nick: &str,
     muted: bool,
 ) {
-    let current_user = cache.read().user.clone();
-    if let Ok(perms) = channel.permissions_for_user(cache, current_user.id) {
-        if !perms.read_message_history() {
-            return;
-        }
+    if let Some(info) = ChannelBufferInfo::new(cache, guild_name, channel, nick) {
+        let _: Option<()> = on_main_blocking(|weecord| info.apply(weecord, muted));
     }
+}
 
-    let channel_type = match channel.kind {
-        // TODO: Should we display store channels somehow?
-        ChannelType::Category | ChannelType::Voice | ChannelType::Store | ChannelType::Stage => {
-            return
-        },
-        ChannelType::Private => "private",
-        ChannelType::Group | ChannelType::Text | ChannelType::News => "channel",
-        ChannelType::__Nonexhaustive => unreachable!(),
-    };
+/// What the buffer of a guild channel is set up from, read from the cache
+struct ChannelBufferInfo {
+    channel: GuildChannel,
+    guild_name: String,
+    nick: String,
+    category: Option<String>,
+    permissions: Option<Permissions>,
+    has_unread: bool,
+}
 
-    let name_id = utils::buffer_id_for_channel(Some(channel.guild_id), channel.id);
-    let has_unread = cache
-        .read()
-        .read_state
-        .get(&channel.id)
-        .map(|rs| rs.last_message_id)
-        != channel.last_message_id;
+impl ChannelBufferInfo {
+    /// Returns `None` for channels that do not get a buffer
+    fn new(
+        cache: &CacheRwLock,
+        guild_name: &str,
+        channel: &GuildChannel,
+        nick: &str,
+    ) -> Option<ChannelBufferInfo> {
+        let permissions = utils::current_user_permissions(cache, channel);
+        if let Some(permissions) = permissions {
+            if !permissions.read_messages() {
+                return None;
+            }
+        }
+
+        match channel.kind {
+            // TODO: Should we display store channels somehow?
+            ChannelType::Category
+            | ChannelType::Voice
+            | ChannelType::Store
+            | ChannelType::Stage => return None,
+            ChannelType::Private | ChannelType::Group | ChannelType::Text | ChannelType::News => {},
+            ChannelType::__Nonexhaustive => unreachable!(),
+        }
 
-    let () = on_main_blocking(|weecord| {
+        Some(ChannelBufferInfo {
+            channel: channel.clone(),
+            guild_name: guild_name.to_owned(),
+            nick: nick.to_owned(),
+            category: utils::category_name(cache, channel),
+            permissions,
+            has_unread: cache
+                .read()
+                .read_state
+                .get(&channel.id)
+                .map(|rs| rs.last_message_id)
+                != channel.last_message_id,
+        })
+    }
+
+    fn apply(&self, weecord: &Discord, muted: bool) {
+        let channel = &self.channel;
+        let channel_type = match channel.kind {
+            ChannelType::Private => "private",
+            _ => "channel",
+        };
+        let name_id = utils::buffer_id_for_channel(Some(channel.guild_id), channel.id);
         let buffer = weecord.buffer_manager.get_or_create_buffer(&name_id);
 
         buffer.set_short_name(&channel.name);
@@ -296,11 +702,24 @@ pub fn create_buffer_from_channel(
         buffer.set_localvar("channelid", &channel.id.0.to_string());
         buffer.set_localvar("guildid", &channel.guild_id.0.to_string());
         buffer.set_localvar("channel", &channel.name);
-        buffer.set_localvar("guild_name", guild_name);
-        buffer.set_localvar("server", guild_name);
+        buffer.set_localvar("guild_name", &self.guild_name);
+        buffer.set_localvar("server", &self.guild_name);
         buffer.set_localvar("type", channel_type);
-        buffer.set_localvar("nick", &nick);
-        if has_unread && !muted {
+        buffer.set_localvar("nick", &self.nick);
+        if let Some(category) = &self.category {
+            buffer.set_localvar("category", category);
+        }
+        let mut read_only = false;
+        if let Some(permissions) = self.permissions {
+            buffer.set_localvar("permissions", &permissions.bits().to_string());
+            read_only = !permissions.send_messages();
+            // Messages can still be received live, but fetching older ones would fail
+            if !permissions.read_message_history() {
+                buffer.set_history_loaded();
+            }
+        }
+        buffer.set_localvar("read_only", &(read_only as u8).to_string());
+        if self.has_unread && !muted {
             buffer.set_hotlist(HotlistPriority::Message);
         }
 
@@ -314,12 +733,22 @@ pub fn create_buffer_from_channel(
             channel.name.clone()
         };
 
+        // Announcements can be followed from other guilds and published with crosspost
+        let announcement = channel.kind == ChannelType::News;
+        buffer.set_localvar("announcement", &(announcement as u8).to_string());
+        if announcement {
+            title += " (announcements, followable)";
+        }
         if muted {
             title += " (muted)";
         }
-        buffer.set_title(&title);
+        if read_only {
+            title += " (read-only)";
+        }
+        buffer.set_localvar("base_title", &title);
+        apply_pin_count(&buffer);
         buffer.set_localvar("muted", &(muted as u8).to_string());
-    });
+    }
 }
 
 // TODO: Reduce code duplication
@@ -358,7 +787,8 @@ pub fn create_buffer_from_dm(
         buffer.switch_to();
     }
     let title = format!("DM with {}", channel.recipient.read().name);
-    buffer.set_title(&title);
+    buffer.set_localvar("base_title", &title);
+    apply_pin_count(&buffer);
 
     load_dm_nicks(&buffer, &*channel);
 }
@@ -392,7 +822,8 @@ pub fn create_buffer_from_group(
     buffer.set_short_name(&channel.name());
     buffer.set_localvar("channelid", &channel.channel_id.0.to_string());
     buffer.set_localvar("nick", &nick);
-    buffer.set_title(&title);
+    buffer.set_localvar("base_title", &title);
+    apply_pin_count(&buffer);
 
     let has_unread = cache
         .read()
@@ -406,8 +837,38 @@ pub fn create_buffer_from_group(
     }
 }
 
+pub fn buffer_id_for_pins(channel_id: ChannelId) -> String {
+    account::namespaced(format!("Pins.{}", channel_id.0))
+}
+
+/// Open a buffer listing entries instead of messages, such as forum posts, DMs or friends
+///
+/// List buffers have no history to load, and without a channelid their input is not sent as a
+/// message but handed to the module owning the buffer `type` (see `hook::buffer_input`). Modules
+/// keep the entries they drew with `MessageManager::set_list`, so input can refer to an entry
+/// by its number.
+pub fn create_list_buffer(
+    weecord: &Discord,
+    name: &str,
+    kind: &str,
+    short_name: &str,
+    title: &str,
+    localvars: &[(&str, &str)],
+) -> Arc<MessageManager> {
+    let buffer = weecord.buffer_manager.get_or_create_buffer(name);
+    buffer.set_short_name(short_name);
+    buffer.set_title(title);
+    for (name, value) in localvars {
+        buffer.set_localvar(name, value);
+    }
+    buffer.set_localvar("type", kind);
+    buffer.set_history_loaded();
+    buffer.switch_to();
+    buffer
+}
+
 pub fn create_pins_buffer(weecord: &Discord, channel: &Channel) {
-    let buffer_name = format!("Pins.{}", channel.id().0);
+    let buffer_name = buffer_id_for_pins(channel.id());
 
     let buffer = weecord.buffer_manager.get_or_create_buffer(&buffer_name);
     buffer.switch_to();
@@ -415,6 +876,7 @@ pub fn create_pins_buffer(weecord: &Discord, channel: &Channel) {
     buffer.set_title(&format!("Pinned messages in #{}", channel.name()));
     buffer.set_full_name(&format!("Pinned messages in ${}", channel.name()));
     buffer.set_short_name(&format!("#{} pins", channel.name()));
+    buffer.set_localvar("type", "pins");
     utils::set_pins_for_channel(&buffer, channel.id());
 }
 
@@ -428,8 +890,8 @@ pub fn load_pin_buffer_history(buffer: &MessageManager) {
     buffer.clear();
     let buffer_name = buffer.get_name().to_string();
 
-    std::thread::spawn(move || {
-        let ctx = match crate::discord::get_ctx() {
+    account::spawn(move || {
+        let ctx = &match crate::discord::get_ctx() {
             Some(ctx) => ctx,
             _ => return,
         };
@@ -438,9 +900,13 @@ pub fn load_pin_buffer_history(buffer: &MessageManager) {
             Ok(pins) => pins,
             Err(_) => return,
         };
+        let channel_name = channel
+            .to_channel_cached(&ctx.cache)
+            .map(|channel| channel.name())
+            .unwrap_or_default();
 
         on_main(move |weecord| {
-            let ctx = match crate::discord::get_ctx() {
+            let ctx = &match crate::discord::get_ctx() {
                 Some(ctx) => ctx,
                 _ => return,
             };
@@ -449,21 +915,359 @@ pub fn load_pin_buffer_history(buffer: &MessageManager) {
                 None => return,
             };
 
-            for pin in pins.iter().rev() {
+            // Pins are numbered like `[n]` arguments, counting back from the newest
+            for (i, pin) in pins.iter().rev().enumerate() {
                 buf.add_message(&ctx.cache, pin, false);
+                let reference = format!(
+                    "[{}] pinned in #{}, message {} (\"jump {}\" to open the channel)",
+                    pins.len() - i,
+                    channel_name,
+                    pin.id.0,
+                    pins.len() - i
+                );
+                buf.print(&format!(
+                    "\t{}",
+                    utils::colorize_string(weecord, "8", &reference)
+                ));
+            }
+            if pins.is_empty() {
+                buf.print("There are no pinned messages");
             }
+            buf.print("");
+            buf.print("Enter \"unpin <n>\", \"pin <message id>\" or \"jump <n>\"");
+            set_pin_count(weecord, channel, pins.len());
         });
     });
 }
 
 pub fn load_pin_buffer_history_for_id(id: ChannelId) {
     on_main(move |weecord| {
-        if let Some(buffer) = weecord.buffer_manager.get_buffer(&format!("Pins.{}", id)) {
+        if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_id_for_pins(id)) {
             load_pin_buffer_history(&buffer)
         };
     })
 }
 
+/// Handle input in a pins buffer, which pins and unpins messages or opens the channel
+pub fn pins_buffer_input(weecord: &Discord, buffer: &Buffer, text: &str) {
+    let buffer = &match weecord.buffer_manager.get_buffer(&*buffer.get_name()) {
+        Some(buffer) => buffer,
+        None => return,
+    };
+    let mut words = text.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("pin"), arg) => set_pinned(buffer, arg, true),
+        (Some("unpin"), arg) => set_pinned(buffer, arg, false),
+        (Some("jump"), arg) => jump_to_pin(weecord, buffer, arg),
+        _ => buffer.print(&format!(
+            "{}\tEnter \"unpin <n>\", \"pin <message id>\" or \"jump <n>\"",
+            weecord.get_prefix("error")
+        )),
+    }
+}
+
+/// Pin or unpin a message by `[n|id]`, in a channel buffer or in its pins buffer
+pub fn set_pinned(buffer: &MessageManager, arg: Option<&str>, pinned: bool) {
+    let channel = match buffer
+        .channel_id()
+        .or_else(|| utils::pins_for_channel(buffer))
+    {
+        Some(channel) => channel,
+        None => return,
+    };
+    if buffer.channel_id().is_some() && !buffer.has_permission(Permissions::MANAGE_MESSAGES) {
+        crate::plugin_print("You do not have permission to pin messages in this channel");
+        return;
+    }
+    let message_id = match utils::message_id_for_arg(buffer, arg) {
+        Some(message_id) => message_id,
+        None => {
+            crate::plugin_print("Could not find that message");
+            return;
+        },
+    };
+
+    // The pins buffer and pin count are updated by the pins update event
+    account::spawn(move || {
+        let ctx = &match crate::discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let result = if pinned {
+            channel.pin(ctx, message_id)
+        } else {
+            channel.unpin(ctx, message_id)
+        };
+        match result {
+            Ok(()) if pinned => crate::plugin_print("Message pinned"),
+            Ok(()) => crate::plugin_print("Message unpinned"),
+            Err(e) => crate::plugin_print(&format!("Unable to change pin: {}", e)),
+        }
+    });
+}
+
+/// Number of messages loaded around a pin that is older than the loaded history of its channel
+const PIN_CONTEXT_SIZE: u64 = 50;
+
+/// Switch to the channel of a pin and scroll to the pinned message
+fn jump_to_pin(weecord: &Discord, pins_buffer: &MessageManager, arg: Option<&str>) {
+    let channel = match utils::pins_for_channel(pins_buffer) {
+        Some(channel) => channel,
+        None => return,
+    };
+    let pin = match arg.and_then(|n| n.parse().ok()) {
+        Some(n) => pins_buffer.nth_last_message(n),
+        None => None,
+    };
+    let pin = match pin {
+        Some(pin) => pin,
+        None => {
+            pins_buffer.print(&format!(
+                "{}\tNo pin {:?}, enter the number of a pin",
+                weecord.get_prefix("error"),
+                arg.unwrap_or_default()
+            ));
+            return;
+        },
+    };
+
+    let buffer = match channel_buffer(weecord, channel) {
+        Some(buffer) => buffer,
+        None => {
+            crate::plugin_print("The channel of this pin is not open");
+            return;
+        },
+    };
+    let buffer_name = buffer.get_name().into_owned();
+    let pin_id = pin.id;
+
+    // Load the channel before switching to it, so the switch does not reload it after the pin
+    // was added
+    let (tx, rx) = crossbeam_channel::unbounded();
+    if buffer.history_loaded() {
+        let _ = tx.send(());
+    } else {
+        load_history(&buffer, tx, weecord.config.message_fetch_count.value());
+    }
+    buffer.switch_to();
+
+    account::spawn(move || {
+        if rx.recv().is_err() {
+            return;
+        }
+        let loaded = on_main_blocking({
+            let buffer_name = buffer_name.clone();
+            move |weecord| {
+                weecord
+                    .buffer_manager
+                    .get_buffer(&buffer_name)
+                    .map(|buffer| buffer.has_message(pin_id))
+            }
+        })
+        .flatten();
+        // Older pins are loaded along with the messages around them
+        let context = match loaded {
+            Some(true) => Vec::new(),
+            Some(false) => {
+                let ctx = &match crate::discord::get_ctx() {
+                    Some(ctx) => ctx,
+                    _ => return,
+                };
+                channel
+                    .messages(ctx, |retriever| {
+                        retriever.around(pin_id).limit(PIN_CONTEXT_SIZE)
+                    })
+                    .unwrap_or_default()
+            },
+            None => return,
+        };
+
+        on_main(move |weecord| {
+            let ctx = &match crate::discord::get_ctx() {
+                Some(ctx) => ctx,
+                _ => return,
+            };
+            let buffer = match weecord.buffer_manager.get_buffer(&buffer_name) {
+                Some(buffer) => buffer,
+                None => return,
+            };
+            let unknown_users: HashSet<_> = buffer
+                .merge_messages(&ctx.cache, context, false)
+                .into_iter()
+                .collect();
+            if let Some(guild) = buffer.guild_id() {
+                request_unknown_members(ctx, guild, channel, &unknown_users);
+            }
+            if !buffer.scroll_to_message(pin_id) {
+                buffer.print(&format!(
+                    "{}\tUnable to load the pinned message {}",
+                    weecord.get_prefix("error"),
+                    pin_id.0
+                ));
+            }
+        });
+    });
+}
+
+/// The buffer of a channel of the current account, if it is open
+pub fn channel_buffer(weecord: &Discord, channel: ChannelId) -> Option<Arc<MessageManager>> {
+    weecord
+        .buffer_manager
+        .buffers()
+        .into_iter()
+        .find(|buffer| buffer.channel_id() == Some(channel))
+}
+
+/// Fetch the number of pinned messages of a channel for the title of its buffer
+pub fn refresh_pin_count(channel: ChannelId) {
+    account::spawn(move || {
+        let ctx = &match crate::discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        if let Ok(pins) = channel.pins(ctx) {
+            on_main(move |weecord| set_pin_count(weecord, channel, pins.len()));
+        }
+    });
+}
+
+fn set_pin_count(weecord: &Discord, channel: ChannelId, count: usize) {
+    if let Some(buffer) = channel_buffer(weecord, channel) {
+        buffer.set_localvar("pin_count", &count.to_string());
+        apply_pin_count(&buffer);
+    }
+}
+
+/// Set the title of a channel buffer, showing how many messages are pinned
+fn apply_pin_count(buffer: &Buffer) {
+    let title = buffer
+        .get_localvar("base_title")
+        .map(|title| title.into_owned())
+        .unwrap_or_default();
+    match buffer
+        .get_localvar("pin_count")
+        .and_then(|count| count.parse::<usize>().ok())
+    {
+        Some(count) if count > 0 => buffer.set_title(&format!("{} [{} pinned]", title, count)),
+        _ => buffer.set_title(&title),
+    }
+}
+
+/// Most pages of missed messages fetched per buffer after reconnecting
+const MAX_BACKFILL_PAGES: usize = 10;
+const BACKFILL_PAGE_SIZE: u64 = 100;
+
+/// Fetch the messages that were sent while disconnected into every buffer that loaded its
+/// history, used after a session could not be resumed
+pub fn backfill_loaded_buffers() {
+    let buffers: Vec<_> = match on_main_blocking(|weecord| {
+        let fetch_count = weecord.config.message_fetch_count.value();
+        let mut buffers = Vec::new();
+        for buffer in weecord.buffer_manager.buffers() {
+            let channel = match buffer.channel_id() {
+                Some(channel) if buffer.history_loaded() => channel,
+                _ => continue,
+            };
+            match buffer.last_message_id() {
+                Some(last_message) => buffers.push((
+                    buffer.get_name().into_owned(),
+                    buffer.guild_id(),
+                    channel,
+                    last_message,
+                )),
+                // Nothing to continue from, load the history like a new buffer
+                None => load_history(&buffer, crossbeam_channel::unbounded().0, fetch_count),
+            }
+        }
+        buffers
+    }) {
+        Some(buffers) => buffers,
+        None => return,
+    };
+
+    let ctx = &match crate::discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+
+    for (buffer_name, guild, channel, last_message) in buffers {
+        let mut missed = Vec::new();
+        let mut after = last_message;
+        let mut complete = false;
+        for _ in 0..MAX_BACKFILL_PAGES {
+            let page = match channel.messages(ctx, |retriever| {
+                retriever.after(after).limit(BACKFILL_PAGE_SIZE)
+            }) {
+                Ok(page) => page,
+                // Whatever was not fetched is reported as missed
+                Err(_) => break,
+            };
+            if let Some(newest) = page.iter().map(|msg| msg.id).max() {
+                after = newest;
+            }
+            let last_page = (page.len() as u64) < BACKFILL_PAGE_SIZE;
+            missed.extend(page);
+            if last_page {
+                complete = true;
+                break;
+            }
+        }
+
+        if missed.is_empty() && complete {
+            continue;
+        }
+
+        on_main(move |weecord| {
+            let ctx = &match crate::discord::get_ctx() {
+                Some(ctx) => ctx,
+                _ => return,
+            };
+            let buffer = match weecord.buffer_manager.get_buffer(&buffer_name) {
+                Some(buffer) => buffer,
+                None => return,
+            };
+
+            let unknown_users: HashSet<_> = buffer
+                .merge_messages(&ctx.cache, missed, true)
+                .into_iter()
+                .collect();
+            if !complete {
+                buffer.print(&format!(
+                    "{}\tMore messages were missed than could be fetched, use /discord rehistory \
+                     to reload the buffer",
+                    weecord.get_prefix("network")
+                ));
+            }
+            if let Some(guild) = guild {
+                request_unknown_members(ctx, guild, channel, &unknown_users);
+            }
+        });
+    }
+}
+
+/// Request members that were mentioned or sent messages but are not cached, the nonce is used to
+/// redraw the channel buffer once they arrive
+fn request_unknown_members(
+    ctx: &Context,
+    guild: GuildId,
+    channel: ChannelId,
+    unknown_users: &HashSet<UserId>,
+) {
+    if unknown_users.is_empty() {
+        return;
+    }
+    let msg = json::object! {
+        "op" =>  OpCode::GetGuildMembers.num(),
+        "d" => json::object! {
+            "guild_id" => guild.0.to_string(),
+            "user_ids" => (unknown_users.iter().map(|id| id.to_string())).collect::<Vec<_>>(),
+            "nonce" => channel.0.to_string(),
+        }
+    };
+    ctx.shard
+        .websocket_message(gateway::Message::Text(msg.to_string()));
+}
+
 pub fn load_history(
     buffer: &MessageManager,
     completion_sender: crossbeam_channel::Sender<()>,
@@ -481,15 +1285,15 @@ pub fn load_history(
 
     let buffer_name = buffer.get_name().to_string();
 
-    std::thread::spawn(move || {
-        let ctx = match crate::discord::get_ctx() {
+    account::spawn(move || {
+        let ctx = &match crate::discord::get_ctx() {
             Some(ctx) => ctx,
             _ => return,
         };
 
         if let Ok(msgs) = channel.messages(ctx, |retriever| retriever.limit(fetch_count as u64)) {
             on_main(move |weechat| {
-                let ctx = match crate::discord::get_ctx() {
+                let ctx = &match crate::discord::get_ctx() {
                     Some(ctx) => ctx,
                     _ => return,
                 };
@@ -528,16 +1332,7 @@ pub fn load_history(
                     }
                 }
                 if let Some(guild) = guild {
-                    let msg = json::object! {
-                        "op" =>  OpCode::GetGuildMembers.num(),
-                        "d" => json::object! {
-                            "guild_id" => guild.0.to_string(),
-                            "user_ids" => (unknown_users.iter().map(|id| id.to_string())).collect::<Vec<_>>(),
-                            "nonce" => channel.0.to_string(),
-                        }
-                    };
-                    ctx.shard
-                        .websocket_message(gateway::Message::Text(msg.to_string()));
+                    request_unknown_members(ctx, guild, channel, &unknown_users);
                 }
                 let _ = completion_sender.send(());
             });
@@ -547,7 +1342,10 @@ pub fn load_history(
 
 pub fn load_dm_nicks(buffer: &MessageManager, channel: &PrivateChannel) {
     let weechat = buffer.get_weechat();
-    let use_presence = crate::upgrade_plugin(&weechat).config.use_presence.value();
+    let use_presence = match crate::upgrade_plugin(&weechat) {
+        Some(weecord) => weecord.config.use_presence.value(),
+        None => return,
+    };
 
     // If the user doesn't want the presence, there's no reason to open
     // the nicklist
@@ -555,7 +1353,7 @@ pub fn load_dm_nicks(buffer: &MessageManager, channel: &PrivateChannel) {
         buffer.set_nicks_loaded();
         buffer.enable_nicklist();
 
-        let ctx = match crate::discord::get_ctx() {
+        let ctx = &match crate::discord::get_ctx() {
             Some(ctx) => ctx,
             _ => return,
         };
@@ -563,15 +1361,7 @@ pub fn load_dm_nicks(buffer: &MessageManager, channel: &PrivateChannel) {
         let recip = channel.recipient.read();
         let cache = ctx.cache.read();
 
-        buffer.add_nick(
-            NickArgs {
-                name: &recip.name,
-                color: &utils::nick_color(&weechat, &recip.name),
-                prefix: &utils::get_user_status_prefix(&weechat, &cache, recip.id),
-                ..Default::default()
-            },
-            None,
-        );
+        add_dm_recipient_nick(&weechat, buffer, &cache, &recip);
 
         // TODO: Detect current user status properly
         buffer.add_nick(
@@ -580,7 +1370,7 @@ pub fn load_dm_nicks(buffer: &MessageManager, channel: &PrivateChannel) {
                 color: &utils::nick_color(&weechat, &cache.user.name),
                 prefix: &utils::format_user_status_prefix(
                     &weechat,
-                    Some(*crate::command::LAST_STATUS.lock()),
+                    Some(crate::discord::presence::status()),
                 ),
                 ..Default::default()
             },
@@ -589,10 +1379,23 @@ pub fn load_dm_nicks(buffer: &MessageManager, channel: &PrivateChannel) {
     }
 }
 
+fn add_dm_recipient_nick(weechat: &Weechat, buffer: &Buffer, cache: &Cache, recipient: &User) {
+    buffer.add_nick(
+        NickArgs {
+            name: &recipient.name,
+            color: &utils::nick_color(&weechat, &recipient.name),
+            prefix: &utils::get_user_status_prefix(&weechat, &cache, recipient.id),
+            ..Default::default()
+        },
+        None,
+    );
+}
+
 // TODO: Make this nicer somehow
 // TODO: Refactor this to use `?`
 pub fn load_nicks(buffer: &Buffer) {
-    if buffer.nicks_loaded() {
+    // Nicklists driven by the member list are filled from its updates instead
+    if buffer.nicks_loaded() || member_list::is_member_list_managed(buffer) {
         return;
     }
 
@@ -613,8 +1416,8 @@ pub fn load_nicks(buffer: &Buffer) {
 
     let sealed_buffer = buffer.seal();
 
-    std::thread::spawn(move || {
-        let ctx = match crate::discord::get_ctx() {
+    account::spawn(move || {
+        let ctx = &match crate::discord::get_ctx() {
             Some(ctx) => ctx,
             _ => return,
         };
@@ -627,8 +1430,8 @@ pub fn load_nicks(buffer: &Buffer) {
 
         drop(guild);
 
-        let () = on_main_blocking(move |weechat| {
-            let ctx = match crate::discord::get_ctx() {
+        let _: Option<()> = on_main_blocking(move |weechat| {
+            let ctx = &match crate::discord::get_ctx() {
                 Some(ctx) => ctx,
                 _ => return,
             };
@@ -636,6 +1439,10 @@ pub fn load_nicks(buffer: &Buffer) {
             let use_presence = weechat.config.use_presence.value();
 
             let buffer = sealed_buffer.unseal(&weechat);
+            // A member list update may have arrived while the members were being read
+            if member_list::is_member_list_managed(&buffer) {
+                return;
+            }
             let guild = guild_id.to_guild_cached(ctx).expect("No guild cache item");
 
             let has_crown = guild_has_crown(&guild.read());
@@ -680,48 +1487,23 @@ fn add_member_to_nicklist(
         return;
     }
 
-    let role_name;
-    let role_color;
-    // TODO: Change offline/online color somehow?
-    if user.bot {
-        role_name = BOT_GROUP_NAME.to_owned();
-        role_color = "gray".to_string();
-    } else if !online && use_presence {
-        role_name = OFFLINE_GROUP_NAME.to_owned();
-        role_color = "grey".to_string();
-    } else if let Some((highest_hoisted, highest)) = utils::find_highest_roles(&ctx.cache, &member)
-    {
-        role_name = format!(
-            "{}|{}",
-            99999 - highest_hoisted.position,
-            highest_hoisted.name
-        );
-        role_color = crate::utils::rgb_to_ansi(highest.colour).to_string();
+    let (hoisted_role, colored_role) = utils::find_highest_roles(&ctx.cache, &member);
+
+    // Mirror the sidebar of the official client: hoisted roles in position order, followed by
+    // online and offline members
+    let group = if use_presence && !online {
+        Some((OFFLINE_GROUP_NAME.to_owned(), "grey".to_owned()))
+    } else if let Some(role) = hoisted_role {
+        Some((role_group_name(&role), role_color(&role)))
+    } else if use_presence {
+        Some((ONLINE_GROUP_NAME.to_owned(), "grey".to_owned()))
     } else {
-        // Can't find a role, add user to generic bucket
-        if use_presence {
-            if online {
-                role_name = ONLINE_GROUP_NAME.to_owned();
-            } else {
-                role_name = OFFLINE_GROUP_NAME.to_owned();
-            }
-            role_color = "grey".to_string();
-        } else {
-            buffer.add_nick(
-                weechat::NickArgs {
-                    name: member.display_name().as_ref(),
-                    color: &utils::nick_color(&weechat, member.display_name().as_ref()),
-                    ..Default::default()
-                },
-                None,
-            );
-            return;
-        }
-    }
-    let group = match buffer.search_nicklist_group(&role_name) {
-        Some(group) => group,
-        None => buffer.add_group(&role_name, &role_color, true, None),
+        None
     };
+    let group = group.map(|(name, color)| match buffer.search_nicklist_group(&name) {
+        Some(group) => group,
+        None => buffer.add_group(&name, &color, true, None),
+    });
 
     // TODO: Only show crown if there are no roles
     let nicklist_name = if guild_has_crown && guild.read().owner_id == user.id {
@@ -730,18 +1512,199 @@ fn add_member_to_nicklist(
         member.display_name().into_owned()
     };
 
+    let nick_color = match colored_role {
+        Some(role) => role_color(&role),
+        None => utils::nick_color(&weechat, &nicklist_name),
+    };
+
     buffer.add_nick(
         weechat::NickArgs {
             name: nicklist_name.as_ref(),
-            color: &utils::nick_color(&weechat, &nicklist_name),
+            color: &nick_color,
             ..Default::default()
         },
-        Some(&group),
+        group.as_ref(),
     );
 }
 
+/// Group names are prefixed with a number so weechat sorts higher roles first
+pub fn role_group_name(role: &Role) -> String {
+    format!("{:05}|{}", 99997 - role.position.min(99997), role.name)
+}
+
+pub fn role_color(role: &Role) -> String {
+    if role.colour.0 == 0 {
+        "default".to_owned()
+    } else {
+        utils::rgb_to_ansi(role.colour).to_string()
+    }
+}
+
+fn remove_member_from_nicklist(weechat: &Weechat, buffer: &Buffer, member: &Member) {
+    let display_name = member.display_name().into_owned();
+    // The guild owner may have been added with a crown
+    let crowned_name = format!("{} {}♛", display_name, weechat.color("214"));
+
+    for name in &[display_name, crowned_name] {
+        if let Some(nick) = buffer.search_nick(name, None) {
+            nick.remove();
+        }
+    }
+}
+
+/// Move a user to the correct nicklist group in every loaded buffer after their presence changes
+pub fn update_presence(guild_id: Option<GuildId>, user_id: UserId) {
+    on_main(move |weecord| {
+        let ctx = &match crate::discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+
+        // The status bar item is shown regardless of the nicklist setting
+        weecord.update_bar_item("discord_dm_status");
+
+        if !weecord.config.use_presence.value() {
+            return;
+        }
+
+        let private_channel = ctx
+            .cache
+            .read()
+            .private_channels
+            .values()
+            .find(|channel| channel.read().recipient.read().id == user_id)
+            .cloned();
+        if let Some(channel) = private_channel {
+            let channel = channel.read();
+            let buffer_name = utils::buffer_id_for_channel(None, channel.id);
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                if buffer.nicks_loaded() {
+                    let recip = channel.recipient.read();
+                    if let Some(nick) = buffer.search_nick(&recip.name, None) {
+                        nick.remove();
+                    }
+                    add_dm_recipient_nick(weecord, &buffer, &ctx.cache.read(), &recip);
+                }
+            }
+        }
+
+        let member = guild_id.and_then(|guild_id| ctx.cache.read().member(guild_id, user_id));
+        if let Some(member) = member {
+            refresh_member(weecord, ctx, &member, None);
+        }
+    });
+}
+
+/// Re-add a member to the nicklist of every loaded buffer in their guild, so they are placed in
+/// the group matching their current roles and presence
+fn refresh_member(weecord: &Discord, ctx: &Context, member: &Member, old: Option<&Member>) {
+    let guild = match member.guild_id.to_guild_cached(ctx) {
+        Some(guild) => guild,
+        None => return,
+    };
+    let (channels, has_crown) = {
+        let guild = guild.read();
+        let channels: Vec<_> = guild.channels.keys().cloned().collect();
+        (channels, guild_has_crown(&guild))
+    };
+    let use_presence = weecord.config.use_presence.value();
+
+    for channel_id in channels {
+        let buffer_name = utils::buffer_id_for_channel(Some(member.guild_id), channel_id);
+        let buffer = match weecord.buffer_manager.get_buffer(&buffer_name) {
+            Some(buffer) => buffer,
+            None => continue,
+        };
+        if !buffer.nicks_loaded() || member_list::is_member_list_managed(&buffer) {
+            continue;
+        }
+
+        if let Some(old) = old {
+            remove_member_from_nicklist(weecord, &buffer, old);
+        }
+        remove_member_from_nicklist(weecord, &buffer, member);
+        add_member_to_nicklist(
+            weecord,
+            ctx,
+            &buffer,
+            channel_id,
+            &guild,
+            member,
+            use_presence,
+            has_crown,
+        );
+    }
+}
+
+/// Move the members holding a role to the right nicklist groups after the role changed
+///
+/// `old_role` is the role before the change, its group is removed if it was renamed, moved or
+/// deleted, as its members are placed in a different group now
+pub fn refresh_role_members(guild_id: GuildId, role_id: RoleId, old_role: Option<Role>) {
+    on_main(move |weecord| {
+        let ctx = &match crate::discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let guild = match guild_id.to_guild_cached(ctx) {
+            Some(guild) => guild,
+            None => return,
+        };
+        let (channels, members, new_group, has_crown) = {
+            let guild = guild.read();
+            let channels: Vec<_> = guild.channels.keys().cloned().collect();
+            let members: Vec<_> = guild
+                .members
+                .values()
+                .filter(|member| member.roles.contains(&role_id))
+                .cloned()
+                .collect();
+            let new_group = guild.roles.get(&role_id).map(role_group_name);
+            (channels, members, new_group, guild_has_crown(&guild))
+        };
+        let old_group = old_role
+            .map(|role| role_group_name(&role))
+            .filter(|old_group| Some(old_group) != new_group.as_ref());
+        if members.is_empty() && old_group.is_none() {
+            return;
+        }
+        let use_presence = weecord.config.use_presence.value();
+
+        for channel_id in channels {
+            let buffer_name = utils::buffer_id_for_channel(Some(guild_id), channel_id);
+            let buffer = match weecord.buffer_manager.get_buffer(&buffer_name) {
+                Some(buffer) => buffer,
+                None => continue,
+            };
+            if !buffer.nicks_loaded() || member_list::is_member_list_managed(&buffer) {
+                continue;
+            }
+
+            for member in &members {
+                remove_member_from_nicklist(weecord, &buffer, member);
+                add_member_to_nicklist(
+                    weecord,
+                    ctx,
+                    &buffer,
+                    channel_id,
+                    &guild,
+                    member,
+                    use_presence,
+                    has_crown,
+                );
+            }
+            if let Some(group) = old_group
+                .as_ref()
+                .and_then(|name| buffer.search_nicklist_group(name))
+            {
+                group.remove();
+            }
+        }
+    });
+}
+
 pub fn update_nick() {
-    let ctx = match crate::discord::get_ctx() {
+    let ctx = &match crate::discord::get_ctx() {
         Some(ctx) => ctx,
         _ => return,
     };
@@ -769,53 +1732,15 @@ pub fn update_nick() {
     }
 }
 
-pub fn update_member_nick(old: &Option<Member>, new: &Member) {
-    let old_nick = if let Some(old) = old.as_ref().map(Member::display_name) {
-        old
-    } else {
-        // TODO: Rebuild entire nicklist?
-        return;
-    };
-    let new_nick = new.display_name();
-    let new = new.clone();
-    let guild_id = new.guild_id;
-
-    if old_nick != new_nick {
-        let old_nick = old_nick.to_owned().to_string();
-        let ctx = match crate::discord::get_ctx() {
+/// Update a member after their nick or roles change
+pub fn update_member(old: Option<Member>, new: Member) {
+    on_main(move |weecord| {
+        let ctx = &match crate::discord::get_ctx() {
             Some(ctx) => ctx,
             _ => return,
         };
-
-        let channels = guild_id.channels(ctx).expect("Unable to fetch channels");
-
-        on_main(move |weechat| {
-            let ctx = match crate::discord::get_ctx() {
-                Some(ctx) => ctx,
-                _ => return,
-            };
-            for channel_id in channels.keys() {
-                let string_channel = utils::buffer_id_for_channel(Some(guild_id), *channel_id);
-                if let Some(buffer) = weechat.buffer_search("weecord", &string_channel) {
-                    if let Some(nick) = buffer.search_nick(&old_nick, None) {
-                        nick.remove();
-                        if let Some(guild) = guild_id.to_guild_cached(&ctx) {
-                            add_member_to_nicklist(
-                                weechat,
-                                &ctx,
-                                &buffer,
-                                *channel_id,
-                                &guild,
-                                &new,
-                                false,
-                                guild_has_crown(&guild.read()),
-                            );
-                        }
-                    }
-                }
-            }
-        })
-    }
+        refresh_member(weecord, ctx, &new, old.as_ref());
+    });
 }
 
 fn guild_has_crown(guild: &Guild) -> bool {
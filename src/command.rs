@@ -1,20 +1,23 @@
 use crate::{
-    buffers, discord, on_main_blocking, plugin_print, upgrade_plugin, utils,
+    bookmarks, buffers, discord,
+    discord::presence::{self, CustomStatus, Game},
+    on_main, on_main_blocking, plugin_print, upgrade_plugin, utils,
     utils::{BufferExt, ChannelExt, GuildOrChannel},
     weechat_utils::MessageManager,
     Discord,
 };
-use lazy_static::lazy_static;
-use parking_lot::Mutex;
-use serenity::model::{gateway::Activity, user::OnlineStatus};
-use std::{borrow::Cow, collections::VecDeque, sync::Arc};
+use serenity::{
+    model::{
+        gateway::ActivityType,
+        guild::Member,
+        permissions::Permissions,
+        user::{OnlineStatus, User},
+    },
+    prelude::Context,
+};
+use std::{borrow::Cow, collections::VecDeque};
 use weechat::{Buffer, CommandHook, ConfigOption, ReturnCode, Weechat};
 
-lazy_static! {
-    // Tracks the last set status for use in setting the current game presence
-    pub static ref LAST_STATUS: Arc<Mutex<OnlineStatus>> = Arc::new(Mutex::new(OnlineStatus::Online));
-}
-
 pub fn init(weechat: &Weechat) -> Vec<CommandHook<()>> {
     let mut hooks = Vec::new();
     hooks.push(weechat.hook_command(
@@ -68,8 +71,12 @@ impl<'a> Args<'a> {
 }
 
 fn run_command(buffer: &Buffer, cmd: &str) {
+    let _account = discord::account::enter(&discord::account::for_buffer(buffer));
     let weechat = buffer.get_weechat();
-    let weecord = upgrade_plugin(&weechat);
+    let weecord = &match upgrade_plugin(&weechat) {
+        Some(weecord) => weecord,
+        None => return,
+    };
 
     let args = Args::from_cmd(cmd);
 
@@ -80,13 +87,17 @@ fn run_command(buffer: &Buffer, cmd: &str) {
     }
 
     match args.base {
-        "connect" => weecord.connect(),
-        "disconnect" => disconnect(weecord),
+        "connect" => match args.args.get(0) {
+            Some(account) => weecord.connect(account),
+            None => weecord.connect(discord::account::DEFAULT_ACCOUNT),
+        },
+        "disconnect" => disconnect(weecord, &args),
         "irc-mode" => irc_mode(weecord),
         "discord-mode" => discord_mode(weecord),
         "token" => token(weecord, &args),
-        "autostart" => autostart(weecord),
-        "noautostart" => noautostart(weecord),
+        "account" => add_account(weecord, &args),
+        "autostart" => autostart(weecord, &args, true),
+        "noautostart" => autostart(weecord, &args, false),
         "query" => {
             crate::hook::handle_query(&args);
         },
@@ -99,10 +110,34 @@ fn run_command(buffer: &Buffer, cmd: &str) {
         "autojoin" => autojoin(weecord, &args, buffer),
         "noautojoin" => noautojoin(weecord, &args),
         "autojoined" => autojoined(weecord),
-        "status" => status(&args),
+        "status" => status(weecord, &args),
         "pins" | "pinned" => pins(weecord, buffer),
-        "game" => game(&args),
+        "pin" | "unpin" => {
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&*buffer.get_name()) {
+                buffers::set_pinned(&buffer, args.args.get(0).copied(), args.base == "pin");
+            }
+        },
+        "bookmark" => bookmark(weecord, &args, buffer),
+        "bookmarks" => bookmarks::open_bookmarks(weecord),
+        "game" => game(weecord, &args),
+        "customstatus" => custom_status(weecord, &args),
         "upload" => upload(&args, buffer),
+        "whois" => whois(&args, buffer),
+        "voice" => voice(weecord, &args, buffer),
+        "channels" => channels(weecord, &args, buffer),
+        "guilds" => guilds(weecord, buffer),
+        "forum" => forum(&args, buffer),
+        "events" => events(&args, buffer),
+        "crosspost" => crosspost(weecord, &args, buffer),
+        "dms" => dms(weecord, &args),
+        "friends" => friends(weecord, &args),
+        "ignore" => ignore(&args, buffer),
+        "unignore" => unignore(&args, buffer),
+        "ignored" => ignored(weecord),
+        "kick" | "ban" | "unban" | "timeout" => moderate(&args, buffer),
+        "purge" => purge(weecord, &args, buffer),
+        "auditlog" => audit_log(&args, buffer),
+        "group" => group(&args, buffer),
         "me" | "tableflip" | "unflip" | "shrug" | "spoiler" => {
             discord_fmt(args.base, args.rest, buffer)
         },
@@ -118,23 +153,24 @@ fn run_command(buffer: &Buffer, cmd: &str) {
     };
 }
 
-fn disconnect(_weechat: &Weechat) {
-    let mut discord = crate::discord::DISCORD.lock();
-    if discord.is_some() {
-        if let Some(discord) = discord.take() {
-            discord.shutdown();
-        };
-        plugin_print("Disconnected");
-    } else {
+fn disconnect(weecord: &Discord, args: &Args) {
+    // Shutting down reports the disconnect
+    let disconnected = match args.args.get(0) {
+        Some(account) => weecord.disconnect(account),
+        None => weecord
+            .connected_accounts()
+            .iter()
+            .fold(false, |any, account| weecord.disconnect(account) || any),
+    };
+    if !disconnected {
         plugin_print("Already disconnected");
     }
 }
 
-fn irc_mode(weechat: &Weechat) {
-    if crate::utils::get_irc_mode(weechat) {
+fn irc_mode(weecord: &Discord) {
+    if crate::utils::get_irc_mode(weecord) {
         plugin_print("irc-mode already enabled")
     } else {
-        let weecord = crate::upgrade_plugin(weechat);
         let before = weecord.config.irc_mode.value();
         let change = weecord.config.irc_mode.set(true);
         format_option_change("irc_mode", "true", Some(&before), change);
@@ -142,11 +178,10 @@ fn irc_mode(weechat: &Weechat) {
     }
 }
 
-fn discord_mode(weechat: &Weechat) {
-    if !crate::utils::get_irc_mode(weechat) {
+fn discord_mode(weecord: &Discord) {
+    if !crate::utils::get_irc_mode(weecord) {
         plugin_print("discord-mode already enabled")
     } else {
-        let weecord = crate::upgrade_plugin(weechat);
         let before = weecord.config.irc_mode.value();
         let change = weecord.config.irc_mode.set(false);
         format_option_change("irc_mode", "false", Some(&before), change);
@@ -154,26 +189,58 @@ fn discord_mode(weechat: &Weechat) {
     }
 }
 
-fn token(weechat: &Weechat, args: &Args) {
+fn token(weecord: &Discord, args: &Args) {
     if args.args.is_empty() {
         plugin_print("token requires an argument");
     } else {
-        let weecord = crate::upgrade_plugin(weechat);
         let new_value = args.rest.trim_matches('"');
-        weecord.config.token.set(new_value);
+        weecord
+            .config
+            .set_account_token(discord::account::DEFAULT_ACCOUNT, new_value);
 
         plugin_print("Set Discord token");
     }
 }
 
-fn autostart(weechat: &Weechat) {
-    crate::upgrade_plugin(weechat).config.autostart.set(true);
-    plugin_print("Discord will now load on startup");
+fn add_account(weecord: &Discord, args: &Args) {
+    let (name, token) = match (args.args.get(0), args.args.get(1)) {
+        (Some(name), Some(token)) => (*name, token.trim_matches('"')),
+        _ => {
+            plugin_print("account requires a name and a token");
+            return;
+        },
+    };
+    if name.contains('.') {
+        plugin_print("Account names can not contain a \".\"");
+        return;
+    }
+
+    if weecord.config.set_account_token(name, token) {
+        plugin_print(&format!("Set the token of account {}", name));
+    } else {
+        plugin_print("Unable to add the account");
+    }
 }
 
-fn noautostart(weechat: &Weechat) {
-    crate::upgrade_plugin(weechat).config.autostart.set(false);
-    plugin_print("Discord will not load on startup");
+fn autostart(weecord: &Discord, args: &Args, autostart: bool) {
+    let account = args
+        .args
+        .get(0)
+        .copied()
+        .unwrap_or(discord::account::DEFAULT_ACCOUNT);
+    if !weecord.config.set_account_autostart(account, autostart) {
+        plugin_print(&format!("Unknown account {}", account));
+    } else if account == discord::account::DEFAULT_ACCOUNT {
+        if autostart {
+            plugin_print("Discord will now load on startup");
+        } else {
+            plugin_print("Discord will not load on startup");
+        }
+    } else if autostart {
+        plugin_print(&format!("Account {} will now connect on startup", account));
+    } else {
+        plugin_print(&format!("Account {} will not connect on startup", account));
+    }
 }
 
 pub(crate) fn join(_weechat: &Weechat, args: &Args, verbose: bool) -> ReturnCode {
@@ -188,7 +255,7 @@ pub(crate) fn join(_weechat: &Weechat, args: &Args, verbose: bool) -> ReturnCode
         };
         let channel_name = args.next();
 
-        let ctx = match discord::get_ctx() {
+        let ctx = &match discord::get_ctx() {
             Some(ctx) => ctx,
             _ => return ReturnCode::Error,
         };
@@ -228,7 +295,7 @@ pub(crate) fn join(_weechat: &Weechat, args: &Args, verbose: bool) -> ReturnCode
 }
 
 fn resolve_channel_id(guild_name: &str, channel_name: Option<&str>) -> Option<String> {
-    let ctx = match discord::get_ctx() {
+    let ctx = &match discord::get_ctx() {
         Some(ctx) => ctx,
         _ => return None,
     };
@@ -269,7 +336,7 @@ fn remove_item(items: Cow<str>, old_item: String) -> String {
     return items.join(",");
 }
 
-fn watch(weechat: &Weechat, args: &Args) {
+fn watch(weecord: &Discord, args: &Args) {
     if args.args.is_empty() {
         plugin_print("watch requires a guild name and optional channel name");
         return;
@@ -285,10 +352,8 @@ fn watch(weechat: &Weechat, args: &Args) {
         Some(cid) => cid,
         None => return,
     };
-
-    let weecord = crate::upgrade_plugin(weechat);
     let new_watched = add_item(weecord.config.watched_channels.value(), new_channel_id);
-    let () = on_main_blocking(|weecord| {
+    let _: Option<()> = on_main_blocking(|weecord| {
         weecord.config.watched_channels.set(&new_watched);
     });
     if let Some(channel_name) = channel_name {
@@ -298,7 +363,7 @@ fn watch(weechat: &Weechat, args: &Args) {
     }
 }
 
-fn nowatch(weechat: &Weechat, args: &Args) {
+fn nowatch(weecord: &Discord, args: &Args) {
     if args.args.is_empty() {
         plugin_print("nowatch requires a guild name and optional channel name");
         return;
@@ -314,10 +379,8 @@ fn nowatch(weechat: &Weechat, args: &Args) {
         Some(cid) => cid,
         None => return,
     };
-
-    let weecord = crate::upgrade_plugin(weechat);
     let new_watched = remove_item(weecord.config.watched_channels.value(), new_channel_id);
-    let () = on_main_blocking(|weecord| {
+    let _: Option<()> = on_main_blocking(|weecord| {
         weecord.config.watched_channels.set(&new_watched);
     });
     if let Some(channel_name) = channel_name {
@@ -330,16 +393,16 @@ fn nowatch(weechat: &Weechat, args: &Args) {
     }
 }
 
-fn watched(weechat: &Weechat) {
+fn watched(weecord: &Discord) {
     let mut channels = Vec::new();
     let mut guilds = Vec::new();
 
-    let ctx = match discord::get_ctx() {
+    let ctx = &match discord::get_ctx() {
         Some(ctx) => ctx,
         _ => return,
     };
 
-    for watched_item in crate::upgrade_plugin(weechat).config.watched_channels() {
+    for watched_item in weecord.config.watched_channels() {
         match watched_item {
             utils::GuildOrChannel::Guild(guild) => guilds.push(guild),
             utils::GuildOrChannel::Channel(guild, channel) => channels.push((guild, channel)),
@@ -347,20 +410,20 @@ fn watched(weechat: &Weechat) {
     }
 
     if guilds.is_empty() && channels.is_empty() {
-        weechat.print("There are no watched guilds or channels");
+        weecord.print("There are no watched guilds or channels");
         return;
     }
 
-    weechat.print("");
+    weecord.print("");
 
-    weechat.print(&format!("Watched Servers: ({})", guilds.len()));
+    weecord.print(&format!("Watched Servers: ({})", guilds.len()));
     for guild in guilds {
         if let Some(guild) = guild.to_guild_cached(ctx) {
-            weechat.print(&format!("  {}", guild.read().name));
+            weecord.print(&format!("  {}", guild.read().name));
         }
     }
 
-    weechat.print(&format!("Watched Channels: ({})", channels.len()));
+    weecord.print(&format!("Watched Channels: ({})", channels.len()));
     for (guild, channel) in channels {
         if let Ok(channel) = channel.to_channel(ctx) {
             let channel_name = channel.name();
@@ -370,17 +433,17 @@ fn watched(weechat: &Weechat) {
                 } else {
                     guild.0.to_string()
                 };
-                weechat.print(&format!("  {}: {}", guild_name, channel_name));
+                weecord.print(&format!("  {}: {}", guild_name, channel_name));
             } else {
-                weechat.print(&format!("  {}", channel_name));
+                weecord.print(&format!("  {}", channel_name));
             }
         } else {
-            weechat.print(&format!("  {:?} {:?}", guild, channel));
+            weecord.print(&format!("  {:?} {:?}", guild, channel));
         }
     }
 }
 
-fn autojoin(weechat: &Weechat, args: &Args, buffer: &Buffer) {
+fn autojoin(weecord: &Discord, args: &Args, buffer: &Buffer) {
     if args.args.is_empty() {
         plugin_print("autojoin requires a guild name and optional channel name");
         return;
@@ -396,8 +459,6 @@ fn autojoin(weechat: &Weechat, args: &Args, buffer: &Buffer) {
         Some(cid) => cid,
         None => return,
     };
-
-    let weecord = crate::upgrade_plugin(weechat);
     let new_autojoined = add_item(weecord.config.autojoin_channels.value(), new_channel_id);
     weecord.config.autojoin_channels.set(&new_autojoined);
 
@@ -412,7 +473,7 @@ fn autojoin(weechat: &Weechat, args: &Args, buffer: &Buffer) {
     }
 }
 
-fn noautojoin(weechat: &Weechat, args: &Args) {
+fn noautojoin(weecord: &Discord, args: &Args) {
     if args.args.is_empty() {
         plugin_print("noautojoin requires a guild name and optional channel name");
         return;
@@ -428,8 +489,6 @@ fn noautojoin(weechat: &Weechat, args: &Args) {
         Some(cid) => cid,
         None => return,
     };
-
-    let weecord = crate::upgrade_plugin(weechat);
     let new_autojoined = remove_item(weecord.config.autojoin_channels.value(), channel_id);
     weecord.config.autojoin_channels.set(&new_autojoined);
 
@@ -446,16 +505,16 @@ fn noautojoin(weechat: &Weechat, args: &Args) {
     }
 }
 
-fn autojoined(weechat: &Weechat) {
+fn autojoined(weecord: &Discord) {
     let mut channels = Vec::new();
     let mut guilds = Vec::new();
 
-    let ctx = match discord::get_ctx() {
+    let ctx = &match discord::get_ctx() {
         Some(ctx) => ctx,
         _ => return,
     };
 
-    for autojoined_item in crate::upgrade_plugin(weechat).config.autojoin_channels() {
+    for autojoined_item in weecord.config.autojoin_channels() {
         match autojoined_item {
             utils::GuildOrChannel::Guild(guild) => guilds.push(guild),
             utils::GuildOrChannel::Channel(guild, channel) => channels.push((guild, channel)),
@@ -463,20 +522,20 @@ fn autojoined(weechat: &Weechat) {
     }
 
     if guilds.is_empty() && channels.is_empty() {
-        weechat.print("There are no guilds or channels set to autojoin");
+        weecord.print("There are no guilds or channels set to autojoin");
         return;
     }
 
-    weechat.print("");
+    weecord.print("");
 
-    weechat.print(&format!("Autojoin Servers: ({})", guilds.len()));
+    weecord.print(&format!("Autojoin Servers: ({})", guilds.len()));
     for guild in guilds {
         if let Some(guild) = guild.to_guild_cached(ctx) {
-            weechat.print(&format!("  {}", guild.read().name));
+            weecord.print(&format!("  {}", guild.read().name));
         }
     }
 
-    weechat.print(&format!("Autojoin Channels: ({})", channels.len()));
+    weecord.print(&format!("Autojoin Channels: ({})", channels.len()));
     for (guild, channel) in channels {
         if let Ok(channel) = channel.to_channel(ctx) {
             let channel_name = channel.name();
@@ -486,18 +545,18 @@ fn autojoined(weechat: &Weechat) {
                 } else {
                     guild.0.to_string()
                 };
-                weechat.print(&format!("  {}: {}", guild_name, channel_name));
+                weecord.print(&format!("  {}: {}", guild_name, channel_name));
             } else {
-                weechat.print(&format!("  {}", channel_name));
+                weecord.print(&format!("  {}", channel_name));
             }
         } else {
-            weechat.print(&format!("  {:?} {:?}", guild, channel));
+            weecord.print(&format!("  {:?} {:?}", guild, channel));
         }
     }
 }
 
-fn status(args: &Args) {
-    let ctx = match crate::discord::get_ctx() {
+fn status(weecord: &Discord, args: &Args) {
+    let ctx = &match crate::discord::get_ctx() {
         Some(ctx) => ctx,
         _ => return,
     };
@@ -507,18 +566,24 @@ fn status(args: &Args) {
         args.args.get(0).unwrap()
     };
 
-    let status = match status_str.to_lowercase().as_str() {
-        "online" => OnlineStatus::Online,
-        "offline" | "invisible" => OnlineStatus::Invisible,
-        "idle" => OnlineStatus::Idle,
-        "dnd" => OnlineStatus::DoNotDisturb,
-        _ => {
+    let status = match presence::parse_status(status_str) {
+        Some(status) => status,
+        None => {
             plugin_print(&format!("Unknown status \"{}\"", status_str));
             return;
         },
     };
-    ctx.set_presence(None, status);
-    *LAST_STATUS.lock() = status;
+    let account = discord::account::current();
+    presence::send_presence(
+        ctx,
+        status,
+        weecord.config.game(&account).as_ref(),
+        weecord.config.custom_status(&account).as_ref(),
+    );
+    presence::set_status(status);
+    weecord
+        .config
+        .set_account_status(&account, utils::status_name(status));
     plugin_print(&format!("Status set to {} {:#?}", status_str, status));
 }
 
@@ -530,7 +595,7 @@ fn pins(weechat: &Discord, buffer: &Buffer) {
         None => return,
     };
 
-    let ctx = match crate::discord::get_ctx() {
+    let ctx = &match crate::discord::get_ctx() {
         Some(ctx) => ctx,
         _ => return,
     };
@@ -544,32 +609,123 @@ fn pins(weechat: &Discord, buffer: &Buffer) {
     buffers::load_pin_buffer_history_for_id(channel.id());
 }
 
-fn game(args: &Args) {
-    let ctx = match crate::discord::get_ctx() {
+fn bookmark(weecord: &Discord, args: &Args, buffer: &Buffer) {
+    let buffer = match weecord.buffer_manager.get_buffer(&*buffer.get_name()) {
+        Some(buffer) => buffer,
+        None => return,
+    };
+    // The message is optional, a first word that is not a number starts the note
+    let (arg, note) = match args.args.get(0) {
+        Some(arg) if arg.parse::<u64>().is_ok() => (Some(*arg), args.rest[arg.len()..].trim()),
+        _ => (None, args.rest.trim()),
+    };
+    let note = if note.is_empty() {
+        None
+    } else {
+        Some(note.to_owned())
+    };
+    bookmarks::add(&buffer, arg, note);
+}
+
+fn game(weecord: &Discord, args: &Args) {
+    let ctx = &match crate::discord::get_ctx() {
         Some(ctx) => ctx,
         _ => return,
     };
 
-    let activity = if args.args.is_empty() {
+    let account = discord::account::current();
+    if args.args.is_empty() {
+        match weecord.config.game(&account) {
+            Some(game) => plugin_print(&format!("Current activity: {}", game)),
+            None => plugin_print("No activity set"),
+        }
+        return;
+    }
+
+    let game = if args.rest == "clear" {
+        None
+    } else {
+        match Game::parse(args.rest) {
+            Ok(game) => Some(game),
+            Err(e) => {
+                plugin_print(&e);
+                return;
+            },
+        }
+    };
+
+    weecord.config.set_game(&account, game.as_ref());
+    presence::send_presence(
+        ctx,
+        presence::status(),
+        game.as_ref(),
+        weecord.config.custom_status(&account).as_ref(),
+    );
+
+    match game {
+        Some(game) => plugin_print(&format!("Activity set to {}", game)),
+        None => plugin_print("Activity cleared"),
+    }
+}
+
+fn custom_status(weecord: &Discord, args: &Args) {
+    let account = discord::account::current();
+    if args.args.is_empty() {
+        match weecord.config.custom_status(&account) {
+            Some(custom_status) => {
+                plugin_print(&format!("Current custom status: {}", custom_status))
+            },
+            None => plugin_print("No custom status set"),
+        }
+        return;
+    }
+
+    let custom_status = if args.rest == "clear" {
         None
-    } else if args.args.len() == 1 {
-        Some(Activity::playing(args.args.get(0).unwrap()))
     } else {
-        let activity_type = args.args.get(0).unwrap();
-        let activity = &args.rest[activity_type.len() + 1..];
-
-        Some(match *activity_type {
-            "playing" | "play" => Activity::playing(activity),
-            "listening" => Activity::listening(activity),
-            "watching" | "watch" => Activity::watching(activity),
-            _ => {
-                plugin_print(&format!("Unknown activity type \"{}\"", activity_type));
+        match CustomStatus::parse(args.rest) {
+            Ok(custom_status) => Some(custom_status),
+            Err(e) => {
+                plugin_print(&e);
                 return;
             },
-        })
+        }
     };
 
-    ctx.set_presence(activity, *LAST_STATUS.lock());
+    let game = weecord.config.game(&account);
+
+    discord::account::spawn(move || {
+        let ctx = &match crate::discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+
+        if let Err(e) = presence::set_custom_status(ctx, custom_status.as_ref()) {
+            plugin_print(&format!("Unable to set custom status: {}", e));
+            return;
+        }
+        presence::send_presence(
+            ctx,
+            presence::status(),
+            game.as_ref(),
+            custom_status.as_ref(),
+        );
+        // Only saved once Discord accepted it, so it is not restored if it was rejected
+        let saved_status = custom_status.clone();
+        on_main(move |weecord| {
+            weecord
+                .config
+                .set_custom_status(&account, saved_status.as_ref());
+            if let Some(custom_status) = &saved_status {
+                presence::clear_on_expiry(custom_status);
+            }
+        });
+
+        match custom_status {
+            Some(custom_status) => plugin_print(&format!("Custom status set to {}", custom_status)),
+            None => plugin_print("Custom status cleared"),
+        }
+    });
 }
 
 fn upload(args: &Args, buffer: &Buffer) {
@@ -594,13 +750,17 @@ fn upload(args: &Args, buffer: &Buffer) {
             },
         };
         let full = full.as_str();
-        // TODO: Check perms and file size
+        // TODO: Check file size
         let channel = if let Some(channel) = buffer.channel_id() {
             channel
         } else {
             return;
         };
-        let ctx = match crate::discord::get_ctx() {
+        if !buffer.has_permission(Permissions::ATTACH_FILES) {
+            plugin_print("You do not have permission to upload files to this channel");
+            return;
+        }
+        let ctx = &match crate::discord::get_ctx() {
             Some(ctx) => ctx,
             _ => return,
         };
@@ -615,6 +775,737 @@ fn upload(args: &Args, buffer: &Buffer) {
     }
 }
 
+fn guilds(weecord: &Discord, buffer: &Buffer) {
+    let ctx = &match discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+
+    let mut folders = discord::folders::cached();
+    // Guilds joined since the folders were fetched are not in any of them
+    let foldered: Vec<_> = folders
+        .iter()
+        .flat_map(|folder| folder.guild_ids.iter().cloned())
+        .collect();
+    let mut unsorted: Vec<_> = ctx
+        .cache
+        .read()
+        .guilds
+        .keys()
+        .filter(|id| !foldered.contains(id))
+        .cloned()
+        .collect();
+    unsorted.sort();
+    for guild_id in unsorted {
+        folders.push(discord::folders::GuildFolder {
+            id: None,
+            name: None,
+            color: None,
+            guild_ids: vec![guild_id],
+        });
+    }
+
+    let guild_line = |guild_id: &serenity::model::id::GuildId, indent: &str| {
+        let guild = guild_id.to_guild_cached(&ctx.cache)?;
+        let guild = guild.read();
+        let buffer_id = utils::buffer_id_for_guild(guild.id);
+        let marker = if weecord.buffer_manager.get_buffer(&buffer_id).is_some() {
+            ""
+        } else {
+            " (not joined)"
+        };
+        Some(format!("{}{}{}", indent, guild.name, marker))
+    };
+
+    let prefix = weecord.get_prefix("network").into_owned();
+    buffer.print(&format!("{}\tGuilds:", prefix));
+    for folder in folders {
+        if folder.is_folder() {
+            let color = match folder.color {
+                Some(color) => {
+                    utils::rgb_to_ansi(serenity::utils::Colour::new(color as u32)).to_string()
+                },
+                None => "bold".to_owned(),
+            };
+            let name = folder.display_name(&ctx.cache);
+            buffer.print(&format!(
+                "{}\t  {}",
+                prefix,
+                utils::colorize_string(weecord, &color, &name)
+            ));
+            for line in folder
+                .guild_ids
+                .iter()
+                .filter_map(|id| guild_line(id, "    "))
+            {
+                buffer.print(&format!("{}\t{}", prefix, line));
+            }
+        } else {
+            for line in folder
+                .guild_ids
+                .iter()
+                .filter_map(|id| guild_line(id, "  "))
+            {
+                buffer.print(&format!("{}\t{}", prefix, line));
+            }
+        }
+    }
+}
+
+fn channels(weecord: &Discord, args: &Args, buffer: &Buffer) {
+    let ctx = &match discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+
+    let guild_id = match args.args.get(0) {
+        Some(guild_name) => match crate::utils::search_guild(&ctx.cache, guild_name) {
+            Some(guild) => guild.read().id,
+            None => {
+                plugin_print(&format!("Could not find guild {:?}", guild_name));
+                return;
+            },
+        },
+        None => match buffer.guild_id() {
+            Some(guild_id) => guild_id,
+            None => {
+                plugin_print("channels requires a guild name outside of guild buffers");
+                return;
+            },
+        },
+    };
+
+    buffers::print_channel_tree_to(weecord, buffer, guild_id);
+}
+
+fn voice(weecord: &Discord, args: &Args, buffer: &Buffer) {
+    let ctx = &match discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+
+    let mut words = args.args.iter();
+    let (guild, channel_name) = match (words.next(), words.next()) {
+        (None, _) => {
+            match buffer.guild_id() {
+                Some(guild_id) => crate::voice::print_voice_overview(weecord, buffer, guild_id),
+                None => plugin_print("voice requires a channel outside of guild buffers"),
+            }
+            return;
+        },
+        (Some(channel_name), None) => {
+            match buffer
+                .guild_id()
+                .and_then(|id| id.to_guild_cached(&ctx.cache))
+            {
+                Some(guild) => (guild, channel_name),
+                None => {
+                    plugin_print("voice requires a guild name outside of guild buffers");
+                    return;
+                },
+            }
+        },
+        (Some(guild_name), Some(channel_name)) => {
+            match crate::utils::search_guild(&ctx.cache, guild_name) {
+                Some(guild) => (guild, channel_name),
+                None => {
+                    plugin_print(&format!("Could not find guild {:?}", guild_name));
+                    return;
+                },
+            }
+        },
+    };
+
+    let guild = guild.read();
+    match crate::voice::search_voice_channel(&guild, channel_name) {
+        Some(channel) => crate::voice::create_voice_buffer(weecord, guild.id, &channel.read()),
+        None => plugin_print(&format!("Could not find voice channel {:?}", channel_name)),
+    }
+}
+
+fn forum(args: &Args, buffer: &Buffer) {
+    if args.args.is_empty() && crate::forum::is_forum_buffer(buffer) {
+        crate::forum::refresh_forum_buffer(buffer);
+        return;
+    }
+
+    match buffer.guild_id() {
+        Some(guild_id) => {
+            let name = if args.rest.is_empty() {
+                None
+            } else {
+                Some(args.rest.to_owned())
+            };
+            crate::forum::open_forum(guild_id, name);
+        },
+        None => plugin_print("forum must be run in a guild buffer"),
+    }
+}
+
+fn events(args: &Args, buffer: &Buffer) {
+    let guild_id = if args.args.is_empty() {
+        buffer.guild_id()
+    } else {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        match crate::utils::search_guild(&ctx.cache, args.rest) {
+            Some(guild) => {
+                let guild_id = guild.read().id;
+                Some(guild_id)
+            },
+            None => {
+                plugin_print(&format!("Could not find guild {:?}", args.rest));
+                return;
+            },
+        }
+    };
+
+    match guild_id {
+        Some(guild_id) => crate::scheduled_events::open_events(guild_id),
+        None => plugin_print("events requires a guild name outside of guild buffers"),
+    }
+}
+
+fn dms(weecord: &Discord, args: &Args) {
+    match crate::dms::DmSort::parse(args.args.get(0).copied()) {
+        Some(sort) => crate::dms::open_dm_list(weecord, sort),
+        None => plugin_print("dms can only be sorted by activity or name"),
+    }
+}
+
+fn friends(weecord: &Discord, args: &Args) {
+    let action = match args.args.get(0) {
+        Some(action) => action,
+        None => {
+            crate::friends::open_friends(weecord);
+            return;
+        },
+    };
+    let action = match crate::friends::FriendAction::parse(action) {
+        Some(action) => action,
+        None => {
+            plugin_print("friends requires one of accept, ignore, add, remove, block or unblock");
+            return;
+        },
+    };
+    let target = args.rest[args.args[0].len()..].trim();
+    if target.is_empty() {
+        plugin_print("friends requires a user");
+        return;
+    }
+    crate::friends::change_relationship(action, target.to_owned());
+}
+
+fn ignore(args: &Args, buffer: &Buffer) {
+    let mut target = Vec::new();
+    let mut guild_name = None;
+    let mut duration = None;
+    let mut words = args.args.iter();
+    while let Some(&word) = words.next() {
+        match word {
+            "-guild" => guild_name = words.next().copied(),
+            "-temp" => match words.next().and_then(|d| utils::parse_duration(d)) {
+                Some(d) => duration = Some(d),
+                None => {
+                    plugin_print("-temp requires a duration such as 30m, 1h or 7d");
+                    return;
+                },
+            },
+            _ => target.push(word),
+        }
+    }
+    if target.is_empty() {
+        plugin_print("ignore requires a user");
+        return;
+    }
+    let target = target.join(" ");
+
+    let ctx = &match discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+    let guild_id = match guild_name {
+        Some(name) => match utils::search_guild(&ctx.cache, name) {
+            Some(guild) => {
+                let guild_id = guild.read().id;
+                Some(guild_id)
+            },
+            None => {
+                plugin_print(&format!("Could not find guild {:?}", name));
+                return;
+            },
+        },
+        None => None,
+    };
+    let search_guild = guild_id.or_else(|| buffer.guild_id());
+
+    discord::account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let user = match utils::search_user(ctx, search_guild, &target) {
+            Some(user) => user,
+            None => {
+                plugin_print(&format!("Could not find user {:?}", target));
+                return;
+            },
+        };
+
+        on_main(move |weecord| {
+            crate::ignore::add(
+                weecord,
+                crate::ignore::Ignore {
+                    user_id: user.id,
+                    guild_id,
+                    expires_at: duration.map(|d| chrono::Utc::now() + d),
+                },
+            );
+            crate::ignore::redraw_buffers(weecord);
+            plugin_print(&format!("Ignoring {}", user.tag()));
+        });
+    });
+}
+
+fn unignore(args: &Args, buffer: &Buffer) {
+    if args.args.is_empty() {
+        plugin_print("unignore requires a user");
+        return;
+    }
+    let target = args.rest.to_owned();
+    let guild_id = buffer.guild_id();
+
+    discord::account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let user = match utils::search_user(ctx, guild_id, &target) {
+            Some(user) => user,
+            None => {
+                plugin_print(&format!("Could not find user {:?}", target));
+                return;
+            },
+        };
+
+        on_main(move |weecord| {
+            if crate::ignore::remove(weecord, user.id) {
+                crate::ignore::redraw_buffers(weecord);
+                plugin_print(&format!("No longer ignoring {}", user.tag()));
+            } else {
+                plugin_print(&format!("{} is not ignored", user.tag()));
+            }
+        });
+    });
+}
+
+fn ignored(weecord: &Discord) {
+    let ignores = weecord.config.ignored_users();
+    if ignores.is_empty() {
+        plugin_print("No ignored users");
+        return;
+    }
+    let ctx = discord::get_ctx();
+
+    plugin_print("Ignored users:");
+    for ignore in ignores {
+        let cache = ctx.as_ref().map(|ctx| ctx.cache.read());
+        let user = cache
+            .as_ref()
+            .and_then(|cache| cache.users.get(&ignore.user_id))
+            .map(|user| user.read().tag())
+            .unwrap_or_else(|| ignore.user_id.0.to_string());
+        let mut line = format!("  {}", user);
+        if let Some(guild_id) = ignore.guild_id {
+            let guild = cache
+                .as_ref()
+                .and_then(|cache| cache.guilds.get(&guild_id))
+                .map(|guild| guild.read().name.clone())
+                .unwrap_or_else(|| guild_id.0.to_string());
+            line.push_str(&format!(" in {}", guild));
+        }
+        if let Some(expires_at) = ignore.expires_at {
+            line.push_str(&format!(
+                " until {}",
+                expires_at
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+            ));
+        }
+        plugin_print(&line);
+    }
+}
+
+fn moderate(args: &Args, buffer: &Buffer) {
+    use crate::moderation::Action;
+
+    let mut words = args.args.iter().copied();
+    let target = match words.next() {
+        Some(target) => target.to_owned(),
+        None => {
+            plugin_print(&format!("{} requires a user", args.base));
+            return;
+        },
+    };
+    let action = match args.base {
+        "kick" => Action::Kick,
+        "ban" => Action::Ban,
+        "unban" => Action::Unban,
+        "timeout" => match crate::moderation::parse_timeout(words.next().unwrap_or_default()) {
+            Ok(duration) => Action::Timeout(duration),
+            Err(e) => {
+                plugin_print(&e);
+                return;
+            },
+        },
+        _ => unreachable!(),
+    };
+    let reason = words.collect::<Vec<_>>().join(" ");
+    let reason = Some(reason).filter(|reason| !reason.is_empty());
+
+    crate::moderation::moderate(buffer, action, target, reason);
+}
+
+fn purge(weecord: &Discord, args: &Args, buffer: &Buffer) {
+    if args.args.get(0) == Some(&"confirm") {
+        crate::moderation::confirm_purge(weecord, buffer);
+        return;
+    }
+
+    let count = match args
+        .args
+        .get(0)
+        .and_then(|count| count.parse::<usize>().ok())
+    {
+        Some(count) if count >= 1 && count <= crate::moderation::MAX_PURGE => count,
+        _ => {
+            plugin_print(&format!(
+                "purge requires a number of messages between 1 and {}",
+                crate::moderation::MAX_PURGE
+            ));
+            return;
+        },
+    };
+    let mut words = args.args.iter().skip(1).copied().peekable();
+    let target = if words.peek() == Some(&"-user") {
+        words.next();
+        match words.next() {
+            Some(user) => Some(user.to_owned()),
+            None => {
+                plugin_print("-user requires a user");
+                return;
+            },
+        }
+    } else {
+        None
+    };
+    let reason = words.collect::<Vec<_>>().join(" ");
+    let reason = Some(reason).filter(|reason| !reason.is_empty());
+
+    crate::moderation::purge(buffer, count, target, reason);
+}
+
+fn audit_log(args: &Args, buffer: &Buffer) {
+    let mut guild_name = Vec::new();
+    let mut user_name = None;
+    let mut action_type = None;
+    let mut words = args.args.iter().copied();
+    while let Some(word) = words.next() {
+        match word {
+            "-user" => user_name = words.next().map(ToOwned::to_owned),
+            "-action" => {
+                let action = words.next().unwrap_or_default();
+                match crate::audit_log::parse_action_type(action) {
+                    Some(action) => action_type = Some(action),
+                    None => {
+                        plugin_print(&format!(
+                            "Unknown action {:?}, expected one of {}",
+                            action,
+                            crate::audit_log::action_type_names()
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ));
+                        return;
+                    },
+                }
+            },
+            _ => guild_name.push(word),
+        }
+    }
+
+    let ctx = &match discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+    let guild = if guild_name.is_empty() {
+        buffer
+            .guild_id()
+            .and_then(|guild_id| guild_id.to_guild_cached(&ctx.cache))
+    } else {
+        utils::search_guild(&ctx.cache, &guild_name.join(" "))
+    };
+    let (guild_id, guild_name) = match guild {
+        Some(guild) => {
+            let guild = guild.read();
+            (guild.id, guild.name.clone())
+        },
+        None if guild_name.is_empty() => {
+            plugin_print("auditlog requires a guild name outside of guild buffers");
+            return;
+        },
+        None => {
+            plugin_print(&format!("Could not find guild {:?}", guild_name.join(" ")));
+            return;
+        },
+    };
+    if buffer.guild_id() == Some(guild_id) && !buffer.has_permission(Permissions::VIEW_AUDIT_LOG) {
+        plugin_print("You do not have permission to view the audit log of this guild");
+        return;
+    }
+
+    discord::account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let user_id = match user_name {
+            Some(name) => match utils::search_user(ctx, Some(guild_id), &name) {
+                Some(user) => Some(user.id),
+                None => {
+                    plugin_print(&format!("Could not find user {:?}", name));
+                    return;
+                },
+            },
+            None => None,
+        };
+
+        on_main(move |weecord| {
+            crate::audit_log::create_audit_log_buffer(
+                weecord,
+                guild_id,
+                &guild_name,
+                user_id,
+                action_type,
+            );
+        });
+    });
+}
+
+fn group(args: &Args, buffer: &Buffer) {
+    use crate::dms::GroupAction;
+
+    let names = || {
+        args.args
+            .iter()
+            .skip(1)
+            .map(|name| name.to_string())
+            .collect()
+    };
+    let action = match args.args.get(0).copied() {
+        Some("create") => {
+            if args.args.len() < 2 {
+                plugin_print("group create requires at least one user");
+            } else {
+                crate::dms::create_group(names());
+            }
+            return;
+        },
+        Some("add") => GroupAction::Add(names()),
+        Some("remove") => GroupAction::Remove(names()),
+        Some("rename") => {
+            let name = args.rest["rename".len()..].trim();
+            if name.is_empty() {
+                plugin_print("group rename requires a name");
+                return;
+            }
+            GroupAction::Rename(name.to_owned())
+        },
+        Some("leave") => GroupAction::Leave,
+        _ => {
+            plugin_print("group requires one of create, add, remove, rename or leave");
+            return;
+        },
+    };
+    crate::dms::edit_group(buffer, action);
+}
+
+fn crosspost(weecord: &Discord, args: &Args, buffer: &Buffer) {
+    let channel_id = match buffer.channel_id() {
+        Some(channel_id) => channel_id,
+        None => return,
+    };
+    if buffer.get_localvar("announcement").as_deref() != Some("1") {
+        plugin_print("crosspost only works in announcement channels");
+        return;
+    }
+
+    let message_id = match weecord
+        .buffer_manager
+        .get_buffer(buffer.get_name().as_ref())
+        .and_then(|buffer| utils::message_id_for_arg(&buffer, args.args.get(0).copied()))
+    {
+        Some(message_id) => message_id,
+        None => {
+            plugin_print("Could not find that message");
+            return;
+        },
+    };
+
+    discord::account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+
+        let path = format!(
+            "/channels/{}/messages/{}/crosspost",
+            channel_id.0, message_id.0
+        );
+        match discord::rest::post(ctx, &path, json::JsonValue::new_object()) {
+            Ok(_) => plugin_print("Message published to following channels"),
+            Err(e) => plugin_print(&format!("Unable to publish message: {}", e)),
+        }
+    });
+}
+
+fn whois(args: &Args, buffer: &Buffer) {
+    if args.args.is_empty() {
+        plugin_print("whois requires a user");
+        return;
+    }
+    let target = args.rest.to_owned();
+    let guild_id = buffer.guild_id();
+    let sealed_buffer = buffer.seal();
+
+    discord::account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+
+        let user = match utils::search_user(ctx, guild_id, &target) {
+            Some(user) => user,
+            None => {
+                plugin_print(&format!("Could not find user {:?}", target));
+                return;
+            },
+        };
+        let member = guild_id.and_then(|guild_id| guild_id.member(ctx, user.id).ok());
+        let profile = discord::rest::get(ctx, &format!("/users/{}/profile", user.id.0)).ok();
+
+        on_main(move |weecord| {
+            let ctx = &match discord::get_ctx() {
+                Some(ctx) => ctx,
+                _ => return,
+            };
+            let buffer = sealed_buffer.unseal(&weecord);
+            let prefix = weecord.get_prefix("network").into_owned();
+            for line in whois_lines(weecord, ctx, &user, member.as_ref(), profile.as_ref()) {
+                buffer.print(&format!("{}\t{}", prefix, line));
+            }
+        });
+    });
+}
+
+fn whois_lines(
+    weechat: &Weechat,
+    ctx: &Context,
+    user: &User,
+    member: Option<&Member>,
+    profile: Option<&json::JsonValue>,
+) -> Vec<String> {
+    let mut lines = vec![format!(
+        "{} ({}){}",
+        utils::colorize_string(weechat, "bold", &user.tag()),
+        user.id.0,
+        if user.bot { " [BOT]" } else { "" }
+    )];
+
+    lines.push(format!(
+        "  created: {}",
+        user.id.created_at().format("%Y-%m-%d %H:%M")
+    ));
+    if let Some(premium_since) = profile.and_then(|p| p["premium_since"].as_str()) {
+        lines.push(format!(
+            "  nitro since: {}",
+            premium_since.split('T').next().unwrap_or(premium_since)
+        ));
+    }
+
+    if let Some(member) = member {
+        if let Some(nick) = &member.nick {
+            lines.push(format!("  nick: {}", nick));
+        }
+        if let Some(joined_at) = member.joined_at {
+            lines.push(format!("  joined: {}", joined_at.format("%Y-%m-%d %H:%M")));
+        }
+        if let Some(mut roles) = member.roles(&ctx.cache) {
+            roles.sort();
+            let roles = roles
+                .iter()
+                .rev()
+                .map(|role| {
+                    if role.colour.0 == 0 {
+                        role.name.clone()
+                    } else {
+                        let color = utils::rgb_to_ansi(role.colour).to_string();
+                        utils::colorize_string(weechat, &color, &role.name)
+                    }
+                })
+                .collect::<Vec<_>>();
+            if !roles.is_empty() {
+                lines.push(format!("  roles: {}", roles.join(", ")));
+            }
+        }
+    }
+
+    let presence = ctx.cache.read().presences.get(&user.id).cloned();
+    let status = presence
+        .as_ref()
+        .map(|p| p.status)
+        .unwrap_or(OnlineStatus::Offline);
+    lines.push(format!(
+        "  status: {}{}",
+        utils::format_user_status_prefix(weechat, Some(status)),
+        utils::status_name(status)
+    ));
+    let activities = presence::activities(user.id, presence.as_ref());
+    for activity in activities.iter().filter(|a| a.kind == ActivityType::Custom) {
+        lines.push(format!(
+            "  custom status: {}",
+            utils::format_custom_status(activity)
+        ));
+    }
+    for activity in activities.iter().filter(|a| a.kind != ActivityType::Custom) {
+        lines.push(format!("  activity: {}", utils::format_activity(activity)));
+    }
+
+    if let Some(profile) = profile {
+        let connections = profile["connected_accounts"]
+            .members()
+            .filter_map(|account| {
+                Some(format!(
+                    "{} ({})",
+                    account["name"].as_str()?,
+                    account["type"].as_str()?
+                ))
+            })
+            .collect::<Vec<_>>();
+        if !connections.is_empty() {
+            lines.push(format!("  connections: {}", connections.join(", ")));
+        }
+
+        let mutual_guilds = profile["mutual_guilds"].len();
+        if mutual_guilds > 0 {
+            lines.push(format!("  mutual servers: {}", mutual_guilds));
+        }
+    }
+
+    lines
+}
+
 // rust-lang/rust#52662 would let this api be improved by accepting option types
 fn format_option_change<T: std::fmt::Display>(
     name: &str,
@@ -659,7 +1550,7 @@ fn discord_fmt(cmd: &str, msg: &str, buffer: &Buffer) {
         return;
     };
 
-    let ctx = match crate::discord::get_ctx() {
+    let ctx = &match crate::discord::get_ctx() {
         Some(ctx) => ctx,
         _ => return,
     };
@@ -684,8 +1575,8 @@ Discord from the comfort of your favorite command-line IRC client!
 Source code available at https://github.com/terminal-discord/weechat-discord
 Originally by https://github.com/khyperia/weechat-discord",
     args: "
-    connect
-    disconnect
+    connect [<account>]
+    disconnect [<account>]
     join
     query
     watch
@@ -693,12 +1584,38 @@ Originally by https://github.com/khyperia/weechat-discord",
     watched
     autojoined
     pins
+    pin [<n>|<message id>]
+    unpin [<n>|<message id>]
+    bookmark [<n>|<message id>] [<note>]
+    bookmarks
     irc-mode
     discord-mode
-    autostart
-    noautostart
+    autostart [<account>]
+    noautostart [<account>]
     token <token>
+    account <name> <token>
     upload <file>
+    game [playing|listening|watching] <name>|clear
+    customstatus [emoji] <text> [-expires 30m|1h|today]|clear
+    whois <user>
+    voice [<guild>] [<channel>]
+    channels [<guild>]
+    guilds
+    forum [<name>]
+    events [<guild>]
+    crosspost [<n>|<message id>]
+    dms [activity|name]
+    friends [accept|ignore|add|remove|block|unblock <user>]
+    ignore <user> [-guild <guild>] [-temp <duration>]
+    unignore <user>
+    ignored
+    kick <user> [<reason>]
+    ban <user> [<reason>]
+    unban <user> [<reason>]
+    timeout <user> <duration>|clear [<reason>]
+    purge <n> [-user <user>] [<reason>]|confirm
+    auditlog [<guild>] [-user <user>] [-action <type>]
+    group create <user>...|add <user>...|remove <user>...|rename <name>|leave
     me
     tableflip
     unflip
@@ -706,8 +1623,8 @@ Originally by https://github.com/khyperia/weechat-discord",
     spoiler
     rehistory",
     args_description: "
-    connect: sign in to discord and open chat buffers
-    disconnect: sign out of Discord
+    connect: sign in to discord and open chat buffers, accounts other than the default one are added with account
+    disconnect: sign out of Discord, or of a single account
     join: join a channel in irc mode by providing guild name and channel name
     query: open a dm with a user (for when there are no discord buffers open)
     irc-mode: enable irc-mode, meaning that weecord will not load all channels like the official client
@@ -716,13 +1633,39 @@ Originally by https://github.com/khyperia/weechat-discord",
     autojoin: Automatically open a channel or entire guild when discord connects
     watched: List watched guilds and channels
     autojoined: List autojoined guilds and channels
-    pins: Show a list of pinned messages for the current channel
-    autostart: automatically sign into discord on start
-    noautostart: disable autostart
-    status: set your Discord online status
+    pins: Show a list of pinned messages for the current channel, entering unpin <n> or pin <message id> in it manages the pins and jump <n> scrolls the channel to a pin
+    pin: pin a message in the current channel, by default the newest message, <n> counts back from the newest message
+    unpin: unpin a message in the current channel or its pins buffer
+    bookmark: save a message of the current channel to your local bookmarks with an optional note, by default the newest message
+    bookmarks: open a buffer listing your bookmarks, entering jump <n> in it switches to the channel of a bookmark and delete <n> removes it
+    autostart: automatically sign into discord on start, or only connect one account on start
+    noautostart: disable autostart, or only for one account
+    status: set your Discord online status, which is restored on connecting
+    game: set the activity shown to others, or show the current one when run without arguments
+    customstatus: set your custom status, or show the current one when run without arguments
     token: set Discord login token
+    account: add an additional account with its login token, or change its token
     rehistory: reload the history in the current buffer
     upload: upload a file to the current channel
+    whois: show the profile, roles, custom status and activity of a user
+    guilds: list all guilds grouped by guild folder
+    channels: list the channels of a guild by category, marking unread channels
+    voice: open a buffer listing who is connected to a voice channel, or list all voice channels of the current guild
+    forum: open a buffer listing the posts of a forum, or list the forums of the current guild. Entering the number of a post opens it, running forum in a forum buffer reloads it
+    events: open a buffer listing the scheduled events of a guild, entering the number of an event toggles your interest in it
+    crosspost: publish a message in an announcement channel to the channels following it, by default the newest message, <n> counts back from the newest message
+    dms: open a buffer listing your DMs and group DMs, most recently active first or by name. Entering the number of a channel opens it
+    friends: open a buffer listing your friends, friend requests and blocked users, or accept or ignore a friend request, send one, remove a friend and block or unblock a user
+    ignore: hide the messages of a user, everywhere or only in one guild, and optionally only for a while. Messages are tagged discord_ignored for a filter, or collapsed with weecord.main.ignored_placeholder
+    unignore: stop ignoring a user
+    ignored: list ignored users
+    kick: kick a user from the guild of the current channel, the reason is recorded in the audit log
+    ban: ban a user from the guild of the current channel
+    unban: lift the ban of a user
+    timeout: stop a user from talking for a while (at most 28 days), clear lifts the timeout
+    purge: delete the last <n> messages of the current channel, optionally only those of one user. The messages are only deleted after running purge confirm
+    auditlog: open a buffer listing the audit log of a guild with the changes and reason of each entry, optionally only the actions of one user or of one type. Entering more in the buffer loads older entries
+    group: create a group DM with some users, or add and remove users, rename or leave the group DM of the current buffer
 
 Examples:
   /discord token 123456789ABCDEF
@@ -732,8 +1675,8 @@ Examples:
   /discord upload file.txt
 ",
     completion:
-"connect || \
-disconnect || \
+"connect %(weecord_account_completion) || \
+disconnect %(weecord_account_completion) || \
 query %(weecord_dm_completion) || \
 watch %(weecord_guild_completion) %(weecord_channel_completion) || \
 nowatch %(weecord_guild_completion) %(weecord_channel_completion) || \
@@ -744,12 +1687,37 @@ noautojoin %(weecord_guild_completion) %(weecord_channel_completion) || \
 irc-mode || \
 discord-mode || \
 pins || \
+pin || \
+unpin || \
+bookmark || \
+bookmarks || \
 token || \
-autostart || \
-noautostart || \
+account || \
+autostart %(weecord_account_completion) || \
+noautostart %(weecord_account_completion) || \
 status online|offline|invisible|idle|dnd || \
-game playing|listening|watching || \
+game playing|listening|watching|clear || \
+customstatus clear || \
 upload %(filename) || \
+whois %(nicks)|%(weecord_dm_completion) || \
+voice %(weecord_guild_completion) || \
+channels %(weecord_guild_completion) || \
+guilds || \
+forum || \
+events %(weecord_guild_completion) || \
+crosspost || \
+dms activity|name || \
+friends accept|ignore|add|remove|block|unblock %(weecord_dm_completion) || \
+ignore %(nicks)|%(weecord_dm_completion) -guild|-temp %(weecord_guild_completion) || \
+unignore %(nicks)|%(weecord_dm_completion) || \
+ignored || \
+kick %(nicks) || \
+ban %(nicks) || \
+unban || \
+timeout %(nicks) 10m|1h|1d|7d|clear || \
+purge confirm|10|50|100 -user %(nicks) || \
+auditlog %(weecord_guild_completion)|-user|-action %(nicks)|member_kick|member_ban_add|member_update|member_role_update|message_delete|channel_update|role_update || \
+group create|add|remove|rename|leave %(weecord_dm_completion)|%(nicks) || \
 me || \
 tableflip || \
 unflip || \
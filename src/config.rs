@@ -1,19 +1,53 @@
-use crate::{utils, utils::GuildOrChannel};
+use crate::{
+    discord::{
+        account,
+        presence::{CustomStatus, Game},
+    },
+    ignore::Ignore,
+    utils,
+    utils::GuildOrChannel,
+};
+use chrono::{DateTime, Utc};
+use std::{cell::RefCell, rc::Rc};
 use weechat::{
-    BooleanOption, ConfigOption, ConfigSectionInfo, IntegerOption, StringOption, Weechat,
+    BooleanOption, ConfigOption, ConfigSection, ConfigSectionInfo, IntegerOption, OptionChanged,
+    StringOption, Weechat,
 };
 
-pub struct Config {
+const ACCOUNT_SECTION: &str = "account";
+
+/// Options of an account, the default account keeps them in the main section and additional
+/// accounts as `weecord.account.<name>.<option>`
+pub struct AccountOptions {
     pub token: StringOption,
+    pub autostart: BooleanOption,
+    pub status: StringOption,
+    pub game: StringOption,
+    pub custom_status: StringOption,
+    pub custom_status_emoji: StringOption,
+    pub custom_status_expires: StringOption,
+}
+
+type Accounts = Rc<RefCell<Vec<(String, AccountOptions)>>>;
+
+pub struct Config {
+    default_account: AccountOptions,
+    accounts: Accounts,
     pub watched_channels: StringOption,
     pub autojoin_channels: StringOption,
-    pub autostart: BooleanOption,
     pub use_presence: BooleanOption,
     pub send_typing_events: BooleanOption,
     pub irc_mode: BooleanOption,
     pub message_fetch_count: IntegerOption,
     pub user_typing_list_max: IntegerOption,
     pub user_typing_list_expanded: BooleanOption,
+    pub member_events: BooleanOption,
+    pub voice_events: BooleanOption,
+    pub ignored_users: StringOption,
+    pub ignored_placeholder: BooleanOption,
+    pub ignored_filter_created: BooleanOption,
+    /// `ignored_users` as last parsed, along with the option value it was parsed from
+    ignored_users_cache: RefCell<(String, Vec<Ignore>)>,
     pub config: weechat::Config<()>,
 }
 
@@ -27,15 +61,7 @@ pub fn init(weechat: &Weechat) -> Config {
 
     let section = config.new_section(section_info);
 
-    let token = section.new_string_option(
-        "token",
-        "Discord auth token. Supports secure data",
-        "",
-        "",
-        false,
-        None,
-        None::<()>,
-    );
+    let default_account = new_account_options(&section, "");
 
     let watched_channels = section.new_string_option(
         "watched_channels",
@@ -57,16 +83,6 @@ pub fn init(weechat: &Weechat) -> Config {
         None::<()>,
     );
 
-    let autostart = section.new_boolean_option(
-        "autostart",
-        "Automatically connect to Discord when weechat starts",
-        false,
-        false,
-        false,
-        None,
-        None::<()>,
-    );
-
     let use_presence = section.new_boolean_option(
         "use_presence",
         "Show the presence of other users in the nicklist",
@@ -133,24 +149,286 @@ pub fn init(weechat: &Weechat) -> Config {
         None::<()>,
     );
 
+    let member_events = section.new_boolean_option(
+        "member_events",
+        "Print members joining, leaving, getting banned and role changes in the guild buffer",
+        false,
+        false,
+        false,
+        None,
+        None::<()>,
+    );
+
+    let voice_events = section.new_boolean_option(
+        "voice_events",
+        "Print members joining, leaving and moving between voice channels in the guild buffer",
+        false,
+        false,
+        false,
+        None,
+        None::<()>,
+    );
+
+    let ignored_users = section.new_string_option(
+        "ignored_users",
+        "Users whose messages are hidden, as user_id[:guild_id[:expires]] entries (set with /discord ignore)",
+        "",
+        "",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let ignored_placeholder = section.new_boolean_option(
+        "ignored_placeholder",
+        "Show messages of ignored users as a placeholder instead of tagging them for the discord_ignored filter",
+        false,
+        false,
+        false,
+        None,
+        None::<()>,
+    );
+
+    let ignored_filter_created = section.new_boolean_option(
+        "ignored_filter_created",
+        "Whether the discord_ignored filter was added, it is only added once so it can be changed or deleted",
+        false,
+        false,
+        false,
+        None,
+        None::<()>,
+    );
+
+    // The options of additional accounts are created as they are read, which also picks up
+    // accounts added to the file on /reload
+    let accounts = Accounts::default();
+    let section_info: ConfigSectionInfo<Accounts> = ConfigSectionInfo {
+        name: ACCOUNT_SECTION,
+        read_callback: Some(read_account_option),
+        read_callback_data: Some(Rc::clone(&accounts)),
+        ..Default::default()
+    };
+    config.new_section(section_info);
+
     config.read();
 
     Config {
-        token,
+        default_account,
+        accounts,
         watched_channels,
         autojoin_channels,
-        autostart,
         use_presence,
         send_typing_events,
         irc_mode,
         message_fetch_count,
         user_typing_list_max,
         user_typing_list_expanded,
+        member_events,
+        voice_events,
+        ignored_users,
+        ignored_placeholder,
+        ignored_filter_created,
+        ignored_users_cache: RefCell::default(),
         config,
     }
 }
 
+/// Create the options of an account, prefixed with `<name>.` unless it is the default account
+fn new_account_options(section: &ConfigSection, name: &str) -> AccountOptions {
+    let option_name = |option: &str| {
+        if name.is_empty() {
+            option.to_owned()
+        } else {
+            format!("{}.{}", name, option)
+        }
+    };
+
+    let token = section.new_string_option(
+        &option_name("token"),
+        "Discord auth token of the account. Supports secure data",
+        "",
+        "",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let autostart = section.new_boolean_option(
+        &option_name("autostart"),
+        "Automatically connect the account when weechat starts",
+        false,
+        false,
+        false,
+        None,
+        None::<()>,
+    );
+
+    let status = section.new_string_option(
+        &option_name("status"),
+        "Status to set on connecting (online, idle, dnd or invisible), set with /discord status",
+        "",
+        "",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let game = section.new_string_option(
+        &option_name("game"),
+        "Activity to show on connecting, eg \"playing Minecraft\" (set with /discord game)",
+        "",
+        "",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let custom_status = section.new_string_option(
+        &option_name("custom_status"),
+        "Custom status text to set on connecting (set with /discord customstatus)",
+        "",
+        "",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let custom_status_emoji = section.new_string_option(
+        &option_name("custom_status_emoji"),
+        "Emoji shown next to the custom status",
+        "",
+        "",
+        false,
+        None,
+        None::<()>,
+    );
+
+    let custom_status_expires = section.new_string_option(
+        &option_name("custom_status_expires"),
+        "When the custom status expires (RFC 3339), empty to never expire",
+        "",
+        "",
+        false,
+        None,
+        None::<()>,
+    );
+
+    AccountOptions {
+        token,
+        autostart,
+        status,
+        game,
+        custom_status,
+        custom_status_emoji,
+        custom_status_expires,
+    }
+}
+
+/// Read an option of the account section, written as `<name>.<option> = <value>`
+fn read_account_option(
+    accounts: &Accounts,
+    _config: &weechat::Config<()>,
+    section: &ConfigSection,
+    option_name: &str,
+    value: &str,
+) -> OptionChanged {
+    let (name, option) = match option_name.rfind('.') {
+        Some(dot) if dot > 0 => (&option_name[..dot], &option_name[dot + 1..]),
+        _ => return OptionChanged::NotFound,
+    };
+    if name == account::DEFAULT_ACCOUNT {
+        return OptionChanged::Error;
+    }
+
+    let mut accounts = accounts.borrow_mut();
+    if !accounts.iter().any(|(account, _)| account == name) {
+        let options = new_account_options(section, name);
+        accounts.push((name.to_owned(), options));
+    }
+    let options = match accounts.iter().find(|(account, _)| account == name) {
+        Some((_, options)) => options,
+        None => return OptionChanged::Error,
+    };
+
+    match option {
+        "token" => options.token.set(value),
+        "autostart" => options.autostart.set(value == "on"),
+        "status" => options.status.set(value),
+        "game" => options.game.set(value),
+        "custom_status" => options.custom_status.set(value),
+        "custom_status_emoji" => options.custom_status_emoji.set(value),
+        "custom_status_expires" => options.custom_status_expires.set(value),
+        _ => OptionChanged::NotFound,
+    }
+}
+
 impl Config {
+    /// Names of all configured accounts, starting with the default account
+    pub fn account_names(&self) -> Vec<String> {
+        std::iter::once(account::DEFAULT_ACCOUNT.to_owned())
+            .chain(self.accounts.borrow().iter().map(|(name, _)| name.clone()))
+            .collect()
+    }
+
+    /// Run a function with the options of an account
+    fn with_account<R>(&self, name: &str, f: impl FnOnce(&AccountOptions) -> R) -> Option<R> {
+        if name == account::DEFAULT_ACCOUNT {
+            return Some(f(&self.default_account));
+        }
+        self.accounts
+            .borrow()
+            .iter()
+            .find(|(account, _)| account == name)
+            .map(|(_, options)| f(options))
+    }
+
+    /// Add an account, or replace the token of an existing one
+    ///
+    /// Returns false if the account section could not be found
+    pub fn set_account_token(&self, name: &str, token: &str) -> bool {
+        if self
+            .with_account(name, |options| options.token.set(token))
+            .is_some()
+        {
+            return true;
+        }
+
+        let section = match self.config.search_section(ACCOUNT_SECTION) {
+            Some(section) => section,
+            None => return false,
+        };
+        let options = new_account_options(section, name);
+        options.token.set(token);
+        self.accounts.borrow_mut().push((name.to_owned(), options));
+        true
+    }
+
+    /// The unevaluated token of an account
+    pub fn account_token(&self, name: &str) -> Option<String> {
+        self.with_account(name, |options| options.token.value().into_owned())
+    }
+
+    pub fn account_autostart(&self, name: &str) -> bool {
+        self.with_account(name, |options| options.autostart.value())
+            .unwrap_or(false)
+    }
+
+    /// Returns false if there is no such account
+    pub fn set_account_autostart(&self, name: &str, autostart: bool) -> bool {
+        self.with_account(name, |options| options.autostart.set(autostart))
+            .is_some()
+    }
+
+    /// The status saved for an account, if one was set
+    pub fn account_status(&self, name: &str) -> Option<String> {
+        self.with_account(name, |options| options.status.value().into_owned())
+            .filter(|status| !status.is_empty())
+    }
+
+    pub fn set_account_status(&self, name: &str, status: &str) {
+        let _ = self.with_account(name, |options| options.status.set(status));
+    }
+
     pub fn autojoin_channels(&self) -> Vec<GuildOrChannel> {
         self.autojoin_channels
             .value()
@@ -168,4 +446,101 @@ impl Config {
             .filter_map(utils::parse_id)
             .collect()
     }
+
+    pub fn game(&self, account: &str) -> Option<Game> {
+        let game = self.with_account(account, |options| options.game.value().into_owned())?;
+        if game.is_empty() {
+            None
+        } else {
+            Game::parse(&game).ok()
+        }
+    }
+
+    pub fn set_game(&self, account: &str, game: Option<&Game>) {
+        let game = game.map(ToString::to_string).unwrap_or_default();
+        let _ = self.with_account(account, |options| options.game.set(&game));
+    }
+
+    /// The persisted custom status of an account, if one is set and has not yet expired
+    pub fn custom_status(&self, account: &str) -> Option<CustomStatus> {
+        let (text, emoji) = self.with_account(account, |options| {
+            (
+                options.custom_status.value().into_owned(),
+                options.custom_status_emoji.value().into_owned(),
+            )
+        })?;
+        if text.is_empty() && emoji.is_empty() {
+            return None;
+        }
+
+        let custom_status = CustomStatus {
+            emoji: Some(emoji).filter(|e| !e.is_empty()),
+            text,
+            expires_at: self.custom_status_expires(account),
+        };
+
+        if custom_status.is_expired() {
+            None
+        } else {
+            Some(custom_status)
+        }
+    }
+
+    /// When the persisted custom status of an account expires, even if it already has
+    pub fn custom_status_expires(&self, account: &str) -> Option<DateTime<Utc>> {
+        let expires = self.with_account(account, |options| {
+            options.custom_status_expires.value().into_owned()
+        })?;
+        DateTime::parse_from_rfc3339(&expires)
+            .ok()
+            .map(|expires_at| expires_at.with_timezone(&Utc))
+    }
+
+    pub fn set_custom_status(&self, account: &str, custom_status: Option<&CustomStatus>) {
+        let (text, emoji, expires) = match custom_status {
+            Some(status) => (
+                status.text.clone(),
+                status.emoji.clone().unwrap_or_default(),
+                status
+                    .expires_at
+                    .map(|e| e.to_rfc3339())
+                    .unwrap_or_default(),
+            ),
+            None => Default::default(),
+        };
+        let _ = self.with_account(account, |options| {
+            options.custom_status.set(&text);
+            options.custom_status_emoji.set(&emoji);
+            options.custom_status_expires.set(&expires);
+        });
+    }
+
+    /// Ignored users, without any temporary ignores that have expired
+    ///
+    /// Checked for every printed message, so the option is only parsed again once it changed
+    pub fn ignored_users(&self) -> Vec<Ignore> {
+        let value = self.ignored_users.value();
+        let mut cache = self.ignored_users_cache.borrow_mut();
+        if cache.0 != *value {
+            *cache = (
+                value.to_string(),
+                value.split(',').filter_map(Ignore::parse).collect(),
+            );
+        }
+        cache
+            .1
+            .iter()
+            .filter(|ignore| !ignore.is_expired())
+            .cloned()
+            .collect()
+    }
+
+    pub fn set_ignored_users(&self, ignores: &[Ignore]) {
+        let ignores = ignores
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        self.ignored_users.set(&ignores);
+    }
 }
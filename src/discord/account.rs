@@ -0,0 +1,121 @@
+//! Tracks which account the current thread is acting for, so several accounts can be connected
+//! at once
+//!
+//! Event handlers enter the session of their client, jobs run on the main thread and threads
+//! spawned through `spawn` act for the account they were created from, and weechat callbacks
+//! enter the account of the buffer they are run in.
+use super::session::Session;
+use std::{borrow::Cow, cell::RefCell, sync::Arc, thread};
+use weechat::Buffer;
+
+/// The account configured with `weecord.main.token`, its buffers are not namespaced
+pub const DEFAULT_ACCOUNT: &str = "default";
+
+#[derive(Clone)]
+struct Current {
+    account: String,
+    session: Option<Arc<Session>>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Current> = RefCell::new(Current {
+        account: DEFAULT_ACCOUNT.to_owned(),
+        session: None,
+    });
+}
+
+/// Restores the previous account when dropped
+#[must_use]
+pub struct AccountGuard {
+    previous: Option<Current>,
+}
+
+impl Drop for AccountGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            CURRENT.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+}
+
+fn enter_current(new: Current) -> AccountGuard {
+    let previous = CURRENT.with(|current| current.replace(new));
+    AccountGuard {
+        previous: Some(previous),
+    }
+}
+
+/// Act for an account until the guard is dropped
+///
+/// The session of the account is only looked up on the main thread, other threads get it from
+/// whoever started them
+pub fn enter(account: &str) -> AccountGuard {
+    let session = crate::sync::plugin().and_then(|weecord| weecord.session(account));
+    enter_current(Current {
+        account: account.to_owned(),
+        session,
+    })
+}
+
+/// Act for the account of a session until the guard is dropped
+pub fn enter_session(session: &Arc<Session>) -> AccountGuard {
+    enter_current(Current {
+        account: session.account().to_owned(),
+        session: Some(Arc::clone(session)),
+    })
+}
+
+pub fn current() -> String {
+    CURRENT.with(|current| current.borrow().account.clone())
+}
+
+/// The session of the current account, if it is connected
+pub fn session() -> Option<Arc<Session>> {
+    CURRENT
+        .with(|current| current.borrow().session.clone())
+        .filter(|session| !session.is_closed())
+}
+
+/// The account of the current thread, to act for it somewhere else
+pub struct Captured(Current);
+
+impl Captured {
+    pub fn enter(self) -> AccountGuard {
+        enter_current(self.0)
+    }
+}
+
+pub fn capture() -> Captured {
+    Captured(CURRENT.with(|current| current.borrow().clone()))
+}
+
+/// Spawn a thread acting for the current account
+pub fn spawn<F, T>(f: F) -> thread::JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let captured = capture();
+    thread::spawn(move || {
+        let _account = captured.enter();
+        f()
+    })
+}
+
+/// The account a buffer belongs to
+pub fn for_buffer(buffer: &Buffer) -> String {
+    buffer
+        .get_localvar("account")
+        .map(Cow::into_owned)
+        .unwrap_or_else(|| DEFAULT_ACCOUNT.to_owned())
+}
+
+/// Prefix a buffer name with the current account, unless it is the default account
+pub fn namespaced(name: String) -> String {
+    let account = current();
+    if account == DEFAULT_ACCOUNT {
+        name
+    } else {
+        format!("{}.{}", account, name)
+    }
+}
@@ -1,40 +1,105 @@
-use super::event_handler::Handler;
+use super::{
+    account,
+    connection::{self, ConnectionState},
+    event_handler::Handler,
+    session::Session,
+};
 use crate::Discord;
-use serenity::{client::bridge::gateway::ShardManager, model::gateway::Ready, prelude::*};
+use serenity::{
+    client::{bridge::gateway::ShardManager, ClientError},
+    gateway::GatewayError,
+    model::gateway::Ready,
+    prelude::*,
+};
 use std::{
-    sync::{mpsc, Arc},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
     thread,
 };
 
 pub struct DiscordClient {
     shard_manager: Arc<Mutex<ShardManager>>,
+    shutting_down: Arc<AtomicBool>,
 }
 
 impl DiscordClient {
     pub fn start(
         weecord: &Discord,
+        session: &Arc<Session>,
         token: &str,
     ) -> Result<(DiscordClient, mpsc::Receiver<Ready>), serenity::Error> {
         let (tx, rx) = mpsc::channel();
-        let handler = Handler::new(weecord, Arc::new(Mutex::new(tx)));
+        let handler = Handler::new(weecord, Arc::clone(session), Arc::new(Mutex::new(tx)));
 
         let mut client = Client::new(token, handler)?;
 
         let shard_manager = client.shard_manager.clone();
-        thread::spawn(move || {
-            if let Err(e) = client.start_shards(1) {
-                crate::on_main(move |weecord| {
-                    weecord.print(&format!(
-                        "discord: An error occurred connecting to discord: {}",
-                        e
-                    ));
-                });
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let shutting_down_clone = Arc::clone(&shutting_down);
+        connection::set_state(ConnectionState::Connecting);
+        account::spawn(move || {
+            let shutting_down = shutting_down_clone;
+            let mut attempt = 0;
+            loop {
+                let result = client.start_shards(1);
+                if shutting_down.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                let e = match result {
+                    Ok(()) => {
+                        connection::set_state(ConnectionState::Disconnected);
+                        break;
+                    },
+                    Err(e) => e,
+                };
+
+                if is_fatal(&e) {
+                    connection::set_state(ConnectionState::Failed(e.to_string()));
+                    break;
+                }
+
+                // A connection that was up for a while starts counting attempts again
+                if connection::state() == ConnectionState::Ready {
+                    attempt = 0;
+                }
+                attempt += 1;
+                if attempt > connection::MAX_RECONNECT_ATTEMPTS {
+                    connection::set_state(ConnectionState::Failed(e.to_string()));
+                    break;
+                }
+
+                let delay = connection::backoff(attempt);
+                connection::set_state(ConnectionState::Reconnecting { attempt, delay });
+                thread::sleep(delay);
+                if shutting_down.load(Ordering::SeqCst) {
+                    break;
+                }
             }
         });
-        Ok((DiscordClient { shard_manager }, rx))
+        Ok((
+            DiscordClient {
+                shard_manager,
+                shutting_down,
+            },
+            rx,
+        ))
     }
 
     pub fn shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
         self.shard_manager.lock().shutdown_all();
+        connection::set_state(ConnectionState::Disconnected);
+    }
+}
+
+/// Errors that retrying will not fix
+fn is_fatal(e: &serenity::Error) -> bool {
+    match e {
+        serenity::Error::Gateway(GatewayError::InvalidAuthentication)
+        | serenity::Error::Client(ClientError::InvalidToken) => true,
+        _ => false,
     }
 }
@@ -0,0 +1,119 @@
+//! Tracks the state of the gateway connection for display in the `discord_status` bar item
+use super::account;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    time::Duration,
+};
+
+/// After this many failed attempts in a row reconnecting is given up
+pub const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    Disconnected,
+    Connecting,
+    Ready,
+    Resuming,
+    Reconnecting { attempt: u32, delay: Duration },
+    Failed(String),
+}
+
+impl ConnectionState {
+    /// The weechat color the state is shown in
+    pub fn color(&self) -> &'static str {
+        match self {
+            ConnectionState::Ready => "green",
+            ConnectionState::Connecting
+            | ConnectionState::Resuming
+            | ConnectionState::Reconnecting { .. } => "yellow",
+            ConnectionState::Disconnected | ConnectionState::Failed(_) => "red",
+        }
+    }
+
+    /// A short description for the bar item
+    pub fn short_name(&self) -> String {
+        match self {
+            ConnectionState::Disconnected => "disconnected".to_owned(),
+            ConnectionState::Connecting => "connecting".to_owned(),
+            ConnectionState::Ready => "connected".to_owned(),
+            ConnectionState::Resuming => "resuming".to_owned(),
+            ConnectionState::Reconnecting { attempt, .. } => format!("reconnecting ({})", attempt),
+            ConnectionState::Failed(_) => "failed".to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConnectionState::Disconnected => write!(f, "Disconnected"),
+            ConnectionState::Connecting => write!(f, "Connecting"),
+            ConnectionState::Ready => write!(f, "Connected"),
+            ConnectionState::Resuming => write!(f, "Connection lost, resuming session"),
+            ConnectionState::Reconnecting { attempt, delay } => write!(
+                f,
+                "Connection lost, reconnecting in {}s (attempt {}/{})",
+                delay.as_secs(),
+                attempt,
+                MAX_RECONNECT_ATTEMPTS
+            ),
+            ConnectionState::Failed(reason) => write!(f, "Connection failed: {}", reason),
+        }
+    }
+}
+
+lazy_static! {
+    // Both are tracked per account
+    static ref STATE: Mutex<HashMap<String, ConnectionState>> = Mutex::new(HashMap::new());
+    // Set once the first ready of a connection was received, so later ones are known to be
+    // reconnects
+    static ref HAS_BEEN_READY: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// The connection state of the current account
+pub fn state() -> ConnectionState {
+    STATE
+        .lock()
+        .get(&account::current())
+        .cloned()
+        .unwrap_or(ConnectionState::Disconnected)
+}
+
+/// Change the connection state of the current account, logging the change to the core buffer
+pub fn set_state(state: ConnectionState) {
+    let account = account::current();
+    {
+        let mut states = STATE.lock();
+        if states.get(&account) == Some(&state) {
+            return;
+        }
+        states.insert(account.clone(), state.clone());
+    }
+
+    if state == ConnectionState::Disconnected {
+        HAS_BEEN_READY.lock().remove(&account);
+    }
+
+    crate::on_main(move |weecord| {
+        if account == account::DEFAULT_ACCOUNT {
+            weecord.print(&format!("discord: {}", state));
+        } else {
+            weecord.print(&format!("discord: [{}] {}", account, state));
+        }
+        weecord.update_bar_item("discord_status");
+    });
+}
+
+/// Mark the connection as ready, returning whether this is a reconnect
+pub fn set_ready() -> bool {
+    set_state(ConnectionState::Ready);
+    !HAS_BEEN_READY.lock().insert(account::current())
+}
+
+/// Exponential backoff between reconnect attempts, capped at a minute
+pub fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(2u64.saturating_pow(attempt.min(6)).min(60))
+}
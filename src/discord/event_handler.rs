@@ -1,9 +1,17 @@
 use crate::{
-    buffers, discord, on_main, on_main_blocking, utils, weechat_utils::MessageManager, Discord,
+    buffers, discord,
+    discord::{
+        account,
+        connection::{self, ConnectionState},
+        folders, member_list, presence,
+        relationships::{self, RelationshipKind},
+        session::{Session, TypingEntry},
+    },
+    on_main, utils, Discord,
 };
-use lazy_static::lazy_static;
 use serenity::{
-    cache::CacheRwLock,
+    client::bridge::gateway::event::ShardStageUpdateEvent,
+    gateway::ConnectionStage,
     model::{gateway::Ready, prelude::*},
     prelude::*,
 };
@@ -11,70 +19,38 @@ use std::{
     collections::HashMap,
     sync::{mpsc::Sender, Arc},
     thread,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::Duration,
 };
+use weechat::ConfigOption;
 
 const MAX_TYPING_EVENTS: usize = 50;
 
-#[derive(Debug, PartialEq, Eq, Ord)]
-pub struct TypingEntry {
-    pub channel_id: ChannelId,
-    pub guild_id: Option<GuildId>,
-    pub user: UserId,
-    pub user_name: String,
-    pub time: u64,
-}
-
-impl PartialOrd for TypingEntry {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.time.partial_cmp(&other.time)
-    }
-}
-
-pub struct TypingTracker {
-    pub entries: Vec<TypingEntry>,
-}
-
-impl TypingTracker {
-    /// Remove any expired entries
-    pub fn sweep(&mut self) {
-        let now = SystemTime::now();
-        let timestamp_now = now
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs() as u64;
-
-        // If the entry is more than 10 seconds old, remove it
-        // TODO: Use binary heap or other structure for better performance?
-        self.entries.retain(|e| timestamp_now - e.time < 10)
-    }
-}
-
-lazy_static! {
-    pub static ref TYPING_EVENTS: Arc<Mutex<TypingTracker>> = Arc::new(Mutex::new(TypingTracker {
-        entries: Vec::new(),
-    }));
-}
-
 pub struct Handler {
+    session: Arc<Session>,
     sender: Arc<Mutex<Sender<Ready>>>,
-    watched_channels: Vec<utils::GuildOrChannel>,
+    watched_channels: Arc<Vec<utils::GuildOrChannel>>,
 }
 
 impl Handler {
-    pub fn new(weecord: &Discord, sender: Arc<Mutex<Sender<Ready>>>) -> Handler {
+    pub fn new(
+        weecord: &Discord,
+        session: Arc<Session>,
+        sender: Arc<Mutex<Sender<Ready>>>,
+    ) -> Handler {
         let watched_channels = weecord.config.watched_channels();
 
         Handler {
+            session,
             sender,
-            watched_channels,
+            watched_channels: Arc::new(watched_channels),
         }
     }
 }
 
 impl EventHandler for Handler {
-    fn channel_create(&self, _ctx: Context, channel: Arc<RwLock<GuildChannel>>) {
-        let channel = channel.read();
+    fn channel_create(&self, ctx: Context, channel: Arc<RwLock<GuildChannel>>) {
+        let _account = account::enter_session(&self.session);
+        let channel = channel.read().clone();
         print_guild_status_message(
             channel.guild_id,
             &format!(
@@ -83,73 +59,253 @@ impl EventHandler for Handler {
                 channel.name()
             ),
         );
+        buffers::refresh_channel(&ctx, &channel);
+        buffers::reorder_channel_buffers(&ctx, channel.guild_id);
     }
 
     fn channel_delete(&self, _ctx: Context, channel: Arc<RwLock<GuildChannel>>) {
+        let _account = account::enter_session(&self.session);
         let channel = channel.read();
         print_guild_status_message(
             channel.guild_id,
             &format!("Channel `{}` deleted", channel.name()),
         );
+        buffers::close_channel_buffer(
+            channel.guild_id,
+            channel.id,
+            format!("Closing the buffer of #{}", channel.name),
+        );
     }
 
     fn channel_pins_update(&self, _ctx: Context, pin: ChannelPinsUpdateEvent) {
+        let _account = account::enter_session(&self.session);
         buffers::load_pin_buffer_history_for_id(pin.channel_id);
+        buffers::refresh_pin_count(pin.channel_id);
     }
 
     fn channel_update(&self, ctx: Context, old: Option<Channel>, new: Channel) {
+        let _account = account::enter_session(&self.session);
         // TODO: Notify more events?
         // * Groups: user learve/join
-        // * guild channel: ?
         match new {
             Channel::Category(new) => {
+                let new = new.read().clone();
+                let guild_id = new
+                    .id
+                    .to_channel_cached(&ctx)
+                    .and_then(Channel::guild)
+                    .map(|ch| ch.read().guild_id);
+                let guild_id = match guild_id {
+                    Some(guild_id) => guild_id,
+                    None => return,
+                };
+
                 // TODO: old doesn't ever seem to be available
                 if let Some(old) = old.and_then(Channel::category) {
-                    let new = new.read();
                     let old = old.read();
-
-                    let guild_id = new
-                        .id
-                        .to_channel_cached(&ctx)
-                        .and_then(Channel::guild)
-                        .map(|ch| ch.read().guild_id);
-
-                    if let Some(guild_id) = guild_id {
-                        if new.name != old.name {
-                            print_guild_status_message(
-                                guild_id,
-                                &format!("Category `{}` renamed to `{}`", old.name, new.name),
-                            );
-                        }
+                    if new.name != old.name {
+                        print_guild_status_message(
+                            guild_id,
+                            &format!("Category `{}` renamed to `{}`", old.name, new.name),
+                        );
                     }
                 }
+
+                // The category name, position and synced permissions of its channels may have
+                // changed
+                let children: Vec<_> = match guild_id.to_guild_cached(&ctx) {
+                    Some(guild) => guild
+                        .read()
+                        .channels
+                        .values()
+                        .map(|channel| channel.read().clone())
+                        .filter(|channel| channel.category_id == Some(new.id))
+                        .collect(),
+                    None => return,
+                };
+                buffers::refresh_channels(&ctx, guild_id, children);
+                buffers::reorder_channel_buffers(&ctx, guild_id);
             },
             Channel::Guild(new) => {
+                let new = new.read().clone();
                 if let Some(old) = old.and_then(Channel::guild) {
-                    let new = new.read();
                     let old = old.read();
 
                     if new.name != old.name {
                         print_guild_status_message(
                             new.guild_id,
-                            &format!("Category `{}` renamed to `{}`", old.name, new.name),
+                            &format!("Channel `{}` renamed to `{}`", old.name, new.name),
                         );
                     }
                 }
+
+                buffers::refresh_channel(&ctx, &new);
+                buffers::reorder_channel_buffers(&ctx, new.guild_id);
             },
             _ => {},
         }
     }
 
+    fn guild_create(&self, ctx: Context, guild: Guild, is_new: bool) {
+        let _account = account::enter_session(&self.session);
+        // Guilds are created for every guild when connecting, only handle ones joined later
+        if !is_new {
+            return;
+        }
+
+        crate::plugin_print(&format!("Joined guild {}", guild.name));
+        let channels: Vec<_> = guild
+            .channels
+            .values()
+            .map(|channel| channel.read().clone())
+            .collect();
+        buffers::refresh_channels(&ctx, guild.id, channels);
+        buffers::reorder_channel_buffers(&ctx, guild.id);
+    }
+
+    fn guild_delete(
+        &self,
+        _ctx: Context,
+        incomplete: PartialGuild,
+        full: Option<Arc<RwLock<Guild>>>,
+    ) {
+        let _account = account::enter_session(&self.session);
+        let name = full
+            .map(|guild| guild.read().name.clone())
+            .unwrap_or(incomplete.name);
+        crate::plugin_print(&format!("Removed from guild {}, closing its buffers", name));
+        buffers::close_guild_buffers(incomplete.id);
+    }
+
+    fn guild_unavailable(&self, _ctx: Context, guild_id: GuildId) {
+        let _account = account::enter_session(&self.session);
+        // Outages are temporary, keep the buffers around until the guild is available again
+        print_guild_status_message(guild_id, "Guild is unavailable due to an outage");
+    }
+
+    fn guild_member_addition(&self, _ctx: Context, guild_id: GuildId, new_member: Member) {
+        let _account = account::enter_session(&self.session);
+        let name = new_member.user.read().tag();
+        let msg = format!("{} joined the guild", name);
+        on_main(move |weecord| {
+            if weecord.config.member_events.value() {
+                print_guild_status_message(guild_id, &msg);
+            }
+        });
+    }
+
+    fn guild_member_removal(
+        &self,
+        _ctx: Context,
+        guild_id: GuildId,
+        user: User,
+        _member_data_if_available: Option<Member>,
+    ) {
+        let _account = account::enter_session(&self.session);
+        let msg = format!("{} left the guild", user.tag());
+        on_main(move |weecord| {
+            if weecord.config.member_events.value() {
+                print_guild_status_message(guild_id, &msg);
+            }
+        });
+    }
+
+    fn guild_ban_addition(&self, _ctx: Context, guild_id: GuildId, banned_user: User) {
+        let _account = account::enter_session(&self.session);
+        let msg = format!("{} was banned", banned_user.tag());
+        on_main(move |weecord| {
+            if weecord.config.member_events.value() {
+                print_guild_status_message(guild_id, &msg);
+            }
+        });
+    }
+
+    fn guild_ban_removal(&self, _ctx: Context, guild_id: GuildId, unbanned_user: User) {
+        let _account = account::enter_session(&self.session);
+        let msg = format!("{} was unbanned", unbanned_user.tag());
+        on_main(move |weecord| {
+            if weecord.config.member_events.value() {
+                print_guild_status_message(guild_id, &msg);
+            }
+        });
+    }
+
+    fn guild_update(&self, _ctx: Context, old: Option<Arc<RwLock<Guild>>>, new: PartialGuild) {
+        let _account = account::enter_session(&self.session);
+        let renamed = old.map(|old| old.read().name != new.name).unwrap_or(true);
+        if renamed {
+            buffers::rename_guild(new.id, new.name);
+        }
+    }
+
     fn guild_member_update(&self, ctx: Context, old: Option<Member>, new: Member) {
-        thread::spawn(move || {
-            buffers::update_member_nick(&old, &new);
-            if ctx.cache.read().user.id == new.user_id() {
+        let _account = account::enter_session(&self.session);
+        account::spawn(move || {
+            let is_current_user = ctx.cache.read().user.id == new.user_id();
+            let guild_id = new.guild_id;
+            let roles_changed = old
+                .as_ref()
+                .map(|old| old.roles != new.roles)
+                .unwrap_or(true);
+            if let Some(old) = &old {
+                print_role_changes(&ctx, old, &new);
+            }
+            buffers::update_member(old, new);
+            if is_current_user {
                 buffers::update_nick();
+                // Roles decide which channels can be accessed
+                if roles_changed {
+                    buffers::refresh_guild_channels(&ctx, guild_id);
+                }
             }
         });
     }
 
+    fn guild_role_create(&self, _ctx: Context, guild_id: GuildId, new: Role) {
+        let _account = account::enter_session(&self.session);
+        buffers::refresh_role_members(guild_id, new.id, None);
+    }
+
+    fn guild_role_delete(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        removed_role_id: RoleId,
+        removed_role_data_if_available: Option<Role>,
+    ) {
+        let _account = account::enter_session(&self.session);
+        buffers::refresh_role_members(guild_id, removed_role_id, removed_role_data_if_available);
+        // Losing a role can revoke access to channels
+        buffers::refresh_guild_channels(&ctx, guild_id);
+    }
+
+    fn guild_role_update(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        old_data_if_available: Option<Role>,
+        new: Role,
+    ) {
+        let _account = account::enter_session(&self.session);
+        buffers::refresh_role_members(guild_id, new.id, old_data_if_available);
+
+        // The permissions of one of our roles changing can grant or revoke access to channels
+        let has_role = guild_id
+            .to_guild_cached(&ctx)
+            .and_then(|guild| {
+                let current_user = ctx.cache.read().user.id;
+                guild
+                    .read()
+                    .members
+                    .get(&current_user)
+                    .map(|member| member.roles.contains(&new.id))
+            })
+            .unwrap_or(false);
+        if has_role || new.id.0 == guild_id.0 {
+            buffers::refresh_guild_channels(&ctx, guild_id);
+        }
+    }
+
     fn guild_members_chunk(
         &self,
         ctx: Context,
@@ -157,6 +313,7 @@ impl EventHandler for Handler {
         _offline_members: HashMap<UserId, Member>,
         nonce: Option<String>,
     ) {
+        let _account = account::enter_session(&self.session);
         on_main(move |weecord| {
             if let Some(channel_id) = nonce {
                 if let Ok(channel_id) = channel_id.parse::<u64>().map(|id| ChannelId(id)) {
@@ -172,85 +329,21 @@ impl EventHandler for Handler {
     }
 
     fn message(&self, ctx: Context, msg: Message) {
-        let string_channel = utils::buffer_id_for_channel(msg.guild_id, msg.channel_id);
-        let () = on_main_blocking(move |weecord| {
-            if let Some(buffer) = weecord.buffer_manager.get_buffer(&string_channel) {
-                print_message(&ctx.cache, &msg, &buffer);
-            } else {
-                match msg.channel_id.to_channel(&ctx) {
-                    chan @ Ok(Channel::Private(_)) => {
-                        if let Some(buffer) = weecord.buffer_manager.get_buffer(&string_channel) {
-                            print_message(&ctx.cache, &msg, &buffer);
-                        } else {
-                            buffers::create_buffer_from_dm(
-                                &ctx.cache,
-                                &weecord,
-                                chan.unwrap(),
-                                &ctx.cache.read().user.name,
-                                false,
-                            );
-                        }
-                    },
-                    chan @ Ok(Channel::Group(_)) => {
-                        if let Some(buffer) = weecord.buffer_manager.get_buffer(&string_channel) {
-                            print_message(&ctx.cache, &msg, &buffer);
-                        } else {
-                            buffers::create_buffer_from_group(
-                                &ctx.cache,
-                                &weecord,
-                                chan.unwrap(),
-                                &ctx.cache.read().user.name,
-                            );
-                        }
-                    },
-                    Ok(Channel::Guild(channel)) => {
-                        // Check that the channel is on the watch list
-                        let channel = channel.read();
-
-                        for watched in &self.watched_channels {
-                            use utils::GuildOrChannel::*;
-                            let add = match watched {
-                                Channel(_, channel_id) => *channel_id == channel.id,
-                                Guild(guild_id) => *guild_id == channel.guild_id,
-                            };
-                            if add {
-                                let guild = match channel.guild_id.to_guild_cached(&ctx.cache) {
-                                    Some(guild) => guild,
-                                    None => return,
-                                };
-
-                                let current_user = ctx.cache.read().user.clone();
-                                let guild = guild.read();
-
-                                // TODO: Colors?
-                                let nick = if let Ok(current_member) =
-                                    guild.id.member(&ctx, current_user.id)
-                                {
-                                    format!("@{}", current_member.display_name())
-                                } else {
-                                    format!("@{}", current_user.name)
-                                };
-
-                                buffers::create_guild_buffer(guild.id, &guild.name);
-                                // TODO: Muting
-                                buffers::create_buffer_from_channel(
-                                    &ctx.cache,
-                                    &guild.name,
-                                    &channel,
-                                    &nick,
-                                    false,
-                                );
-                                break;
-                            }
-                        }
-                    },
-                    _ => {},
-                }
-            }
-        });
+        let _account = account::enter_session(&self.session);
+        let buffer_name = utils::buffer_id_for_channel(msg.guild_id, msg.channel_id);
+        // Messages are printed in batches, so a busy guild costs one job per batch instead of one
+        // per message and the event thread never waits for weechat
+        if self.session.queue_message(buffer_name, msg) {
+            let session = Arc::clone(&self.session);
+            let watched_channels = Arc::clone(&self.watched_channels);
+            on_main(move |weecord| {
+                print_pending_messages(weecord, ctx, &session, watched_channels)
+            });
+        }
     }
 
     fn message_delete(&self, ctx: Context, channel_id: ChannelId, deleted_message_id: MessageId) {
+        let _account = account::enter_session(&self.session);
         delete_message(&ctx, channel_id, deleted_message_id)
     }
 
@@ -260,6 +353,7 @@ impl EventHandler for Handler {
         channel_id: ChannelId,
         deleted_messages_ids: Vec<MessageId>,
     ) {
+        let _account = account::enter_session(&self.session);
         for message_id in deleted_messages_ids {
             delete_message(&ctx, channel_id, message_id)
         }
@@ -272,6 +366,7 @@ impl EventHandler for Handler {
         _new: Option<Message>,
         event: MessageUpdateEvent,
     ) {
+        let _account = account::enter_session(&self.session);
         let (guild_id, channel_id, message_id) = match ctx.cache.read().channel(&event.channel_id) {
             Some(Channel::Guild(channel)) => {
                 let channel = channel.read();
@@ -284,9 +379,9 @@ impl EventHandler for Handler {
 
         let buffer_name = utils::buffer_id_for_channel(guild_id, channel_id);
 
-        thread::spawn(move || {
+        account::spawn(move || {
             on_main(move |weecord| {
-                let ctx = match crate::discord::get_ctx() {
+                let ctx = &match crate::discord::get_ctx() {
                     Some(ctx) => ctx,
                     _ => return,
                 };
@@ -307,14 +402,36 @@ impl EventHandler for Handler {
     }
 
     fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        let _account = account::enter_session(&self.session);
         reaction_update(ctx, reaction)
     }
 
     fn reaction_remove(&self, ctx: Context, reaction: Reaction) {
+        let _account = account::enter_session(&self.session);
         reaction_update(ctx, reaction)
     }
 
+    fn presence_update(&self, _ctx: Context, event: PresenceUpdateEvent) {
+        let _account = account::enter_session(&self.session);
+        presence::presence_changed(&event.presence);
+        buffers::update_presence(event.guild_id, event.presence.user_id);
+        if relationships::kind(event.presence.user_id) == Some(RelationshipKind::Friend) {
+            on_main(crate::friends::refresh_friends_buffer);
+        }
+    }
+
+    fn voice_state_update(&self, _ctx: Context, guild_id: Option<GuildId>, state: VoiceState) {
+        let _account = account::enter_session(&self.session);
+        if let Some(guild_id) = guild_id {
+            crate::voice::handle_voice_state_update(guild_id, state);
+        }
+    }
+
     fn ready(&self, ctx: Context, ready: Ready) {
+        let _account = account::enter_session(&self.session);
+        // Subscriptions do not carry over to a new session
+        member_list::reset();
+
         // Cache seems not to have all fields properly populated
 
         ctx.shard
@@ -340,16 +457,62 @@ impl EventHandler for Handler {
             }
         }
         if let Some(presence) = ctx.cache.read().presences.get(&ready.user.id) {
-            *crate::command::LAST_STATUS.lock() = presence.status;
+            *self.session.status.lock() = presence.status;
         }
-
-        unsafe {
-            crate::discord::CONTEXT = Some(ctx);
+        folders::load(&ready);
+
+        self.session.set_ctx(ctx);
+        if relationships::load(&ready) {
+            on_main(crate::friends::refresh_friends_buffer);
+        } else {
+            account::spawn(|| {
+                let ctx = &match discord::get_ctx() {
+                    Some(ctx) => ctx,
+                    _ => return,
+                };
+                match relationships::fetch(ctx) {
+                    Ok(()) => on_main(crate::friends::refresh_friends_buffer),
+                    Err(e) => crate::plugin_print(&format!("Unable to load friends: {}", e)),
+                }
+            });
+        }
+        if connection::set_ready() {
+            // Messages sent while the session was gone are not replayed
+            account::spawn(buffers::backfill_loaded_buffers);
         }
         let _ = self.sender.lock().send(ready);
     }
 
+    fn resume(&self, _ctx: Context, _: ResumedEvent) {
+        let _account = account::enter_session(&self.session);
+        connection::set_state(ConnectionState::Ready);
+    }
+
+    fn shard_stage_update(&self, _ctx: Context, event: ShardStageUpdateEvent) {
+        let _account = account::enter_session(&self.session);
+        let state = match event.new {
+            ConnectionStage::Resuming => ConnectionState::Resuming,
+            ConnectionStage::Connecting
+            | ConnectionStage::Handshake
+            | ConnectionStage::Identifying => match connection::state() {
+                // A new session is being started after the old one could not be resumed
+                ConnectionState::Ready | ConnectionState::Resuming => {
+                    ConnectionState::Reconnecting {
+                        attempt: 1,
+                        delay: Duration::from_secs(0),
+                    }
+                },
+                ConnectionState::Reconnecting { .. } => return,
+                _ => ConnectionState::Connecting,
+            },
+            // Connected is followed by a ready or resumed event which marks the connection ready
+            _ => return,
+        };
+        connection::set_state(state);
+    }
+
     fn typing_start(&self, ctx: Context, event: TypingStartEvent) {
+        let _account = account::enter_session(&self.session);
         // TODO: Do we want to fetch the user if it isn't cached? (check performance)
         let current_user_id = ctx.cache.read().user.id;
 
@@ -359,7 +522,7 @@ impl EventHandler for Handler {
                 return;
             }
             // TODO: Resolve guild nick names
-            let mut typing_events = TYPING_EVENTS.lock();
+            let mut typing_events = self.session.typing.lock();
             typing_events.entries.push(TypingEntry {
                 channel_id: event.channel_id,
                 guild_id: event.guild_id,
@@ -377,13 +540,15 @@ impl EventHandler for Handler {
                 weechat.update_bar_item("discord_typing");
             });
 
+            let session = Arc::clone(&self.session);
             thread::Builder::new()
                 .name("Typing indicator updater".into())
-                .spawn(|| {
+                .spawn(move || {
                     // Wait a few seconds, then sweep the list and update the bar item
                     thread::sleep(Duration::from_secs(10));
 
-                    let mut typing_events = TYPING_EVENTS.lock();
+                    let _account = account::enter_session(&session);
+                    let mut typing_events = session.typing.lock();
                     typing_events.sweep();
                     crate::on_main(|weechat| {
                         weechat.update_bar_item("discord_typing");
@@ -393,8 +558,41 @@ impl EventHandler for Handler {
         }
     }
 
+    fn unknown(&self, _ctx: Context, name: String, raw: serde_json::Value) {
+        let _account = account::enter_session(&self.session);
+        let event = match json::parse(&raw.to_string()) {
+            Ok(event) => event,
+            Err(_) => return,
+        };
+        match name.as_str() {
+            "GUILD_MEMBER_LIST_UPDATE" => member_list::handle_update(&event),
+            "RELATIONSHIP_ADD" => {
+                relationships::handle_add(&event);
+                if event["type"].as_u64() == Some(3) {
+                    if let Some(name) = event["user"]["username"].as_str() {
+                        crate::plugin_print(&format!("{} sent you a friend request", name));
+                    }
+                }
+                on_main(crate::friends::refresh_friends_buffer);
+            },
+            "USER_SETTINGS_UPDATE" => {
+                if folders::handle_settings_update(&event) {
+                    if let Some(ctx) = discord::get_ctx() {
+                        buffers::refresh_folder_localvars(&ctx);
+                    }
+                }
+            },
+            "RELATIONSHIP_REMOVE" => {
+                relationships::handle_remove(&event);
+                on_main(crate::friends::refresh_friends_buffer);
+            },
+            _ => {},
+        }
+    }
+
     fn user_update(&self, _ctx: Context, _old: CurrentUser, _new: CurrentUser) {
-        thread::spawn(|| {
+        let _account = account::enter_session(&self.session);
+        account::spawn(|| {
             // TODO: Update nicklist (and/or just rework all nick stuff)
             buffers::update_nick();
         });
@@ -408,7 +606,7 @@ fn delete_message(ctx: &Context, channel_id: ChannelId, deleted_message_id: Mess
 
         on_main(move |weecord| {
             if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
-                let ctx = match discord::get_ctx() {
+                let ctx = &match discord::get_ctx() {
                     Some(ctx) => ctx,
                     _ => return,
                 };
@@ -428,10 +626,10 @@ fn reaction_update(ctx: Context, reaction: Reaction) {
         _ => return,
     };
     let buffer_name = utils::buffer_id_for_channel(guild_id, reaction.channel_id);
-    thread::spawn(move || {
+    account::spawn(move || {
         on_main(move |weecord| {
             if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
-                let ctx = match crate::discord::get_ctx() {
+                let ctx = &match crate::discord::get_ctx() {
                     Some(ctx) => ctx,
                     _ => return,
                 };
@@ -452,10 +650,119 @@ fn reaction_update(ctx: Context, reaction: Reaction) {
     });
 }
 
-fn print_message(cache: &CacheRwLock, msg: &Message, buffer: &MessageManager) {
-    let muted = utils::buffer_is_muted(&buffer);
-    let notify = !msg.is_own(cache) && !muted;
-    buffer.add_message(cache, &msg, notify);
+/// Print every message queued since the last batch
+fn print_pending_messages(
+    weecord: &Discord,
+    ctx: Context,
+    session: &Session,
+    watched_channels: Arc<Vec<utils::GuildOrChannel>>,
+) {
+    for (buffer_name, msgs) in session.take_pending_messages() {
+        match weecord.buffer_manager.get_buffer(&buffer_name) {
+            Some(buffer) => {
+                let muted = utils::buffer_is_muted(&buffer);
+                for msg in &msgs {
+                    let notify = !msg.is_own(&ctx.cache) && !muted;
+                    buffer.add_message(&ctx.cache, msg, notify);
+                }
+            },
+            None => {
+                if let Some(msg) = msgs.first() {
+                    open_buffer_for_message(ctx.clone(), msg, Arc::clone(&watched_channels));
+                }
+            },
+        }
+    }
+}
+
+/// Open the buffer of a channel without one when it receives a message, if it is a private
+/// channel or watched
+///
+/// New buffers load their history, which includes the message
+fn open_buffer_for_message(
+    ctx: Context,
+    msg: &Message,
+    watched_channels: Arc<Vec<utils::GuildOrChannel>>,
+) {
+    let channel_id = msg.channel_id;
+    account::spawn(move || match channel_id.to_channel(&ctx) {
+        Ok(channel @ Channel::Private(_)) => on_main(move |weecord| {
+            let nick = ctx.cache.read().user.name.clone();
+            buffers::create_buffer_from_dm(&ctx.cache, &weecord, channel, &nick, false);
+        }),
+        Ok(channel @ Channel::Group(_)) => on_main(move |weecord| {
+            let nick = ctx.cache.read().user.name.clone();
+            buffers::create_buffer_from_group(&ctx.cache, &weecord, channel, &nick);
+        }),
+        Ok(Channel::Guild(channel)) => {
+            // Check that the channel is on the watch list
+            let channel = channel.read().clone();
+            let watched = watched_channels.iter().any(|watched| {
+                use utils::GuildOrChannel::*;
+                match watched {
+                    Channel(_, channel_id) => *channel_id == channel.id,
+                    Guild(guild_id) => *guild_id == channel.guild_id,
+                }
+            });
+            if !watched {
+                return;
+            }
+
+            let guild = match channel.guild_id.to_guild_cached(&ctx.cache) {
+                Some(guild) => guild,
+                None => return,
+            };
+            let guild_name = guild.read().name.clone();
+
+            let current_user = ctx.cache.read().user.clone();
+            // TODO: Colors?
+            let nick = if let Ok(current_member) = channel.guild_id.member(&ctx, current_user.id) {
+                format!("@{}", current_member.display_name())
+            } else {
+                format!("@{}", current_user.name)
+            };
+
+            on_main(move |_| {
+                buffers::create_guild_buffer(channel.guild_id, &guild_name);
+                // TODO: Muting
+                buffers::create_buffer_from_channel(
+                    &ctx.cache,
+                    &guild_name,
+                    &channel,
+                    &nick,
+                    false,
+                );
+            });
+        },
+        _ => {},
+    });
+}
+
+/// Print a member event to the guild buffer, if enabled
+fn print_role_changes(ctx: &Context, old: &Member, new: &Member) {
+    let role_name = |id: &RoleId| {
+        id.to_role_cached(&ctx.cache)
+            .map(|role| role.name)
+            .unwrap_or_else(|| id.0.to_string())
+    };
+
+    let name = new.user.read().tag();
+    let mut changes = Vec::new();
+    for role in new.roles.iter().filter(|role| !old.roles.contains(role)) {
+        changes.push(format!("{} was given the role {}", name, role_name(role)));
+    }
+    for role in old.roles.iter().filter(|role| !new.roles.contains(role)) {
+        changes.push(format!("{} lost the role {}", name, role_name(role)));
+    }
+
+    let guild_id = new.guild_id;
+    on_main(move |weecord| {
+        if weecord.config.member_events.value() {
+            for msg in changes {
+                print_guild_status_message(guild_id, &msg);
+            }
+        }
+    });
 }
 
 fn print_guild_status_message(guild_id: GuildId, msg: &str) {
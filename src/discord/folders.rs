@@ -0,0 +1,103 @@
+//! Guild folders, which serenity does not parse from the user settings
+use super::account;
+use serenity::{
+    cache::CacheRwLock,
+    model::{gateway::Ready, id::GuildId},
+};
+
+#[derive(Debug, Clone)]
+pub struct GuildFolder {
+    /// Guilds that are not in a folder are sent as a folder without an id
+    pub id: Option<u64>,
+    pub name: Option<String>,
+    pub color: Option<u64>,
+    pub guild_ids: Vec<GuildId>,
+}
+
+impl GuildFolder {
+    fn from_json(folder: &json::JsonValue) -> GuildFolder {
+        GuildFolder {
+            id: folder["id"].as_u64(),
+            name: folder["name"]
+                .as_str()
+                .filter(|name| !name.is_empty())
+                .map(ToOwned::to_owned),
+            color: folder["color"].as_u64(),
+            guild_ids: folder["guild_ids"]
+                .members()
+                .filter_map(|id| id.as_str().and_then(|id| id.parse().ok()))
+                .map(GuildId)
+                .collect(),
+        }
+    }
+
+    pub fn is_folder(&self) -> bool {
+        self.id.is_some()
+    }
+
+    /// The name of the folder, unnamed folders are named after their guilds like in the client
+    pub fn display_name(&self, cache: &CacheRwLock) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+
+        let cache = cache.read();
+        self.guild_ids
+            .iter()
+            .filter_map(|id| cache.guilds.get(id))
+            .map(|guild| guild.read().name.clone())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Read the guild folders from the user settings sent with ready, in the order shown by the
+/// client, and keep them for the session
+pub fn load(ready: &Ready) {
+    // Serenity has no type for folders, so they are read from the serialized settings
+    let folders = serde_json::to_value(&ready.user_settings)
+        .ok()
+        .and_then(|settings| json::parse(&settings.to_string()).ok())
+        .map(|settings| parse(&settings))
+        .unwrap_or_default();
+
+    store(folders);
+}
+
+/// Update the folders from a user settings update, returning whether they were changed
+///
+/// Updates only hold the settings that changed, so most of them do not touch folders
+pub fn handle_settings_update(settings: &json::JsonValue) -> bool {
+    if !settings.has_key("guild_folders") {
+        return false;
+    }
+    store(parse(settings));
+    true
+}
+
+fn parse(settings: &json::JsonValue) -> Vec<GuildFolder> {
+    settings["guild_folders"]
+        .members()
+        .map(GuildFolder::from_json)
+        .collect()
+}
+
+fn store(folders: Vec<GuildFolder>) {
+    if let Some(session) = account::session() {
+        *session.guild_folders.lock() = folders;
+    }
+}
+
+/// The folders of the current account
+pub fn cached() -> Vec<GuildFolder> {
+    account::session()
+        .map(|session| session.guild_folders.lock().clone())
+        .unwrap_or_default()
+}
+
+/// The folder a guild is in, if it is in a real folder
+pub fn folder_for_guild(guild_id: GuildId) -> Option<GuildFolder> {
+    cached()
+        .into_iter()
+        .find(|folder| folder.is_folder() && folder.guild_ids.contains(&guild_id))
+}
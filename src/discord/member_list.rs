@@ -0,0 +1,622 @@
+//! Tracks the lazily loaded member lists sent with `GUILD_MEMBER_LIST_UPDATE`
+//!
+//! Large guilds do not send their members on connect, instead the client subscribes to ranges of
+//! the member list of a channel (opcode 14) and Discord keeps that range up to date with a series
+//! of SYNC, INSERT, UPDATE, DELETE and INVALIDATE operations.
+use super::account;
+use crate::{on_main, utils, weechat_utils::MessageManager, Discord};
+use indexmap::IndexMap;
+use json::JsonValue;
+use serenity::{client::bridge::gateway, model::prelude::*, prelude::Context};
+use std::collections::{HashMap, HashSet};
+use weechat::Weechat;
+
+/// Size of each range requested from Discord, ranges are inclusive
+const RANGE_SIZE: u64 = 100;
+/// Discord only tracks a few ranges per channel, the first range is always kept
+const MAX_RANGES: usize = 3;
+
+/// The member lists of every guild of a session
+pub type MemberLists = HashMap<GuildId, GuildMemberLists>;
+
+#[derive(Default)]
+pub struct GuildMemberLists {
+    /// The ranges requested for each subscribed channel
+    subscriptions: IndexMap<ChannelId, Vec<(u64, u64)>>,
+    /// Lists by id, channels whose members can see the same messages share a list
+    lists: HashMap<String, MemberList>,
+}
+
+#[derive(Default)]
+struct MemberList {
+    channels: HashSet<ChannelId>,
+    items: Vec<ListItem>,
+    member_count: u64,
+}
+
+#[derive(Debug, Clone)]
+enum ListItem {
+    /// A slot that has not been synced yet
+    Placeholder,
+    Group(String),
+    Member(ListMember),
+}
+
+#[derive(Debug, Clone)]
+struct ListMember {
+    name: String,
+    roles: Vec<RoleId>,
+    /// The group the member was in when they were added to the nicklist
+    group: Option<String>,
+}
+
+#[derive(Debug)]
+enum NicklistChange {
+    Clear,
+    Add(ListMember),
+    Remove(ListMember),
+}
+
+impl ListItem {
+    fn parse(item: &JsonValue) -> Option<ListItem> {
+        if item["group"].is_object() {
+            return Some(ListItem::Group(item["group"]["id"].as_str()?.to_owned()));
+        }
+
+        let member = &item["member"];
+        let user = &member["user"];
+        let name = member["nick"]
+            .as_str()
+            .or_else(|| user["username"].as_str())?
+            .to_owned();
+        let roles = member["roles"]
+            .members()
+            .filter_map(|id| id.as_str()?.parse().ok().map(RoleId))
+            .collect();
+
+        Some(ListItem::Member(ListMember {
+            name,
+            roles,
+            group: None,
+        }))
+    }
+}
+
+impl MemberList {
+    /// The id of the group an item belongs to, the closest group item above it
+    fn group_for(&self, index: usize) -> Option<String> {
+        self.items[..index.min(self.items.len())]
+            .iter()
+            .rev()
+            .find_map(|item| match item {
+                ListItem::Group(id) => Some(id.clone()),
+                _ => None,
+            })
+    }
+
+    fn set(&mut self, index: usize, item: ListItem, changes: &mut Vec<NicklistChange>) {
+        if self.items.len() <= index {
+            self.items.resize(index + 1, ListItem::Placeholder);
+        }
+        let old = std::mem::replace(&mut self.items[index], item);
+        if let ListItem::Member(old) = old {
+            changes.push(NicklistChange::Remove(old));
+        }
+        self.added(index, changes);
+    }
+
+    fn insert(&mut self, index: usize, item: ListItem, changes: &mut Vec<NicklistChange>) {
+        let index = index.min(self.items.len());
+        self.items.insert(index, item);
+        self.added(index, changes);
+    }
+
+    fn delete(&mut self, index: usize, changes: &mut Vec<NicklistChange>) {
+        if index >= self.items.len() {
+            return;
+        }
+        if let ListItem::Member(old) = self.items.remove(index) {
+            changes.push(NicklistChange::Remove(old));
+        }
+    }
+
+    fn invalidate(&mut self, start: usize, end: usize, changes: &mut Vec<NicklistChange>) {
+        let end = (end + 1).min(self.items.len());
+        if start >= end {
+            return;
+        }
+        for item in self.items.drain(start..end) {
+            if let ListItem::Member(old) = item {
+                changes.push(NicklistChange::Remove(old));
+            }
+        }
+    }
+
+    /// Record the group of a newly placed member, queueing it to be added to the nicklist
+    fn added(&mut self, index: usize, changes: &mut Vec<NicklistChange>) {
+        let group = self.group_for(index);
+        if let ListItem::Member(member) = &mut self.items[index] {
+            member.group = group;
+            changes.push(NicklistChange::Add(member.clone()));
+        }
+    }
+
+    /// Whether there are members beyond the requested ranges
+    fn has_more(&self, ranges: &[(u64, u64)]) -> bool {
+        let loaded = ranges.iter().map(|&(_, end)| end + 1).max().unwrap_or(0);
+        // Groups are included in the list indices, so this is only an approximation
+        loaded < self.member_count
+    }
+}
+
+/// Subscribe to the member list of a channel, has no effect if it is already subscribed
+pub fn subscribe(ctx: &Context, guild_id: GuildId, channel_id: ChannelId) {
+    let session = match account::session() {
+        Some(session) => session,
+        None => return,
+    };
+    let mut member_lists = session.member_lists.lock();
+    let guild_lists = member_lists.entry(guild_id).or_default();
+    if guild_lists.subscriptions.contains_key(&channel_id) {
+        return;
+    }
+
+    guild_lists
+        .subscriptions
+        .insert(channel_id, vec![(0, RANGE_SIZE - 1)]);
+    guild_lists
+        .lists
+        .entry(list_id(ctx, channel_id))
+        .or_default()
+        .channels
+        .insert(channel_id);
+    send_subscriptions(ctx, guild_id, guild_lists);
+}
+
+/// A scroll of the nicklist, as given to `/bar scroll`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scroll {
+    Up,
+    Down,
+    Top,
+}
+
+impl Scroll {
+    /// Parse a scroll value such as "+100%", "y-10" or "b", horizontal scrolls are ignored
+    pub fn parse(value: &str) -> Option<Scroll> {
+        match value.trim_start_matches('y').chars().next()? {
+            '+' | 'e' => Some(Scroll::Down),
+            '-' => Some(Scroll::Up),
+            'b' => Some(Scroll::Top),
+            _ => None,
+        }
+    }
+}
+
+/// Move the ranges subscribed to for a channel along with its scrolled nicklist
+///
+/// Only a few ranges are kept, so they follow the part of the list in view: scrolling down
+/// requests the next range and drops the one furthest up, scrolling up requests a dropped range
+/// again and drops the one furthest down.
+pub fn scroll(ctx: &Context, guild_id: GuildId, channel_id: ChannelId, scroll: Scroll) {
+    let session = match account::session() {
+        Some(session) => session,
+        None => return,
+    };
+    let mut member_lists = session.member_lists.lock();
+    let guild_lists = match member_lists.get_mut(&guild_id) {
+        Some(guild_lists) => guild_lists,
+        None => return,
+    };
+
+    let has_more = match guild_lists.subscriptions.get(&channel_id) {
+        Some(ranges) => guild_lists
+            .lists
+            .values()
+            .find(|list| list.channels.contains(&channel_id))
+            .map(|list| list.has_more(ranges))
+            .unwrap_or(false),
+        None => return,
+    };
+    let ranges = match guild_lists.subscriptions.get_mut(&channel_id) {
+        Some(ranges) => ranges,
+        None => return,
+    };
+    // The first range past the top of the list that is still subscribed to
+    let first_scrolled = ranges.get(1).map(|&(start, _)| start);
+
+    match scroll {
+        Scroll::Down => {
+            if !has_more {
+                return;
+            }
+            let start = ranges.iter().map(|&(_, end)| end + 1).max().unwrap_or(0);
+            ranges.push((start, start + RANGE_SIZE - 1));
+            if ranges.len() > MAX_RANGES {
+                // Always keep the top of the list
+                ranges.remove(1);
+            }
+        },
+        Scroll::Up => match first_scrolled {
+            Some(start) if start > RANGE_SIZE => {
+                ranges.insert(1, (start - RANGE_SIZE, start - 1));
+                if ranges.len() > MAX_RANGES {
+                    ranges.pop();
+                }
+            },
+            _ => return,
+        },
+        Scroll::Top => match first_scrolled {
+            Some(start) if start > RANGE_SIZE => {
+                ranges.truncate(1);
+                ranges.push((RANGE_SIZE, 2 * RANGE_SIZE - 1));
+            },
+            _ => return,
+        },
+    }
+    send_subscriptions(ctx, guild_id, guild_lists);
+}
+
+/// The id Discord uses for the member list of a channel
+///
+/// Discord does not say which channel a list update is for, but the id is derived from the
+/// overwrites that change who can view the channel, so it can be computed the same way
+fn list_id(ctx: &Context, channel_id: ChannelId) -> String {
+    match channel_id.to_channel_cached(ctx) {
+        Some(Channel::Guild(channel)) => {
+            list_id_for_overwrites(&channel.read().permission_overwrites)
+        },
+        _ => "everyone".to_owned(),
+    }
+}
+
+fn list_id_for_overwrites(overwrites: &[PermissionOverwrite]) -> String {
+    let mut overwrites: Vec<_> = overwrites
+        .iter()
+        .filter_map(|overwrite| {
+            let id = match overwrite.kind {
+                PermissionOverwriteType::Member(id) => id.0,
+                PermissionOverwriteType::Role(id) => id.0,
+                _ => return None,
+            };
+            if overwrite.allow.contains(Permissions::READ_MESSAGES) {
+                Some(format!("allow:{}", id))
+            } else if overwrite.deny.contains(Permissions::READ_MESSAGES) {
+                Some(format!("deny:{}", id))
+            } else {
+                None
+            }
+        })
+        .collect();
+    if overwrites.is_empty() {
+        return "everyone".to_owned();
+    }
+    overwrites.sort();
+    murmur3_32(overwrites.join(",").as_bytes()).to_string()
+}
+
+/// 32 bit MurmurHash3 with a seed of 0, which Discord hashes member list ids with
+fn murmur3_32(data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let scramble = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+    let mut hash: u32 = 0;
+    let chunks = data.chunks_exact(4);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        hash ^= scramble(k);
+        hash = hash
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe654_6b64);
+    }
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .enumerate()
+            .fold(0, |k, (i, &byte)| k | (byte as u32) << (8 * i));
+        hash ^= scramble(k);
+    }
+
+    hash ^= data.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^ (hash >> 16)
+}
+
+/// Forget every subscription, must be called when the gateway session is lost
+pub fn reset() {
+    if let Some(session) = account::session() {
+        session.member_lists.lock().clear();
+    }
+}
+
+fn send_subscriptions(ctx: &Context, guild_id: GuildId, guild_lists: &GuildMemberLists) {
+    let mut channels = JsonValue::new_object();
+    for (channel_id, ranges) in &guild_lists.subscriptions {
+        let ranges: Vec<Vec<u64>> = ranges
+            .iter()
+            .map(|&(start, end)| vec![start, end])
+            .collect();
+        channels[channel_id.0.to_string().as_str()] = ranges.into();
+    }
+
+    let msg = json::object! {
+        "op" => 14,
+        "d" => json::object! {
+            "guild_id" => guild_id.0.to_string(),
+            "typing" => true,
+            "activities" => true,
+            "channels" => channels,
+        }
+    };
+    ctx.shard
+        .websocket_message(gateway::Message::Text(msg.to_string()));
+}
+
+/// Apply a `GUILD_MEMBER_LIST_UPDATE` event and update the nicklists of the affected buffers
+pub fn handle_update(event: &JsonValue) {
+    let guild_id = match event["guild_id"].as_str().and_then(|id| id.parse().ok()) {
+        Some(id) => GuildId(id),
+        None => return,
+    };
+    let list_id = match event["id"].as_str() {
+        Some(id) => id.to_owned(),
+        None => return,
+    };
+
+    let session = match account::session() {
+        Some(session) => session,
+        None => return,
+    };
+    let mut changes = Vec::new();
+    let channels: Vec<_> = {
+        let mut member_lists = session.member_lists.lock();
+        let guild_lists = member_lists.entry(guild_id).or_default();
+
+        // Lists of channels that were not subscribed to are tracked, but not shown anywhere
+        let list = guild_lists.lists.entry(list_id).or_default();
+        list.member_count = event["member_count"].as_u64().unwrap_or(list.member_count);
+
+        for op in event["ops"].members() {
+            record_presences(op);
+            apply_op(list, op, &mut changes);
+        }
+
+        list.channels.iter().cloned().collect()
+    };
+
+    if changes.is_empty() || channels.is_empty() {
+        return;
+    }
+
+    on_main(move |weecord| {
+        for channel_id in channels {
+            let buffer_name = utils::buffer_id_for_channel(Some(guild_id), channel_id);
+            if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                apply_changes(weecord, &buffer, guild_id, &changes);
+            }
+        }
+    });
+}
+
+/// Record the activities of the members of an op, serenity only keeps the first one
+fn record_presences(op: &JsonValue) {
+    for item in op["items"].members().chain(std::iter::once(&op["item"])) {
+        let member = &item["member"];
+        if let Some(user_id) = member["user"]["id"].as_str().and_then(|id| id.parse().ok()) {
+            super::presence::record_activities(UserId(user_id), &member["presence"]["activities"]);
+        }
+    }
+}
+
+fn apply_op(list: &mut MemberList, op: &JsonValue, changes: &mut Vec<NicklistChange>) {
+    let index = op["index"].as_usize();
+    let range = op["range"][0].as_usize().zip(op["range"][1].as_usize());
+
+    match op["op"].as_str() {
+        Some("SYNC") => {
+            let (start, end) = match range {
+                Some(range) => range,
+                None => return,
+            };
+            if start == 0 {
+                // A fresh list replaces whatever was loaded from the cache
+                list.items.clear();
+                changes.push(NicklistChange::Clear);
+            } else {
+                list.invalidate(start, end, changes);
+            }
+            for (offset, item) in op["items"]
+                .members()
+                .filter_map(ListItem::parse)
+                .enumerate()
+            {
+                list.set(start + offset, item, changes);
+            }
+        },
+        Some("INSERT") => {
+            if let (Some(index), Some(item)) = (index, ListItem::parse(&op["item"])) {
+                list.insert(index, item, changes);
+            }
+        },
+        Some("UPDATE") => {
+            if let (Some(index), Some(item)) = (index, ListItem::parse(&op["item"])) {
+                list.set(index, item, changes);
+            }
+        },
+        Some("DELETE") => {
+            if let Some(index) = index {
+                list.delete(index, changes);
+            }
+        },
+        Some("INVALIDATE") => {
+            if let Some((start, end)) = range {
+                list.invalidate(start, end, changes);
+            }
+        },
+        _ => {},
+    }
+}
+
+fn apply_changes(
+    weecord: &Discord,
+    buffer: &MessageManager,
+    guild_id: GuildId,
+    changes: &[NicklistChange],
+) {
+    let ctx = &match super::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+    let guild = guild_id.to_guild_cached(ctx);
+
+    set_member_list_managed(buffer);
+    buffer.enable_nicklist();
+
+    for change in changes {
+        match change {
+            NicklistChange::Clear => buffer.nicklist_remove_all(),
+            NicklistChange::Remove(member) => {
+                if let Some(nick) = buffer.search_nick(&member.name, None) {
+                    nick.remove();
+                }
+            },
+            NicklistChange::Add(member) => {
+                let guild = guild.as_ref().map(|guild| guild.read());
+                let roles = guild.as_ref().map(|guild| &guild.roles);
+
+                let group = member.group.as_ref().map(|id| {
+                    let (name, color) = group_name(id, roles);
+                    match buffer.search_nicklist_group(&name) {
+                        Some(group) => group,
+                        None => buffer.add_group(&name, &color, true, None),
+                    }
+                });
+
+                let color = member_color(weecord, member, roles);
+                buffer.add_nick(
+                    weechat::NickArgs {
+                        name: &member.name,
+                        color: &color,
+                        ..Default::default()
+                    },
+                    group.as_ref(),
+                );
+            },
+        }
+    }
+}
+
+fn group_name(id: &str, roles: Option<&HashMap<RoleId, Role>>) -> (String, String) {
+    match id {
+        "online" => (
+            crate::buffers::ONLINE_GROUP_NAME.to_owned(),
+            "grey".to_owned(),
+        ),
+        "offline" => (
+            crate::buffers::OFFLINE_GROUP_NAME.to_owned(),
+            "grey".to_owned(),
+        ),
+        id => {
+            let role = id
+                .parse()
+                .ok()
+                .and_then(|id| roles.and_then(|roles| roles.get(&RoleId(id))));
+            match role {
+                Some(role) => (
+                    crate::buffers::role_group_name(role),
+                    crate::buffers::role_color(role),
+                ),
+                None => (format!("99997|{}", id), "grey".to_owned()),
+            }
+        },
+    }
+}
+
+fn member_color(
+    weechat: &Weechat,
+    member: &ListMember,
+    roles: Option<&HashMap<RoleId, Role>>,
+) -> String {
+    let highest_colored = roles.and_then(|roles| {
+        member
+            .roles
+            .iter()
+            .filter_map(|id| roles.get(id))
+            .filter(|role| role.colour.0 != 0)
+            .max()
+    });
+
+    match highest_colored {
+        Some(role) => crate::buffers::role_color(role),
+        None => utils::nick_color(weechat, &member.name),
+    }
+}
+
+/// Mark a buffer as having its nicklist driven by the member list, rather than the cache
+fn set_member_list_managed(buffer: &MessageManager) {
+    buffer.set_localvar("member_list", "true");
+}
+
+pub fn is_member_list_managed(buffer: &weechat::Buffer) -> bool {
+    buffer.get_localvar("member_list").is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn murmur3_reference_vectors() {
+        assert_eq!(murmur3_32(b""), 0);
+        assert_eq!(murmur3_32(b"abc"), 3_017_643_002);
+        assert_eq!(murmur3_32(b"hello"), 613_153_351);
+        assert_eq!(
+            murmur3_32(b"The quick brown fox jumps over the lazy dog"),
+            776_992_547
+        );
+    }
+
+    #[test]
+    fn list_id_hashes_sorted_overwrites() {
+        let overwrite = |kind, allow, deny| PermissionOverwrite { allow, deny, kind };
+        let overwrites = [
+            overwrite(
+                PermissionOverwriteType::Role(RoleId(2)),
+                Permissions::empty(),
+                Permissions::READ_MESSAGES,
+            ),
+            overwrite(
+                PermissionOverwriteType::Member(UserId(1)),
+                Permissions::READ_MESSAGES,
+                Permissions::empty(),
+            ),
+            // Overwrites that do not change who can view the channel are not part of the id
+            overwrite(
+                PermissionOverwriteType::Role(RoleId(3)),
+                Permissions::SEND_MESSAGES,
+                Permissions::empty(),
+            ),
+        ];
+        assert_eq!(
+            list_id_for_overwrites(&overwrites),
+            murmur3_32(b"allow:1,deny:2").to_string()
+        );
+        assert_eq!(list_id_for_overwrites(&overwrites[2..]), "everyone");
+        assert_eq!(list_id_for_overwrites(&[]), "everyone");
+    }
+
+    #[test]
+    fn parse_scroll() {
+        assert_eq!(Scroll::parse("+100%"), Some(Scroll::Down));
+        assert_eq!(Scroll::parse("y-10"), Some(Scroll::Up));
+        assert_eq!(Scroll::parse("e"), Some(Scroll::Down));
+        assert_eq!(Scroll::parse("yb"), Some(Scroll::Top));
+        assert_eq!(Scroll::parse("x+10"), None);
+    }
+}
@@ -1,27 +1,29 @@
-use self::client::DiscordClient;
+use self::{client::DiscordClient, session::Session};
 use crate::Discord;
-use lazy_static::lazy_static;
-use serenity::{client::Context, prelude::Mutex};
-use std::{sync::Arc, thread};
+use serenity::client::Context;
+use std::sync::Arc;
 
+pub mod account;
 mod client;
+pub mod connection;
 mod event_handler;
+pub mod folders;
 pub mod formatting;
-
-pub use event_handler::TYPING_EVENTS;
-
-pub static mut CONTEXT: Option<Context> = None;
-
-pub fn get_ctx() -> Option<&'static Context> {
-    unsafe { CONTEXT.as_ref() }
-}
-
-lazy_static! {
-    pub(crate) static ref DISCORD: Arc<Mutex<Option<DiscordClient>>> = Arc::new(Mutex::new(None));
+pub mod member_list;
+pub mod presence;
+pub mod relationships;
+pub mod rest;
+pub mod session;
+
+/// The context of the account the current thread is acting for
+pub fn get_ctx() -> Option<Context> {
+    account::session().and_then(|session| session.ctx())
 }
 
-pub fn init(weecord: &Discord, token: &str, irc_mode: bool) {
-    let (discord_client, events) = match DiscordClient::start(weecord, token) {
+/// Connect an account, the session is entered for the connection
+pub fn init(weecord: &Discord, session: &Arc<Session>, token: &str, irc_mode: bool) {
+    let _account = account::enter_session(session);
+    let (discord_client, events) = match DiscordClient::start(weecord, session, token) {
         Ok(d) => d,
         Err(e) => {
             // Cannot use plugin_print because we haven't finished init
@@ -29,13 +31,14 @@ pub fn init(weecord: &Discord, token: &str, irc_mode: bool) {
                 "discord: An error occurred connecting to discord: {}",
                 e
             ));
+            session.teardown();
             return;
         },
     };
 
-    thread::spawn(move || {
+    account::spawn(move || {
         if let Ok(ready) = events.recv() {
-            crate::plugin_print("Discord connected");
+            crate::on_main(presence::restore);
             if irc_mode {
                 crate::buffers::create_autojoin_buffers(&ready);
             } else {
@@ -44,5 +47,5 @@ pub fn init(weecord: &Discord, token: &str, irc_mode: bool) {
         }
     });
 
-    *DISCORD.lock() = Some(discord_client);
+    session.set_client(discord_client);
 }
@@ -0,0 +1,364 @@
+use super::{account, rest};
+use crate::Discord;
+use chrono::{DateTime, Local, TimeZone, Utc};
+use serenity::{client::bridge::gateway, model::prelude::*, prelude::Context};
+use std::{fmt, thread};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameKind {
+    Playing,
+    Listening,
+    Watching,
+}
+
+impl GameKind {
+    fn num(self) -> u8 {
+        match self {
+            GameKind::Playing => 0,
+            GameKind::Listening => 2,
+            GameKind::Watching => 3,
+        }
+    }
+}
+
+/// A game activity set by the user, persisted as "<kind> <name>"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Game {
+    pub kind: GameKind,
+    pub name: String,
+}
+
+impl Game {
+    /// Parse "[playing|listening|watching] name", an unknown kind is treated as part of the name
+    /// when only a single word is given
+    pub fn parse(input: &str) -> Result<Game, String> {
+        let input = input.trim();
+        let mut split = input.splitn(2, ' ');
+        let first = split.next().unwrap_or_default();
+        let rest = split.next().map(str::trim);
+
+        let rest = match rest {
+            Some(rest) if !rest.is_empty() => rest,
+            _ => {
+                return Ok(Game {
+                    kind: GameKind::Playing,
+                    name: input.to_owned(),
+                })
+            },
+        };
+
+        let kind = match first {
+            "playing" | "play" => GameKind::Playing,
+            "listening" => GameKind::Listening,
+            "watching" | "watch" => GameKind::Watching,
+            _ => return Err(format!("Unknown activity type \"{}\"", first)),
+        };
+
+        Ok(Game {
+            kind,
+            name: rest.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            GameKind::Playing => "playing",
+            GameKind::Listening => "listening",
+            GameKind::Watching => "watching",
+        };
+        write!(f, "{} {}", kind, self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomStatus {
+    pub emoji: Option<String>,
+    pub text: String,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl CustomStatus {
+    /// Parse "[emoji] text [-expires 30m|1h|today]"
+    pub fn parse(input: &str) -> Result<CustomStatus, String> {
+        let mut words: Vec<_> = input.split(' ').filter(|w| !w.is_empty()).collect();
+
+        let mut expires_at = None;
+        if let Some(pos) = words.iter().position(|w| *w == "-expires") {
+            let spec = match words.get(pos + 1) {
+                Some(spec) => *spec,
+                None => return Err("-expires requires a duration or \"today\"".to_owned()),
+            };
+            expires_at = Some(parse_expiry(spec)?);
+            words.remove(pos);
+            words.remove(pos);
+        }
+
+        let emoji = match words.first() {
+            Some(word) if is_emoji(word) => Some(words.remove(0).to_owned()),
+            _ => None,
+        };
+
+        let text = words.join(" ");
+        if emoji.is_none() && text.is_empty() {
+            return Err("customstatus requires an emoji or some text".to_owned());
+        }
+
+        Ok(CustomStatus {
+            emoji,
+            text,
+            expires_at,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at <= Utc::now())
+            .unwrap_or(false)
+    }
+
+    fn to_settings_json(&self) -> json::JsonValue {
+        let mut status = json::object! {
+            "text" => self.text.clone(),
+        };
+        if let Some(emoji) = &self.emoji {
+            status["emoji_name"] = emoji.trim_matches(':').into();
+        }
+        if let Some(expires_at) = self.expires_at {
+            status["expires_at"] = expires_at.to_rfc3339().into();
+        }
+        status
+    }
+
+    fn to_activity_json(&self) -> json::JsonValue {
+        let mut activity = json::object! {
+            "type" => 4,
+            "name" => "Custom Status",
+            "state" => self.text.clone(),
+        };
+        if let Some(emoji) = &self.emoji {
+            activity["emoji"] = json::object! { "name" => emoji.trim_matches(':') };
+        }
+        activity
+    }
+}
+
+impl fmt::Display for CustomStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.emoji {
+            Some(emoji) if self.text.is_empty() => write!(f, "{}", emoji)?,
+            Some(emoji) => write!(f, "{} {}", emoji, self.text)?,
+            None => write!(f, "{}", self.text)?,
+        }
+        if let Some(expires_at) = self.expires_at {
+            write!(
+                f,
+                " (until {})",
+                expires_at.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Either a `:name:` style emoji or a word starting with a non-ascii character
+fn is_emoji(word: &str) -> bool {
+    (word.len() > 2 && word.starts_with(':') && word.ends_with(':'))
+        || word.chars().next().map(|c| !c.is_ascii()).unwrap_or(false)
+}
+
+fn parse_expiry(spec: &str) -> Result<DateTime<Utc>, String> {
+    if spec == "today" {
+        // Midnight can be skipped by a daylight saving change
+        let midnight = Local::today().naive_local().succ().and_hms(0, 0, 0);
+        return Local
+            .from_local_datetime(&midnight)
+            .earliest()
+            .map(|midnight| midnight.with_timezone(&Utc))
+            .ok_or_else(|| "Midnight does not exist today, use a duration instead".to_owned());
+    }
+
+    crate::utils::parse_duration(spec)
+        .and_then(|duration| Utc::now().checked_add_signed(duration))
+        .ok_or_else(|| format!("Invalid expiry \"{}\", expected eg 30m, 1h or today", spec))
+}
+
+fn status_str(status: OnlineStatus) -> &'static str {
+    match status {
+        OnlineStatus::DoNotDisturb => "dnd",
+        OnlineStatus::Idle => "idle",
+        OnlineStatus::Invisible => "invisible",
+        OnlineStatus::Offline => "offline",
+        _ => "online",
+    }
+}
+
+/// Send the full presence to the gateway
+///
+/// serenity only supports a single activity, which would drop the custom status whenever the
+/// game is changed
+pub fn send_presence(
+    ctx: &Context,
+    status: OnlineStatus,
+    game: Option<&Game>,
+    custom_status: Option<&CustomStatus>,
+) {
+    let mut activities = json::JsonValue::new_array();
+    if let Some(custom_status) = custom_status.filter(|c| !c.is_expired()) {
+        let _ = activities.push(custom_status.to_activity_json());
+    }
+    if let Some(game) = game {
+        let _ = activities.push(json::object! {
+            "type" => game.kind.num(),
+            "name" => game.name.clone(),
+        });
+    }
+
+    let msg = json::object! {
+        "op" => 3,
+        "d" => json::object! {
+            "since" => 0,
+            "activities" => activities,
+            "status" => status_str(status),
+            "afk" => false,
+        }
+    };
+    ctx.shard
+        .websocket_message(gateway::Message::Text(msg.to_string()));
+}
+
+/// Store the custom status in the users settings so it is shown on other clients
+pub fn set_custom_status(ctx: &Context, custom_status: Option<&CustomStatus>) -> rest::Result<()> {
+    let custom_status = match custom_status {
+        Some(custom_status) => custom_status.to_settings_json(),
+        None => json::JsonValue::Null,
+    };
+
+    rest::patch(
+        ctx,
+        "/users/@me/settings",
+        json::object! { "custom_status" => custom_status },
+    )
+    .map(|_| ())
+}
+
+/// Parse a status, also accepting the names given by `utils::status_name`
+pub fn parse_status(status: &str) -> Option<OnlineStatus> {
+    match status.to_lowercase().as_str() {
+        "online" => Some(OnlineStatus::Online),
+        "offline" | "invisible" => Some(OnlineStatus::Invisible),
+        "idle" => Some(OnlineStatus::Idle),
+        "dnd" | "do not disturb" => Some(OnlineStatus::DoNotDisturb),
+        _ => None,
+    }
+}
+
+/// Record the activities of a user from a raw presence payload
+pub fn record_activities(user_id: UserId, activities: &json::JsonValue) {
+    let activities = activities
+        .members()
+        .filter_map(|activity| serde_json::from_str::<Activity>(&activity.dump()).ok())
+        .collect();
+    if let Some(session) = account::session() {
+        session.activities.lock().insert(user_id, activities);
+    }
+}
+
+/// Forget the recorded activities of a user when a presence update no longer matches them
+pub fn presence_changed(presence: &Presence) {
+    let session = match account::session() {
+        Some(session) => session,
+        None => return,
+    };
+    let mut activities = session.activities.lock();
+    let matches = match (activities.get(&presence.user_id), &presence.activity) {
+        (Some(recorded), Some(activity)) => recorded
+            .iter()
+            .any(|it| it.kind == activity.kind && it.name == activity.name),
+        (Some(recorded), None) => recorded.is_empty(),
+        (None, _) => return,
+    };
+    if !matches {
+        activities.remove(&presence.user_id);
+    }
+}
+
+/// Every activity of a user, falling back to the single one serenity keeps
+pub fn activities(user_id: UserId, presence: Option<&Presence>) -> Vec<Activity> {
+    let recorded =
+        account::session().and_then(|session| session.activities.lock().get(&user_id).cloned());
+    match recorded {
+        Some(activities) => activities,
+        None => presence
+            .and_then(|presence| presence.activity.clone())
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// The status last set on the current account, used when sending a new game
+pub fn status() -> OnlineStatus {
+    account::session()
+        .map(|session| *session.status.lock())
+        .unwrap_or(OnlineStatus::Online)
+}
+
+pub fn set_status(status: OnlineStatus) {
+    if let Some(session) = account::session() {
+        *session.status.lock() = status;
+    }
+}
+
+/// Stop sending a custom status over the gateway once it expires, Discord only clears the one
+/// stored in the settings
+pub fn clear_on_expiry(custom_status: &CustomStatus) {
+    let expires_at = match custom_status.expires_at {
+        Some(expires_at) => expires_at,
+        None => return,
+    };
+    account::spawn(move || {
+        if let Ok(wait) = (expires_at - Utc::now()).to_std() {
+            thread::sleep(wait);
+        }
+        crate::on_main(move |weecord| {
+            let config = &weecord.config;
+            let account_name = account::current();
+            // Replaced by another custom status in the meantime
+            if config.custom_status_expires(&account_name) != Some(expires_at) {
+                return;
+            }
+            config.set_custom_status(&account_name, None);
+            if let Some(ctx) = super::get_ctx() {
+                send_presence(&ctx, status(), config.game(&account_name).as_ref(), None);
+            }
+        });
+    });
+}
+
+/// Reapply the persisted status, game and custom status, called after connecting
+pub fn restore(weecord: &Discord) {
+    let ctx = &match super::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+
+    // Each account restores its own presence
+    let account_name = account::current();
+    let saved_status = weecord
+        .config
+        .account_status(&account_name)
+        .and_then(|status| parse_status(&status));
+    if let Some(status) = saved_status {
+        set_status(status);
+    }
+
+    let game = weecord.config.game(&account_name);
+    let custom_status = weecord.config.custom_status(&account_name);
+    if saved_status.is_some() || game.is_some() || custom_status.is_some() {
+        send_presence(ctx, status(), game.as_ref(), custom_status.as_ref());
+    }
+    if let Some(custom_status) = &custom_status {
+        clear_on_expiry(custom_status);
+    }
+}
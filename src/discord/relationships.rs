@@ -0,0 +1,206 @@
+//! Friends, friend requests and blocked users, which serenity does not parse from the ready event
+//!
+//! Relationships are read from the ready event and kept up to date with the `RELATIONSHIP_ADD`
+//! and `RELATIONSHIP_REMOVE` events.
+use super::{account, rest};
+use indexmap::IndexMap;
+use json::JsonValue;
+use serenity::{
+    model::{gateway::Ready, id::UserId},
+    prelude::Context,
+};
+
+/// The relationships of a session, by user
+pub type Relationships = IndexMap<UserId, Relationship>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RelationshipKind {
+    Friend,
+    Blocked,
+    IncomingRequest,
+    OutgoingRequest,
+}
+
+impl RelationshipKind {
+    fn from_u64(kind: u64) -> Option<RelationshipKind> {
+        match kind {
+            1 => Some(RelationshipKind::Friend),
+            2 => Some(RelationshipKind::Blocked),
+            3 => Some(RelationshipKind::IncomingRequest),
+            4 => Some(RelationshipKind::OutgoingRequest),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Relationship {
+    pub user_id: UserId,
+    pub name: String,
+    pub discriminator: String,
+    pub kind: RelationshipKind,
+}
+
+impl Relationship {
+    fn from_json(relationship: &JsonValue) -> Option<Relationship> {
+        let user = &relationship["user"];
+        Some(Relationship {
+            user_id: UserId(relationship["id"].as_str()?.parse().ok()?),
+            name: user["username"].as_str()?.to_owned(),
+            discriminator: user["discriminator"].as_str().unwrap_or("0").to_owned(),
+            kind: RelationshipKind::from_u64(relationship["type"].as_u64()?)?,
+        })
+    }
+
+    /// The name of the user, with the discriminator for users that still have one
+    pub fn tag(&self) -> String {
+        if self.discriminator == "0" {
+            self.name.clone()
+        } else {
+            format!("{}#{}", self.name, self.discriminator)
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        let name = name.trim_start_matches('@').to_lowercase();
+        self.user_id.0.to_string() == name
+            || self.name.to_lowercase() == name
+            || self.tag().to_lowercase() == name
+    }
+}
+
+/// Store the relationships sent with the ready event in the current session
+///
+/// Returns false if the event has none, in which case they have to be fetched with `fetch`
+pub fn load(ready: &Ready) -> bool {
+    // Serenity has no type for relationships, so they are read from the serialized event
+    let ready = match serde_json::to_value(ready)
+        .ok()
+        .and_then(|ready| json::parse(&ready.to_string()).ok())
+    {
+        Some(ready) => ready,
+        None => return false,
+    };
+    if !ready.has_key("relationships") {
+        return false;
+    }
+    store(&ready["relationships"]);
+    true
+}
+
+/// Fetch the relationships of the current user and store them in the current session
+pub fn fetch(ctx: &Context) -> rest::Result<()> {
+    store(&rest::get(ctx, "/users/@me/relationships")?);
+    Ok(())
+}
+
+fn store(relationships: &JsonValue) {
+    if let Some(session) = account::session() {
+        *session.relationships.lock() = relationships
+            .members()
+            .filter_map(Relationship::from_json)
+            .map(|relationship| (relationship.user_id, relationship))
+            .collect();
+    }
+}
+
+/// Apply a `RELATIONSHIP_ADD` event, which also changes the kind of existing relationships
+pub fn handle_add(event: &JsonValue) {
+    if let (Some(session), Some(relationship)) =
+        (account::session(), Relationship::from_json(event))
+    {
+        session
+            .relationships
+            .lock()
+            .insert(relationship.user_id, relationship);
+    }
+}
+
+/// Apply a `RELATIONSHIP_REMOVE` event
+pub fn handle_remove(event: &JsonValue) {
+    let user_id = event["id"].as_str().and_then(|id| id.parse().ok());
+    if let (Some(session), Some(user_id)) = (account::session(), user_id) {
+        session.relationships.lock().shift_remove(&UserId(user_id));
+    }
+}
+
+/// The relationships of the current session, sorted by kind and then by name
+pub fn all() -> Vec<Relationship> {
+    let mut relationships: Vec<_> = match account::session() {
+        Some(session) => session.relationships.lock().values().cloned().collect(),
+        None => return Vec::new(),
+    };
+    relationships.sort_by_key(|relationship| (relationship.kind, relationship.name.to_lowercase()));
+    relationships
+}
+
+pub fn with_kind(kind: RelationshipKind) -> Vec<Relationship> {
+    all()
+        .into_iter()
+        .filter(|relationship| relationship.kind == kind)
+        .collect()
+}
+
+/// Find a relationship by user id, name or tag
+pub fn search(name: &str) -> Option<Relationship> {
+    all()
+        .into_iter()
+        .find(|relationship| relationship.matches(name))
+}
+
+/// The relationship with a user, if there is one
+pub fn kind(user_id: UserId) -> Option<RelationshipKind> {
+    account::session().and_then(|session| {
+        session
+            .relationships
+            .lock()
+            .get(&user_id)
+            .map(|relationship| relationship.kind)
+    })
+}
+
+pub fn is_blocked(user_id: UserId) -> bool {
+    kind(user_id) == Some(RelationshipKind::Blocked)
+}
+
+/// Accept an incoming friend request
+pub fn accept(ctx: &Context, user_id: UserId) -> rest::Result<JsonValue> {
+    rest::put(
+        ctx,
+        &format!("/users/@me/relationships/{}", user_id.0),
+        JsonValue::new_object(),
+    )
+}
+
+/// Remove a relationship, which ignores or cancels a request, removes a friend or unblocks a
+/// user
+pub fn remove(ctx: &Context, user_id: UserId) -> rest::Result<JsonValue> {
+    rest::delete(ctx, &format!("/users/@me/relationships/{}", user_id.0))
+}
+
+pub fn block(ctx: &Context, user_id: UserId) -> rest::Result<JsonValue> {
+    rest::put(
+        ctx,
+        &format!("/users/@me/relationships/{}", user_id.0),
+        json::object! { "type" => 2 },
+    )
+}
+
+/// Send a friend request to a username, or to a `name#1234` tag
+pub fn send_request(ctx: &Context, tag: &str) -> rest::Result<JsonValue> {
+    let body = match tag
+        .trim_start_matches('@')
+        .rsplitn(2, '#')
+        .collect::<Vec<_>>()[..]
+    {
+        [discriminator, name] if discriminator.parse::<u64>().is_ok() => json::object! {
+            "username" => name,
+            "discriminator" => discriminator.parse::<u64>().ok(),
+        },
+        _ => json::object! {
+            "username" => tag.trim_start_matches('@'),
+            "discriminator" => JsonValue::Null,
+        },
+    };
+    rest::post(ctx, "/users/@me/relationships", body)
+}
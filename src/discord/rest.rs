@@ -0,0 +1,167 @@
+//! Minimal access to the parts of the Discord REST api that serenity does not expose
+use json::JsonValue;
+use lazy_static::lazy_static;
+use reqwest::{Client, Method, Response, StatusCode};
+use serenity::prelude::Context;
+use std::{fmt, thread, time::Duration};
+
+const API_BASE: &str = "https://discordapp.com/api/v6";
+
+/// Times a rate limited request is retried before giving up
+const MAX_RETRIES: usize = 5;
+/// Longest rate limit waited out, longer ones are returned as errors
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref CLIENT: Client = Client::new();
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The request could not be sent or its response could not be read
+    Request(reqwest::Error),
+    /// Discord refused the request, usually with a message saying why
+    Status {
+        status: StatusCode,
+        message: Option<String>,
+    },
+    /// The response was not json
+    Parse,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Request(e) => write!(f, "{}", e),
+            Error::Status {
+                status,
+                message: Some(message),
+            } => write!(f, "{} ({})", message, status),
+            Error::Status {
+                status,
+                message: None,
+            } => write!(f, "Discord returned {}", status),
+            Error::Parse => write!(f, "Unable to parse Discord response"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Error {
+        Error::Request(e)
+    }
+}
+
+/// Perform an authenticated request against the api, returning the parsed response body
+///
+/// Empty responses (such as `204 No Content`) are returned as `JsonValue::Null`
+pub fn request(
+    ctx: &Context,
+    method: Method,
+    path: &str,
+    body: Option<JsonValue>,
+) -> Result<JsonValue> {
+    request_with_reason(ctx, method, path, body, None)
+}
+
+/// Perform a request with a reason that is shown in the audit log of the guild
+///
+/// Rate limited requests are retried once Discord allows it, so callers sending many requests in
+/// a row do not have to pace them
+pub fn request_with_reason(
+    ctx: &Context,
+    method: Method,
+    path: &str,
+    body: Option<JsonValue>,
+    reason: Option<&str>,
+) -> Result<JsonValue> {
+    let body = body.map(|body| body.dump());
+    let mut retries = 0;
+    loop {
+        let mut request = CLIENT
+            .request(method.clone(), &format!("{}{}", API_BASE, path))
+            .header("Authorization", ctx.http.token.as_str());
+
+        if let Some(reason) = reason.filter(|reason| !reason.is_empty()) {
+            request = request.header("X-Audit-Log-Reason", percent_encode(reason));
+        }
+
+        if let Some(body) = &body {
+            request = request
+                .header("Content-Type", "application/json")
+                .body(body.clone());
+        }
+
+        let mut response = request.send()?;
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS && retries < MAX_RETRIES {
+            let wait = retry_after(&response);
+            if wait <= MAX_RETRY_WAIT {
+                retries += 1;
+                thread::sleep(wait);
+                continue;
+            }
+        }
+
+        let text = response.text()?;
+        if !status.is_success() {
+            let message = json::parse(&text)
+                .ok()
+                .and_then(|error| error["message"].as_str().map(ToOwned::to_owned));
+            return Err(Error::Status { status, message });
+        }
+        if text.is_empty() {
+            return Ok(JsonValue::Null);
+        }
+        return json::parse(&text).map_err(|_| Error::Parse);
+    }
+}
+
+/// How long to wait before retrying a rate limited request
+fn retry_after(response: &Response) -> Duration {
+    response
+        .headers()
+        .get("Retry-After")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(|seconds| Duration::from_millis((seconds * 1000.0) as u64))
+        .unwrap_or_else(|| Duration::from_secs(1))
+}
+
+pub fn get(ctx: &Context, path: &str) -> Result<JsonValue> {
+    request(ctx, Method::GET, path, None)
+}
+
+pub fn post(ctx: &Context, path: &str, body: JsonValue) -> Result<JsonValue> {
+    request(ctx, Method::POST, path, Some(body))
+}
+
+pub fn put(ctx: &Context, path: &str, body: JsonValue) -> Result<JsonValue> {
+    request(ctx, Method::PUT, path, Some(body))
+}
+
+pub fn delete(ctx: &Context, path: &str) -> Result<JsonValue> {
+    request(ctx, Method::DELETE, path, None)
+}
+
+pub fn patch(ctx: &Context, path: &str, body: JsonValue) -> Result<JsonValue> {
+    request(ctx, Method::PATCH, path, Some(body))
+}
+
+/// Headers can only contain ascii, so the audit log reason is percent encoded
+fn percent_encode(input: &str) -> String {
+    input
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            },
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -0,0 +1,179 @@
+//! The state of a connected account
+//!
+//! Sessions are owned by the plugin, threads acting for an account hold a reference to its
+//! session (see `account`), so everything they share is torn down together when the account is
+//! disconnected or the plugin is unloaded.
+use super::{
+    client::DiscordClient, folders::GuildFolder, member_list::MemberLists,
+    relationships::Relationships,
+};
+use indexmap::IndexMap;
+use parking_lot::{Mutex, RwLock};
+use serenity::{model::prelude::*, prelude::Context};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, PartialEq, Eq, Ord)]
+pub struct TypingEntry {
+    pub channel_id: ChannelId,
+    pub guild_id: Option<GuildId>,
+    pub user: UserId,
+    pub user_name: String,
+    pub time: u64,
+}
+
+impl PartialOrd for TypingEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.time.partial_cmp(&other.time)
+    }
+}
+
+#[derive(Default)]
+pub struct TypingTracker {
+    pub entries: Vec<TypingEntry>,
+}
+
+impl TypingTracker {
+    /// Remove any expired entries
+    pub fn sweep(&mut self) {
+        let now = SystemTime::now();
+        let timestamp_now = now
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs() as u64;
+
+        // If the entry is more than 10 seconds old, remove it
+        // TODO: Use binary heap or other structure for better performance?
+        self.entries.retain(|e| timestamp_now - e.time < 10)
+    }
+}
+
+pub struct Session {
+    account: String,
+    client: Mutex<Option<DiscordClient>>,
+    ctx: RwLock<Option<Context>>,
+    closed: AtomicBool,
+    pub typing: Mutex<TypingTracker>,
+    pub member_lists: Mutex<MemberLists>,
+    pub relationships: Mutex<Relationships>,
+    /// The status last set by the user, restored when reconnecting
+    pub status: Mutex<OnlineStatus>,
+    /// Guild folders of the user, in the order shown by the client
+    pub guild_folders: Mutex<Vec<GuildFolder>>,
+    /// The order channel buffers were last sorted in, by guild
+    pub channel_order: Mutex<HashMap<GuildId, Vec<ChannelId>>>,
+    // The cache is updated before events are dispatched, so the previous channel of each user
+    // is tracked here to be able to report who left which channel
+    pub voice_channels: Mutex<HashMap<(GuildId, UserId), ChannelId>>,
+    /// Every activity of users seen in a raw presence, serenity only keeps the first one
+    pub activities: Mutex<HashMap<UserId, Vec<Activity>>>,
+    /// Names of the buffers opened for the account, the buffers themselves are only touched on
+    /// the main thread and are kept by the buffer manager
+    buffers: Mutex<HashSet<String>>,
+    /// Messages received from the gateway that have not been printed yet, by buffer name
+    pending_messages: Mutex<IndexMap<String, Vec<Message>>>,
+}
+
+impl Session {
+    pub fn new(account: &str) -> Session {
+        Session {
+            account: account.to_owned(),
+            client: Mutex::new(None),
+            ctx: RwLock::new(None),
+            closed: AtomicBool::new(false),
+            typing: Mutex::new(TypingTracker::default()),
+            member_lists: Mutex::new(MemberLists::new()),
+            relationships: Mutex::new(Relationships::new()),
+            status: Mutex::new(OnlineStatus::Online),
+            guild_folders: Mutex::new(Vec::new()),
+            channel_order: Mutex::new(HashMap::new()),
+            voice_channels: Mutex::new(HashMap::new()),
+            activities: Mutex::new(HashMap::new()),
+            buffers: Mutex::new(HashSet::new()),
+            pending_messages: Mutex::new(IndexMap::new()),
+        }
+    }
+
+    pub fn account(&self) -> &str {
+        &self.account
+    }
+
+    /// The context of the gateway connection, not set until the first ready
+    pub fn ctx(&self) -> Option<Context> {
+        self.ctx.read().clone()
+    }
+
+    pub fn set_ctx(&self, ctx: Context) {
+        // A late ready must not revive a session that was already torn down
+        if !self.is_closed() {
+            *self.ctx.write() = Some(ctx);
+        }
+    }
+
+    pub fn set_client(&self, client: DiscordClient) {
+        if self.is_closed() {
+            client.shutdown();
+        } else {
+            *self.client.lock() = Some(client);
+        }
+    }
+
+    /// Queue a message to be printed with the next batch, returning whether it started a new
+    /// batch that has to be scheduled
+    pub fn queue_message(&self, buffer_name: String, msg: Message) -> bool {
+        let mut pending = self.pending_messages.lock();
+        let new_batch = pending.is_empty();
+        pending.entry(buffer_name).or_default().push(msg);
+        new_batch
+    }
+
+    /// Take every queued message, grouped by buffer in the order they were first received
+    pub fn take_pending_messages(&self) -> IndexMap<String, Vec<Message>> {
+        std::mem::replace(&mut *self.pending_messages.lock(), IndexMap::new())
+    }
+
+    pub fn add_buffer(&self, name: &str) {
+        self.buffers.lock().insert(name.to_owned());
+    }
+
+    /// Forget a buffer that was closed, along with everything queued for it
+    pub fn remove_buffer(&self, name: &str) {
+        self.buffers.lock().remove(name);
+        self.pending_messages.lock().shift_remove(name);
+    }
+
+    /// Names of the buffers opened for the account
+    pub fn buffer_names(&self) -> Vec<String> {
+        self.buffers.lock().iter().cloned().collect()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Shut down the connection and drop all state, threads still holding the session see it
+    /// as disconnected
+    pub fn teardown(&self) {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        if let Some(client) = self.client.lock().take() {
+            client.shutdown();
+        }
+        *self.ctx.write() = None;
+        self.typing.lock().entries.clear();
+        self.member_lists.lock().clear();
+        self.relationships.lock().clear();
+        *self.status.lock() = OnlineStatus::Online;
+        self.guild_folders.lock().clear();
+        self.channel_order.lock().clear();
+        self.voice_channels.lock().clear();
+        self.activities.lock().clear();
+        self.buffers.lock().clear();
+        self.pending_messages.lock().clear();
+    }
+}
@@ -0,0 +1,384 @@
+//! The list of private channels and management of group DMs
+//!
+//! A DM list buffer lists every DM and group DM, entering the number of a channel opens it.
+use crate::{
+    buffers,
+    discord::{self, account, rest},
+    on_main, plugin_print, utils,
+    utils::BufferExt,
+    Discord,
+};
+use parking_lot::RwLock;
+use serenity::{cache::CacheRwLock, model::prelude::*, prelude::Context};
+use std::sync::Arc;
+use weechat::Weechat;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmSort {
+    /// Most recently active first, like the client
+    Activity,
+    Name,
+}
+
+impl DmSort {
+    pub fn parse(arg: Option<&str>) -> Option<DmSort> {
+        match arg {
+            None | Some("activity") => Some(DmSort::Activity),
+            Some("name") => Some(DmSort::Name),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DmEntry {
+    channel_id: ChannelId,
+    name: String,
+    is_group: bool,
+    last_message_id: Option<MessageId>,
+    unread: bool,
+}
+
+pub fn buffer_id_for_dm_list() -> String {
+    account::namespaced("DMs".to_owned())
+}
+
+fn group_name(group: &Group) -> String {
+    match &group.name {
+        Some(name) if !name.is_empty() => name.clone(),
+        _ => group
+            .recipients
+            .values()
+            .map(|user| user.read().name.clone())
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+fn dm_entries(cache: &CacheRwLock, sort: DmSort) -> Vec<DmEntry> {
+    let cache = cache.read();
+    let unread = |channel_id: ChannelId, last_message_id: Option<MessageId>| {
+        last_message_id.is_some()
+            && cache
+                .read_state
+                .get(&channel_id)
+                .map(|rs| rs.last_message_id)
+                != last_message_id
+    };
+
+    let dms = cache.private_channels.values().map(|channel| {
+        let channel = channel.read();
+        DmEntry {
+            channel_id: channel.id,
+            name: channel.recipient.read().name.clone(),
+            is_group: false,
+            last_message_id: channel.last_message_id,
+            unread: unread(channel.id, channel.last_message_id),
+        }
+    });
+    let groups = cache.groups.values().map(|group| {
+        let group = group.read();
+        DmEntry {
+            channel_id: group.channel_id,
+            name: group_name(&group),
+            is_group: true,
+            last_message_id: group.last_message_id,
+            unread: unread(group.channel_id, group.last_message_id),
+        }
+    });
+
+    let mut entries: Vec<_> = dms.chain(groups).collect();
+    match sort {
+        // Message ids are snowflakes, so newer messages have larger ids
+        DmSort::Activity => entries.sort_by(|a, b| b.last_message_id.cmp(&a.last_message_id)),
+        DmSort::Name => entries.sort_by_key(|entry| entry.name.to_lowercase()),
+    }
+    entries
+}
+
+/// Open a buffer listing every DM and group DM
+pub fn open_dm_list(weecord: &Discord, sort: DmSort) {
+    let ctx = &match discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+    let entries = dm_entries(&ctx.cache, sort);
+
+    let title = match sort {
+        DmSort::Activity => "Direct messages, by last activity",
+        DmSort::Name => "Direct messages, by name",
+    };
+    let buffer = buffers::create_list_buffer(
+        weecord,
+        &buffer_id_for_dm_list(),
+        "dm_list",
+        "DMs",
+        title,
+        &[],
+    );
+
+    buffer.clear();
+    if entries.is_empty() {
+        buffer.print("You have no direct messages");
+    } else {
+        for (i, entry) in entries.iter().enumerate() {
+            buffer.print(&format_entry(weecord, i + 1, entry));
+        }
+        buffer.print("");
+        buffer.print("Enter the number of a channel to open it");
+    }
+
+    buffer.set_list(entries);
+}
+
+fn format_entry(weechat: &Weechat, number: usize, entry: &DmEntry) -> String {
+    let mut line = format!(
+        "{}\t{}",
+        utils::colorize_string(weechat, "chat_delimiters", &format!("[{}]", number)),
+        if entry.is_group {
+            format!("{} (group)", entry.name)
+        } else {
+            utils::format_nick_color(weechat, &entry.name)
+        }
+    );
+    if entry.unread {
+        line.push_str(&format!(
+            " {}",
+            utils::colorize_string(weechat, "yellow", "*")
+        ));
+    }
+    line
+}
+
+/// Open a channel by its number in a DM list buffer
+pub fn open_dm_from_list(weecord: &Discord, list_buffer: &weechat::Buffer, arg: &str) {
+    let entries = weecord
+        .buffer_manager
+        .get_buffer(&list_buffer.get_name())
+        .and_then(|buffer| buffer.with_list(|entries: &mut Vec<DmEntry>| entries.clone()));
+    let entries = match entries {
+        Some(entries) => entries,
+        None => return,
+    };
+    let entry = match arg.parse::<usize>() {
+        Ok(n) if n >= 1 && n <= entries.len() => &entries[n - 1],
+        _ => {
+            list_buffer.print(&format!(
+                "{}\tNo channel {:?}, enter the number of a channel",
+                weecord.get_prefix("error"),
+                arg
+            ));
+            return;
+        },
+    };
+
+    open_private_channel(weecord, entry.channel_id);
+}
+
+/// Open the buffer of a DM or group DM and switch to it
+fn open_private_channel(weecord: &Discord, channel_id: ChannelId) {
+    let ctx = &match discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+    let channel = match channel_id.to_channel_cached(&ctx.cache) {
+        Some(channel) => channel,
+        None => return,
+    };
+    let nick = format!("@{}", ctx.cache.read().user.name);
+    match channel {
+        channel @ Channel::Private(_) => {
+            buffers::create_buffer_from_dm(&ctx.cache, weecord, channel, &nick, true)
+        },
+        channel @ Channel::Group(_) => {
+            buffers::create_buffer_from_group(&ctx.cache, weecord, channel, &nick);
+            if let Some(buffer) = weecord
+                .buffer_manager
+                .get_buffer(&utils::buffer_id_for_channel(None, channel_id))
+            {
+                buffer.switch_to();
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Open a DM with a user, creating the channel if there has never been one
+pub fn open_dm_with_user(user_id: UserId) {
+    account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let channel = match user_id.create_dm_channel(ctx) {
+            Ok(channel) => channel,
+            Err(e) => {
+                plugin_print(&format!("Unable to open DM: {}", e));
+                return;
+            },
+        };
+        let nick = format!("@{}", ctx.cache.read().user.name);
+
+        on_main(move |weecord| {
+            let ctx = &match discord::get_ctx() {
+                Some(ctx) => ctx,
+                _ => return,
+            };
+            buffers::create_buffer_from_dm(
+                &ctx.cache,
+                weecord,
+                Channel::Private(Arc::new(RwLock::new(channel))),
+                &nick,
+                true,
+            );
+        });
+    });
+}
+
+/// The group DM of a buffer, if it is one
+fn group_for_buffer(ctx: &Context, buffer: &weechat::Buffer) -> Option<ChannelId> {
+    buffer
+        .channel_id()
+        .filter(|channel_id| ctx.cache.read().groups.contains_key(channel_id))
+}
+
+/// Resolve user names or ids, reporting any that could not be found
+fn search_users(ctx: &Context, names: &[String]) -> Option<Vec<User>> {
+    let mut users = Vec::new();
+    for name in names {
+        match utils::search_user(ctx, None, name) {
+            Some(user) => users.push(user),
+            None => {
+                plugin_print(&format!("Could not find user {:?}", name));
+                return None;
+            },
+        }
+    }
+    Some(users)
+}
+
+/// Create a group DM with some users and open it
+pub fn create_group(names: Vec<String>) {
+    account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let users = match search_users(ctx, &names) {
+            Some(users) => users,
+            None => return,
+        };
+
+        let recipients: Vec<_> = users.iter().map(|user| user.id.0.to_string()).collect();
+        let channel = match rest::post(
+            ctx,
+            "/users/@me/channels",
+            json::object! { "recipients" => recipients },
+        ) {
+            Ok(channel) => channel,
+            Err(e) => {
+                plugin_print(&format!("Unable to create group: {}", e));
+                return;
+            },
+        };
+        let channel_id = match channel["id"].as_str().and_then(|id| id.parse().ok()) {
+            Some(id) => ChannelId(id),
+            None => return,
+        };
+
+        // The cache is only updated once the channel create event arrives
+        if let Ok(channel) = channel_id.to_channel(ctx) {
+            let nick = format!("@{}", ctx.cache.read().user.name);
+            on_main(move |weecord| {
+                let ctx = &match discord::get_ctx() {
+                    Some(ctx) => ctx,
+                    _ => return,
+                };
+                buffers::create_buffer_from_group(&ctx.cache, weecord, channel, &nick);
+                if let Some(buffer) = weecord
+                    .buffer_manager
+                    .get_buffer(&utils::buffer_id_for_channel(None, channel_id))
+                {
+                    buffer.switch_to();
+                }
+            });
+        }
+    });
+}
+
+#[derive(Debug, Clone)]
+pub enum GroupAction {
+    Add(Vec<String>),
+    Remove(Vec<String>),
+    Rename(String),
+    Leave,
+}
+
+/// Change the group DM of a buffer
+pub fn edit_group(buffer: &weechat::Buffer, action: GroupAction) {
+    let ctx = &match discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+    let channel_id = match group_for_buffer(ctx, buffer) {
+        Some(channel_id) => channel_id,
+        None => {
+            plugin_print("group commands must be run in a group DM buffer");
+            return;
+        },
+    };
+
+    account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+
+        match action {
+            GroupAction::Add(names) | GroupAction::Remove(names) if names.is_empty() => {
+                plugin_print("No users given");
+            },
+            GroupAction::Add(names) => {
+                for user in search_users(ctx, &names).unwrap_or_default() {
+                    let path = format!("/channels/{}/recipients/{}", channel_id.0, user.id.0);
+                    if let Err(e) = rest::put(ctx, &path, json::JsonValue::new_object()) {
+                        plugin_print(&format!("Unable to add {}: {}", user.name, e));
+                    }
+                }
+            },
+            GroupAction::Remove(names) => {
+                for user in search_users(ctx, &names).unwrap_or_default() {
+                    let path = format!("/channels/{}/recipients/{}", channel_id.0, user.id.0);
+                    if let Err(e) = rest::delete(ctx, &path) {
+                        plugin_print(&format!("Unable to remove {}: {}", user.name, e));
+                    }
+                }
+            },
+            GroupAction::Rename(name) => {
+                let path = format!("/channels/{}", channel_id.0);
+                match rest::patch(ctx, &path, json::object! { "name" => name.clone() }) {
+                    Ok(_) => on_main(move |weecord| {
+                        if let Some(buffer) = weecord
+                            .buffer_manager
+                            .get_buffer(&utils::buffer_id_for_channel(None, channel_id))
+                        {
+                            buffer.set_short_name(&name);
+                        }
+                    }),
+                    Err(e) => plugin_print(&format!("Unable to rename group: {}", e)),
+                }
+            },
+            GroupAction::Leave => {
+                let path = format!("/channels/{}", channel_id.0);
+                match rest::delete(ctx, &path) {
+                    Ok(_) => on_main(move |weecord| {
+                        weecord
+                            .buffer_manager
+                            .close_buffer(&utils::buffer_id_for_channel(None, channel_id));
+                    }),
+                    Err(e) => plugin_print(&format!("Unable to leave group: {}", e)),
+                }
+            },
+        }
+    });
+}
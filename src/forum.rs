@@ -0,0 +1,370 @@
+//! Forum channels, whose posts are threads listed through the api as serenity has no forums
+//!
+//! A forum buffer lists the posts of a forum, entering the number of a post opens its thread in
+//! a regular channel buffer.
+use crate::{
+    buffers,
+    discord::{self, account, rest},
+    on_main, plugin_print, utils,
+    utils::BufferExt,
+    Discord,
+};
+use chrono::{Local, TimeZone, Utc};
+use json::JsonValue;
+use serenity::{model::prelude::*, prelude::Context};
+use std::collections::HashMap;
+use weechat::Weechat;
+
+/// Channel type of forums in the api
+const FORUM_CHANNEL_TYPE: u64 = 15;
+/// Most posts listed in a forum buffer
+const MAX_POSTS: u64 = 25;
+/// Milliseconds between the unix epoch and the first second of 2015, the Discord epoch
+const DISCORD_EPOCH: u64 = 1_420_070_400_000;
+
+#[derive(Debug, Clone)]
+pub struct Forum {
+    pub id: ChannelId,
+    pub name: String,
+    pub topic: Option<String>,
+    /// Tag names by id
+    tags: HashMap<String, String>,
+}
+
+impl Forum {
+    fn from_json(channel: &JsonValue) -> Option<Forum> {
+        Some(Forum {
+            id: ChannelId(channel["id"].as_str()?.parse().ok()?),
+            name: channel["name"].as_str()?.to_owned(),
+            topic: channel["topic"]
+                .as_str()
+                .filter(|topic| !topic.is_empty())
+                .map(ToOwned::to_owned),
+            tags: channel["available_tags"]
+                .members()
+                .filter_map(|tag| {
+                    let name = match tag["emoji_name"].as_str() {
+                        Some(emoji) => format!("{} {}", emoji, tag["name"].as_str()?),
+                        None => tag["name"].as_str()?.to_owned(),
+                    };
+                    Some((tag["id"].as_str()?.to_owned(), name))
+                })
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ForumPost {
+    pub id: ChannelId,
+    pub name: String,
+    pub tags: Vec<String>,
+    pub message_count: u64,
+    pub last_message_id: Option<MessageId>,
+    pub archived: bool,
+    pub locked: bool,
+}
+
+impl ForumPost {
+    fn from_json(thread: &JsonValue, forum: &Forum) -> Option<ForumPost> {
+        Some(ForumPost {
+            id: ChannelId(thread["id"].as_str()?.parse().ok()?),
+            name: thread["name"].as_str()?.to_owned(),
+            tags: thread["applied_tags"]
+                .members()
+                .filter_map(|id| id.as_str())
+                .filter_map(|id| forum.tags.get(id).cloned())
+                .collect(),
+            message_count: thread["message_count"].as_u64().unwrap_or(0),
+            last_message_id: thread["last_message_id"]
+                .as_str()
+                .and_then(|id| id.parse().ok())
+                .map(MessageId),
+            archived: thread["thread_metadata"]["archived"]
+                .as_bool()
+                .unwrap_or(false),
+            locked: thread["thread_metadata"]["locked"]
+                .as_bool()
+                .unwrap_or(false),
+        })
+    }
+
+    /// When the last message was sent, read from the snowflake of the message
+    fn last_activity(&self) -> String {
+        // A post without replies was last active when it was created
+        let id = self.last_message_id.map(|id| id.0).unwrap_or(self.id.0);
+        let millis = (id >> 22) + DISCORD_EPOCH;
+        Utc.timestamp_opt((millis / 1000) as i64, 0)
+            .single()
+            .map(|date| {
+                date.with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_default()
+    }
+}
+
+pub fn buffer_id_for_forum(channel_id: ChannelId) -> String {
+    account::namespaced(format!("Forum.{}", channel_id.0))
+}
+
+pub fn is_forum_buffer(buffer: &weechat::Buffer) -> bool {
+    buffer.get_localvar("type").as_deref() == Some("forum")
+}
+
+fn forum_channel_for_buffer(buffer: &weechat::Buffer) -> Option<ChannelId> {
+    buffer
+        .get_localvar("forum_channel")
+        .and_then(|id| id.parse().ok())
+        .map(ChannelId)
+}
+
+/// Fetch the forum channels of a guild that are visible to the current user
+pub fn fetch_forums(ctx: &Context, guild_id: GuildId) -> rest::Result<Vec<Forum>> {
+    let channels = rest::get(ctx, &format!("/guilds/{}/channels", guild_id.0))?;
+    let mut forums: Vec<_> = channels
+        .members()
+        .filter(|channel| channel["type"].as_u64() == Some(FORUM_CHANNEL_TYPE))
+        .filter_map(|channel| {
+            Forum::from_json(channel).map(|forum| (channel["position"].as_i64(), forum))
+        })
+        .collect();
+    forums.sort_by_key(|(position, _)| *position);
+    Ok(forums.into_iter().map(|(_, forum)| forum).collect())
+}
+
+/// Fetch the most recently active posts of a forum, including archived ones
+pub fn fetch_posts(ctx: &Context, forum: &Forum) -> rest::Result<Vec<ForumPost>> {
+    let result = rest::get(
+        ctx,
+        &format!(
+            "/channels/{}/threads/search?archived=true&sort_by=last_message_time&sort_order=desc&limit={}",
+            forum.id.0, MAX_POSTS
+        ),
+    )?;
+    Ok(result["threads"]
+        .members()
+        .filter_map(|thread| ForumPost::from_json(thread, forum))
+        .collect())
+}
+
+/// List the forums of a guild, or open the forum matching `name`
+pub fn open_forum(guild_id: GuildId, name: Option<String>) {
+    account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let forums = match fetch_forums(ctx, guild_id) {
+            Ok(forums) => forums,
+            Err(e) => {
+                plugin_print(&format!("Unable to fetch forums: {}", e));
+                return;
+            },
+        };
+
+        let name = match name {
+            Some(name) => name,
+            None => {
+                if forums.is_empty() {
+                    plugin_print("This guild has no forums");
+                } else {
+                    let names: Vec<_> = forums.iter().map(|forum| forum.name.as_str()).collect();
+                    plugin_print(&format!("Forums: {}", names.join(", ")));
+                }
+                return;
+            },
+        };
+
+        let forum = match forums.into_iter().find(|forum| {
+            parsing::weechat_arg_strip(&forum.name).to_lowercase() == name.to_lowercase()
+                || forum.id.0.to_string() == name
+        }) {
+            Some(forum) => forum,
+            None => {
+                plugin_print(&format!("Could not find forum {:?}", name));
+                return;
+            },
+        };
+
+        load_posts(ctx, guild_id, forum);
+    });
+}
+
+fn load_posts(ctx: &Context, guild_id: GuildId, forum: Forum) {
+    let posts = match fetch_posts(ctx, &forum) {
+        Ok(posts) => posts,
+        Err(e) => {
+            plugin_print(&format!("Unable to fetch posts of {}: {}", forum.name, e));
+            return;
+        },
+    };
+    let guild_name = guild_id
+        .to_guild_cached(&ctx.cache)
+        .map(|guild| guild.read().name.clone())
+        .unwrap_or_default();
+
+    on_main(move |weecord| {
+        create_forum_buffer(weecord, guild_id, &guild_name, &forum, posts);
+    });
+}
+
+/// Open a buffer listing the posts of a forum
+fn create_forum_buffer(
+    weecord: &Discord,
+    guild_id: GuildId,
+    guild_name: &str,
+    forum: &Forum,
+    posts: Vec<ForumPost>,
+) {
+    let title = match &forum.topic {
+        Some(topic) => format!("Forum {} | {}", forum.name, topic),
+        None => format!("Forum {}", forum.name),
+    };
+    let buffer = buffers::create_list_buffer(
+        weecord,
+        &buffer_id_for_forum(forum.id),
+        "forum",
+        &format!("#{}", forum.name),
+        &title,
+        &[
+            ("guildid", guild_id.0.to_string().as_str()),
+            ("guild_name", guild_name),
+            ("server", guild_name),
+            ("forum_channel", forum.id.0.to_string().as_str()),
+            ("forum_name", forum.name.as_str()),
+        ],
+    );
+
+    buffer.clear();
+    if posts.is_empty() {
+        buffer.print("This forum has no posts");
+    } else {
+        for (i, post) in posts.iter().enumerate() {
+            buffer.print(&format_post(weecord, i + 1, post));
+        }
+        buffer.print("");
+        buffer.print("Enter the number of a post to open it");
+    }
+
+    buffer.set_list(posts);
+}
+
+fn format_post(weechat: &Weechat, number: usize, post: &ForumPost) -> String {
+    let mut line = format!(
+        "{}\t{}",
+        utils::colorize_string(weechat, "chat_delimiters", &format!("[{}]", number)),
+        utils::colorize_string(weechat, "bold", &post.name)
+    );
+    if !post.tags.is_empty() {
+        line.push_str(&format!(" [{}]", post.tags.join(", ")));
+    }
+    // The opening message is not a reply
+    let replies = post.message_count.saturating_sub(1);
+    line.push_str(&format!(
+        " | {} {}",
+        replies,
+        if replies == 1 { "reply" } else { "replies" }
+    ));
+    line.push_str(&format!(" | last active {}", post.last_activity()));
+    if post.locked {
+        line.push_str(" (locked)");
+    } else if post.archived {
+        line.push_str(" (archived)");
+    }
+    line
+}
+
+/// Open the thread of a post, by its number in a forum buffer or its id
+pub fn open_post(weecord: &Discord, forum_buffer: &weechat::Buffer, arg: &str) {
+    let posts = weecord
+        .buffer_manager
+        .get_buffer(&forum_buffer.get_name())
+        .and_then(|buffer| buffer.with_list(|posts: &mut Vec<ForumPost>| posts.clone()));
+    let posts = match posts {
+        Some(posts) => posts,
+        None => return,
+    };
+    let post = match arg.parse::<usize>() {
+        Ok(n) if n >= 1 && n <= posts.len() => posts.get(n - 1),
+        _ => posts.iter().find(|post| post.id.0.to_string() == arg),
+    };
+    let post = match post {
+        Some(post) => post,
+        None => {
+            forum_buffer.print(&format!(
+                "{}\tNo post {:?}, enter the number of a post",
+                weecord.get_prefix("error"),
+                arg
+            ));
+            return;
+        },
+    };
+    let guild_id = match forum_buffer.guild_id() {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
+    let guild_name = forum_buffer
+        .get_localvar("guild_name")
+        .map(|name| name.into_owned())
+        .unwrap_or_default();
+    let forum_name = forum_buffer
+        .get_localvar("forum_name")
+        .map(|name| name.into_owned())
+        .unwrap_or_default();
+
+    let buffer = weecord
+        .buffer_manager
+        .get_or_create_buffer(&utils::buffer_id_for_channel(Some(guild_id), post.id));
+    buffer.set_short_name(&format!("#{}/{}", forum_name, post.name));
+    buffer.set_localvar("channelid", &post.id.0.to_string());
+    buffer.set_localvar("guildid", &guild_id.0.to_string());
+    buffer.set_localvar("channel", &post.name);
+    buffer.set_localvar("guild_name", &guild_name);
+    buffer.set_localvar("server", &guild_name);
+    buffer.set_localvar("type", "channel");
+    if let Some(forum_channel) = forum_channel_for_buffer(forum_buffer) {
+        buffer.set_localvar("thread_parent", &forum_channel.0.to_string());
+    }
+    let read_only = post.locked || post.archived;
+    buffer.set_localvar("read_only", &(read_only as u8).to_string());
+    let mut title = if post.tags.is_empty() {
+        post.name.clone()
+    } else {
+        format!("{} | {}", post.name, post.tags.join(", "))
+    };
+    if read_only {
+        title += " (read-only)";
+    }
+    buffer.set_title(&title);
+    // Threads have no member list of their own
+    buffer.set_nicks_loaded();
+    buffer.switch_to();
+
+    let sealed_buffer = buffer.seal();
+    account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let nick = crate::buffers::guild_nick(ctx, guild_id);
+        on_main(move |weecord| {
+            let buffer = sealed_buffer.unseal(weecord);
+            buffer.set_localvar("nick", &nick);
+            weecord.update_bar_item("input_prompt");
+        });
+    });
+}
+
+/// Reload the posts of the forum shown in a forum buffer
+pub fn refresh_forum_buffer(forum_buffer: &weechat::Buffer) {
+    let (guild_id, forum_id) = match (
+        forum_buffer.guild_id(),
+        forum_channel_for_buffer(forum_buffer),
+    ) {
+        (Some(guild_id), Some(forum_id)) => (guild_id, forum_id),
+        _ => return,
+    };
+    open_forum(guild_id, Some(forum_id.0.to_string()));
+}
@@ -0,0 +1,229 @@
+//! The friends buffer, listing friends with their presence, pending friend requests and blocked
+//! users
+//!
+//! Entering the number of a user in the friends buffer opens a DM with them.
+use crate::{
+    buffers,
+    discord::{
+        self, account,
+        relationships::{self, Relationship, RelationshipKind},
+    },
+    dms, plugin_print, utils, Discord,
+};
+use serenity::{cache::CacheRwLock, model::prelude::*};
+use weechat::Weechat;
+
+pub fn buffer_id_for_friends() -> String {
+    account::namespaced("Friends".to_owned())
+}
+
+/// Open the friends buffer
+pub fn open_friends(weecord: &Discord) {
+    let buffer_name = buffer_id_for_friends();
+    buffers::create_list_buffer(
+        weecord,
+        &buffer_name,
+        "friends",
+        "Friends",
+        "Friends, friend requests and blocked users",
+        &[],
+    );
+
+    draw_friends(weecord, &buffer_name);
+}
+
+/// Redraw the friends buffer of the current account, if it is open
+pub fn refresh_friends_buffer(weecord: &Discord) {
+    let buffer_name = buffer_id_for_friends();
+    if weecord.buffer_manager.get_buffer(&buffer_name).is_some() {
+        draw_friends(weecord, &buffer_name);
+    }
+}
+
+fn draw_friends(weecord: &Discord, buffer_name: &str) {
+    let ctx = &match discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+    let buffer = match weecord.buffer_manager.get_buffer(buffer_name) {
+        Some(buffer) => buffer,
+        None => return,
+    };
+
+    let sections = [
+        (RelationshipKind::Friend, "Friends"),
+        (
+            RelationshipKind::IncomingRequest,
+            "Incoming friend requests",
+        ),
+        (
+            RelationshipKind::OutgoingRequest,
+            "Outgoing friend requests",
+        ),
+        (RelationshipKind::Blocked, "Blocked users"),
+    ];
+
+    buffer.clear();
+    let mut listed = Vec::new();
+    for &(kind, title) in &sections {
+        let users = relationships::with_kind(kind);
+        if users.is_empty() {
+            continue;
+        }
+
+        buffer.print(&utils::colorize_string(weecord, "bold", title));
+        for relationship in users {
+            listed.push(relationship);
+            buffer.print(&format_relationship(
+                weecord,
+                &ctx.cache,
+                listed.len(),
+                &listed[listed.len() - 1],
+            ));
+        }
+        buffer.print("");
+    }
+
+    if listed.is_empty() {
+        buffer.print("You have no friends, friend requests or blocked users");
+    } else {
+        buffer.print("Enter the number of a user to open a DM with them");
+    }
+    buffer.set_list(listed);
+}
+
+fn format_relationship(
+    weechat: &Weechat,
+    cache: &CacheRwLock,
+    number: usize,
+    relationship: &Relationship,
+) -> String {
+    let number = utils::colorize_string(weechat, "chat_delimiters", &format!("[{}]", number));
+    let name = utils::format_nick_color(weechat, &relationship.tag());
+    if relationship.kind != RelationshipKind::Friend {
+        return format!("{}\t{}", number, name);
+    }
+
+    let cache = cache.read();
+    let presence = cache.presences.get(&relationship.user_id);
+    format!(
+        "{}\t{}{} {}",
+        number,
+        utils::format_user_status_prefix(weechat, presence.map(|p| p.status)),
+        name,
+        utils::format_presence(weechat, presence)
+    )
+}
+
+/// Open a DM with a user by their number in a friends buffer
+pub fn open_dm_from_list(weecord: &Discord, list_buffer: &weechat::Buffer, arg: &str) {
+    let users = weecord
+        .buffer_manager
+        .get_buffer(&list_buffer.get_name())
+        .and_then(|buffer| buffer.with_list(|users: &mut Vec<Relationship>| users.clone()));
+    let users = match users {
+        Some(users) => users,
+        None => return,
+    };
+    match arg.parse::<usize>() {
+        Ok(n) if n >= 1 && n <= users.len() => dms::open_dm_with_user(users[n - 1].user_id),
+        _ => list_buffer.print(&format!(
+            "{}\tNo user {:?}, enter the number of a user",
+            weecord.get_prefix("error"),
+            arg
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FriendAction {
+    Accept,
+    Ignore,
+    Add,
+    Remove,
+    Block,
+    Unblock,
+}
+
+impl FriendAction {
+    pub fn parse(action: &str) -> Option<FriendAction> {
+        match action {
+            "accept" => Some(FriendAction::Accept),
+            "ignore" => Some(FriendAction::Ignore),
+            "add" => Some(FriendAction::Add),
+            "remove" => Some(FriendAction::Remove),
+            "block" => Some(FriendAction::Block),
+            "unblock" => Some(FriendAction::Unblock),
+            _ => None,
+        }
+    }
+}
+
+/// Change the relationship with a user, the friends buffer is redrawn once Discord sends the
+/// relationship event
+pub fn change_relationship(action: FriendAction, target: String) {
+    account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+
+        // Friend requests are sent by name, the user does not have to be known yet
+        if action == FriendAction::Add {
+            match relationships::send_request(ctx, &target) {
+                Ok(_) => plugin_print(&format!("Sent a friend request to {}", target)),
+                Err(e) => plugin_print(&format!("Unable to send friend request: {}", e)),
+            }
+            return;
+        }
+
+        let relationship = relationships::search(&target);
+        let expected: &[RelationshipKind] = match action {
+            FriendAction::Accept => &[RelationshipKind::IncomingRequest],
+            // Ignoring an outgoing request cancels it
+            FriendAction::Ignore => &[
+                RelationshipKind::IncomingRequest,
+                RelationshipKind::OutgoingRequest,
+            ],
+            FriendAction::Unblock => &[RelationshipKind::Blocked],
+            FriendAction::Remove => &[RelationshipKind::Friend],
+            _ => &[],
+        };
+        let kind = relationship.as_ref().map(|relationship| relationship.kind);
+        if !expected.is_empty() && !kind.map_or(false, |kind| expected.contains(&kind)) {
+            plugin_print(&format!(
+                "No {} matching {:?}",
+                match action {
+                    FriendAction::Accept | FriendAction::Ignore => "friend request",
+                    FriendAction::Unblock => "blocked user",
+                    _ => "friend",
+                },
+                target
+            ));
+            return;
+        }
+
+        let user_id = match relationship {
+            Some(relationship) => relationship.user_id,
+            None => match utils::search_user(ctx, None, &target) {
+                Some(user) => user.id,
+                None => {
+                    plugin_print(&format!("Could not find user {:?}", target));
+                    return;
+                },
+            },
+        };
+
+        let result = match action {
+            FriendAction::Accept => relationships::accept(ctx, user_id),
+            FriendAction::Block => relationships::block(ctx, user_id),
+            FriendAction::Ignore | FriendAction::Remove | FriendAction::Unblock => {
+                relationships::remove(ctx, user_id)
+            },
+            FriendAction::Add => unreachable!(),
+        };
+        if let Err(e) = result {
+            plugin_print(&format!("Unable to update {}: {}", target, e));
+        }
+    });
+}
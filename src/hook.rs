@@ -1,20 +1,19 @@
 use crate::{
     buffers::load_pin_buffer_history,
     command::Args,
-    discord, on_main, plugin_print, utils,
+    discord,
+    discord::{account, member_list, relationships::RelationshipKind},
+    on_main, plugin_print, utils,
     utils::{BufferExt, ChannelExt},
 };
 use crossbeam_channel::unbounded;
 use lazy_static::lazy_static;
 use serenity::{
-    client::bridge::gateway,
     model::{channel::ReactionType, prelude::*},
     prelude::*,
 };
 use std::{
-    iter::FromIterator,
     sync::Arc,
-    thread,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use weechat::{Buffer, CompletionPosition, ConfigOption, ReturnCode, Weechat};
@@ -26,15 +25,18 @@ lazy_static! {
 pub struct HookHandles {
     _buffer_switch_handle: weechat::SignalHook<()>,
     _buffer_typing_handle: weechat::SignalHook<()>,
+    _buffer_closing_handle: weechat::SignalHook<()>,
     _command_handles: Vec<weechat::CommandHook<()>>,
     _query_handle: weechat::CommandRunHook<()>,
     _nick_handle: weechat::CommandRunHook<()>,
     _join_handle: weechat::CommandRunHook<()>,
+    _nicklist_scroll_handle: weechat::CommandRunHook<()>,
     _guild_completion_handle: weechat::CompletionHook<()>,
     _channel_completion_handle: weechat::CompletionHook<()>,
     _dm_completion_handle: weechat::CompletionHook<()>,
     _nick_completion_handle: weechat::CompletionHook<()>,
     _role_completion_handle: weechat::CompletionHook<()>,
+    _account_completion_handle: weechat::CompletionHook<()>,
 }
 
 pub fn init(weechat: &Weechat) -> HookHandles {
@@ -42,7 +44,7 @@ pub fn init(weechat: &Weechat) -> HookHandles {
 
     let _buffer_switch_handle = weechat.hook_signal(
         "buffer_switch",
-        |_, _, value| handle_buffer_switch(value),
+        |_, weechat, value| handle_buffer_switch(weechat, value),
         None,
     );
 
@@ -52,12 +54,19 @@ pub fn init(weechat: &Weechat) -> HookHandles {
         None,
     );
 
+    let _buffer_closing_handle = weechat.hook_signal(
+        "buffer_closing",
+        |_, weechat, value| handle_buffer_closing(weechat, value),
+        None,
+    );
+
     let _query_handle = weechat.hook_command_run(
         "/query",
         |_, ref buffer, ref command| {
             if buffer.guild_id().is_none() {
                 return ReturnCode::Error;
             };
+            let _account = account::enter(&account::for_buffer(buffer));
 
             handle_query(&Args::from_cmd(
                 &command.replace("/query ", "/discord query "),
@@ -78,6 +87,12 @@ pub fn init(weechat: &Weechat) -> HookHandles {
         None,
     );
 
+    let _nicklist_scroll_handle = weechat.hook_command_run(
+        "/bar scroll nicklist*",
+        |_, ref buffer, ref command| handle_nicklist_scroll(buffer, command),
+        None,
+    );
+
     let _guild_completion_handle = weechat.hook_completion(
         "weecord_guild_completion",
         "Completion for discord guilds",
@@ -113,22 +128,33 @@ pub fn init(weechat: &Weechat) -> HookHandles {
         None,
     );
 
+    let _account_completion_handle = weechat.hook_completion(
+        "weecord_account_completion",
+        "Completion for configured Discord accounts",
+        |_, ref buffer, _, completions| handle_account_completion(buffer, completions),
+        None,
+    );
+
     HookHandles {
         _buffer_switch_handle,
         _buffer_typing_handle,
+        _buffer_closing_handle,
         _command_handles,
         _query_handle,
         _nick_handle,
         _join_handle,
+        _nicklist_scroll_handle,
         _guild_completion_handle,
         _channel_completion_handle,
         _dm_completion_handle,
         _nick_completion_handle,
         _role_completion_handle,
+        _account_completion_handle,
     }
 }
 
 pub fn buffer_input(buffer: Buffer, text: &str) {
+    let _account = account::enter(&account::for_buffer(&buffer));
     let text = if text.is_empty() {
         return;
     } else {
@@ -137,8 +163,31 @@ pub fn buffer_input(buffer: Buffer, text: &str) {
     let channel = buffer.channel_id();
     let guild = buffer.guild_id();
 
+    let weechat = buffer.get_weechat();
+    let weecord = &match crate::upgrade_plugin(&weechat) {
+        Some(weecord) => weecord,
+        None => return,
+    };
+
+    // List buffers handle their own input, see `buffers::create_list_buffer`
+    let input = text.trim();
+    match buffer.get_localvar("type").as_deref() {
+        Some("forum") => return crate::forum::open_post(weecord, &buffer, input),
+        Some("dm_list") => return crate::dms::open_dm_from_list(weecord, &buffer, input),
+        Some("audit_log") => {
+            return crate::audit_log::audit_log_buffer_input(weecord, &buffer, input)
+        },
+        Some("pins") => return crate::buffers::pins_buffer_input(weecord, &buffer, input),
+        Some("bookmarks") => {
+            return crate::bookmarks::bookmarks_buffer_input(weecord, &buffer, input)
+        },
+        Some("friends") => return crate::friends::open_dm_from_list(weecord, &buffer, input),
+        Some("events") => return crate::scheduled_events::toggle_interest(weecord, &buffer, input),
+        _ => {},
+    }
+
     if let Some(channel) = channel {
-        let ctx = match crate::discord::get_ctx() {
+        let ctx = &match crate::discord::get_ctx() {
             Some(ctx) => ctx,
             _ => return,
         };
@@ -187,6 +236,13 @@ pub fn buffer_input(buffer: Buffer, text: &str) {
             return;
         }
         if let Some(reaction) = parsing::parse_reaction(text) {
+            if reaction.add && !buffer.has_permission(Permissions::ADD_REACTIONS) {
+                buffer.print(&format!(
+                    "{}\tYou do not have permission to add reactions in this channel",
+                    buffer.get_weechat().get_prefix("network"),
+                ));
+                return;
+            }
             if let Ok(msgs) =
                 channel.messages(ctx, |retriever| retriever.limit(reaction.line as u64))
             {
@@ -208,6 +264,13 @@ pub fn buffer_input(buffer: Buffer, text: &str) {
             }
             return;
         }
+        if !buffer.has_permission(Permissions::SEND_MESSAGES) {
+            buffer.print(&format!(
+                "{}\tThis channel is read-only, the message was not sent",
+                buffer.get_weechat().get_prefix("network"),
+            ));
+            return;
+        }
         let text = utils::create_mentions(&ctx.cache, guild, text);
         let text = utils::expand_guild_emojis(&ctx.cache, guild, &text);
         if let Err(e) = channel.say(ctx, text) {
@@ -222,11 +285,14 @@ pub fn buffer_input(buffer: Buffer, text: &str) {
     }
 }
 
-fn handle_buffer_switch(data: weechat::SignalHookValue) -> ReturnCode {
+fn handle_buffer_switch(weechat: &Weechat, data: weechat::SignalHookValue) -> ReturnCode {
     if let weechat::SignalHookValue::Pointer(buffer_ptr) = data {
-        let buffer = unsafe { crate::utils::buffer_from_ptr(buffer_ptr) };
-        let weechat = buffer.get_weechat();
-        let weecord = crate::upgrade_plugin(&weechat);
+        let buffer = unsafe { crate::utils::buffer_from_ptr(weechat, buffer_ptr) };
+        let _account = account::enter(&account::for_buffer(&buffer));
+        let weecord = &match crate::upgrade_plugin(weechat) {
+            Some(weecord) => weecord,
+            None => return ReturnCode::Ok,
+        };
         let buffer = match weecord
             .buffer_manager
             .get_buffer(buffer.get_name().as_ref())
@@ -248,6 +314,9 @@ fn handle_buffer_switch(data: weechat::SignalHookValue) -> ReturnCode {
             let fetch_count = weecord.config.message_fetch_count.value();
 
             crate::buffers::load_history(&buffer, tx, fetch_count);
+            if let Some(channel_id) = buffer.channel_id() {
+                crate::buffers::refresh_pin_count(channel_id);
+            }
         }
 
         if !buffer.nicks_loaded() {
@@ -256,51 +325,18 @@ fn handle_buffer_switch(data: weechat::SignalHookValue) -> ReturnCode {
 
         let channel_id = buffer.channel_id();
 
-        thread::spawn(move || {
+        account::spawn(move || {
             if rx.recv().is_err() {
                 return;
             }
-            let ctx = match discord::get_ctx() {
+            let ctx = &match discord::get_ctx() {
                 Some(s) => s,
                 None => return,
             };
             if let Some(channel) = channel_id.and_then(|id| id.to_channel_cached(&ctx)) {
                 if let Some(guild_channel) = channel.clone().guild() {
                     let guild_id = guild_channel.read().guild_id;
-                    use std::collections::{HashMap, HashSet};
-                    lazy_static! {
-                        static ref CHANNELS: Arc<Mutex<HashMap<GuildId, HashSet<ChannelId>>>> =
-                            Arc::new(Mutex::new(HashMap::new()));
-                    }
-
-                    let mut channels = CHANNELS.lock();
-                    let send = if let Some(guild_channels) = channels.get_mut(&guild_id) {
-                        guild_channels.insert(channel.id())
-                    } else {
-                        channels
-                            .insert(guild_id, HashSet::from_iter(vec![channel.id()].into_iter()));
-                        true
-                    };
-                    if send {
-                        let channels = channels.get(&guild_id).unwrap();
-                        let channels_obj: HashMap<String, Vec<Vec<_>>> = HashMap::from_iter(
-                            channels
-                                .iter()
-                                .map(|ch| (format!("{}", ch.0), vec![vec![0, 99]])),
-                        );
-
-                        let msg = json::object! {
-                            "op" => 14,
-                            "d" => json::object! {
-                                "guild_id" => format!("{}", guild_id.0),
-                                "typing" => true,
-                                "activities" => true,
-                                "channels" => channels_obj,
-                            }
-                        };
-                        ctx.shard
-                            .websocket_message(gateway::Message::Text(msg.to_string()));
-                    }
+                    member_list::subscribe(ctx, guild_id, channel.id());
                 }
 
                 if let Some(rs) = ctx.cache.read().read_state.get(&channel.id()) {
@@ -316,15 +352,47 @@ fn handle_buffer_switch(data: weechat::SignalHookValue) -> ReturnCode {
     ReturnCode::Ok
 }
 
+fn handle_nicklist_scroll(buffer: &Buffer, command: &str) -> ReturnCode {
+    let _account = account::enter(&account::for_buffer(buffer));
+    // `/bar scroll nicklist <window> <value>`
+    let scroll = match command
+        .split_whitespace()
+        .last()
+        .and_then(member_list::Scroll::parse)
+    {
+        Some(scroll) => scroll,
+        None => return ReturnCode::Ok,
+    };
+    if let (Some(guild_id), Some(channel_id)) = (buffer.guild_id(), buffer.channel_id()) {
+        account::spawn(move || {
+            if let Some(ctx) = discord::get_ctx() {
+                member_list::scroll(&ctx, guild_id, channel_id, scroll);
+            }
+        });
+    }
+    // Let weechat scroll the bar
+    ReturnCode::Ok
+}
+
+fn handle_buffer_closing(weechat: &Weechat, data: weechat::SignalHookValue) -> ReturnCode {
+    if let weechat::SignalHookValue::Pointer(buffer_ptr) = data {
+        let buffer = unsafe { crate::utils::buffer_from_ptr(weechat, buffer_ptr) };
+        if let Some(weecord) = crate::upgrade_plugin(weechat) {
+            weecord.buffer_manager.forget_buffer(&buffer.get_name());
+        }
+    }
+    ReturnCode::Ok
+}
+
 fn handle_buffer_typing(weechat: &Weechat, data: weechat::SignalHookValue) -> ReturnCode {
     if let weechat::SignalHookValue::Pointer(buffer_ptr) = data {
-        let buffer = unsafe { crate::utils::buffer_from_ptr(buffer_ptr) };
+        let buffer = unsafe { crate::utils::buffer_from_ptr(weechat, buffer_ptr) };
+        let _account = account::enter(&account::for_buffer(&buffer));
         if let Some(channel_id) = buffer.channel_id() {
-            if crate::upgrade_plugin(weechat)
-                .config
-                .send_typing_events
-                .value()
-            {
+            let send_typing_events = crate::upgrade_plugin(weechat)
+                .map(|weecord| weecord.config.send_typing_events.value())
+                .unwrap_or(false);
+            if send_typing_events {
                 if buffer.input().starts_with('/') {
                     return ReturnCode::Ok;
                 }
@@ -338,8 +406,8 @@ fn handle_buffer_typing(weechat: &Weechat, data: weechat::SignalHookValue) -> Re
                 if *LAST_TYPING_TIMESTAMP.lock() + 9 < timestamp_now {
                     *LAST_TYPING_TIMESTAMP.lock() = timestamp_now;
 
-                    std::thread::spawn(move || {
-                        let ctx = match discord::get_ctx() {
+                    account::spawn(move || {
+                        let ctx = &match discord::get_ctx() {
                             Some(s) => s,
                             None => return,
                         };
@@ -353,6 +421,7 @@ fn handle_buffer_typing(weechat: &Weechat, data: weechat::SignalHookValue) -> Re
 }
 
 fn handle_channel_completion(buffer: &Buffer, completion: weechat::Completion) -> ReturnCode {
+    let _account = account::enter(&account::for_buffer(buffer));
     // Get the previous argument with should be the guild name
     // TODO: Generalize this?
     let input = buffer.input();
@@ -369,7 +438,7 @@ fn handle_channel_completion(buffer: &Buffer, completion: weechat::Completion) -
     };
 
     // Match mangled name to the real name
-    let ctx = match discord::get_ctx() {
+    let ctx = &match discord::get_ctx() {
         Some(s) => s,
         None => return ReturnCode::Ok,
     };
@@ -397,8 +466,9 @@ fn handle_channel_completion(buffer: &Buffer, completion: weechat::Completion) -
     ReturnCode::Ok
 }
 
-fn handle_guild_completion(_buffer: &Buffer, completion: weechat::Completion) -> ReturnCode {
-    let ctx = match discord::get_ctx() {
+fn handle_guild_completion(buffer: &Buffer, completion: weechat::Completion) -> ReturnCode {
+    let _account = account::enter(&account::for_buffer(buffer));
+    let ctx = &match discord::get_ctx() {
         Some(s) => s,
         None => return ReturnCode::Ok,
     };
@@ -409,19 +479,25 @@ fn handle_guild_completion(_buffer: &Buffer, completion: weechat::Completion) ->
     ReturnCode::Ok
 }
 
-fn handle_dm_completion(_buffer: &Buffer, completion: weechat::Completion) -> ReturnCode {
-    let ctx = match discord::get_ctx() {
+fn handle_dm_completion(buffer: &Buffer, completion: weechat::Completion) -> ReturnCode {
+    let _account = account::enter(&account::for_buffer(buffer));
+    let ctx = &match discord::get_ctx() {
         Some(s) => s,
         None => return ReturnCode::Ok,
     };
     for dm in ctx.cache.read().private_channels.values() {
         completion.add(&dm.read().recipient.read().name);
     }
+    // Friends that have never been messaged do not have a private channel yet
+    for relationship in discord::relationships::all() {
+        completion.add(&relationship.name);
+    }
     ReturnCode::Ok
 }
 
 fn handle_nick_completion(buffer: &Buffer, completion: weechat::Completion) -> ReturnCode {
-    let ctx = match discord::get_ctx() {
+    let _account = account::enter(&account::for_buffer(buffer));
+    let ctx = &match discord::get_ctx() {
         Some(s) => s,
         None => return ReturnCode::Ok,
     };
@@ -446,7 +522,8 @@ fn handle_nick_completion(buffer: &Buffer, completion: weechat::Completion) -> R
 }
 
 fn handle_role_completion(buffer: &Buffer, completion: weechat::Completion) -> ReturnCode {
-    let ctx = match discord::get_ctx() {
+    let _account = account::enter(&account::for_buffer(buffer));
+    let ctx = &match discord::get_ctx() {
         Some(s) => s,
         None => return ReturnCode::Ok,
     };
@@ -465,6 +542,19 @@ fn handle_role_completion(buffer: &Buffer, completion: weechat::Completion) -> R
     ReturnCode::Ok
 }
 
+fn handle_account_completion(buffer: &Buffer, completion: weechat::Completion) -> ReturnCode {
+    let weechat = buffer.get_weechat();
+    let weecord = match crate::upgrade_plugin(&weechat) {
+        Some(weecord) => weecord,
+        None => return ReturnCode::Ok,
+    };
+    for account in weecord.config.account_names() {
+        completion.add(&account);
+    }
+
+    ReturnCode::Ok
+}
+
 // TODO: Make this faster
 // TODO: Handle command options
 pub fn handle_query(args: &Args) -> ReturnCode {
@@ -494,8 +584,8 @@ pub fn handle_query(args: &Args) -> ReturnCode {
         None => return ReturnCode::Ok,
     };
 
-    thread::spawn(move || {
-        let ctx = match crate::discord::get_ctx() {
+    account::spawn(move || {
+        let ctx = &match crate::discord::get_ctx() {
             Some(ctx) => ctx,
             _ => return,
         };
@@ -513,6 +603,16 @@ pub fn handle_query(args: &Args) -> ReturnCode {
             }
         }
 
+        if found_members.is_empty() {
+            let target = target.to_lowercase();
+            for relationship in discord::relationships::with_kind(RelationshipKind::Friend) {
+                if relationship.name.to_lowercase().contains(&target) {
+                    if let Ok(user) = relationship.user_id.to_user(ctx) {
+                        found_members.push(user);
+                    }
+                }
+            }
+        }
         if found_members.is_empty() {
             let guilds = current_user.guilds(ctx).expect("Unable to fetch guilds");
             for guild in &guilds {
@@ -531,7 +631,7 @@ pub fn handle_query(args: &Args) -> ReturnCode {
         if let Some(target) = found_members.get(0) {
             if let Ok(chan) = target.create_dm_channel(ctx) {
                 on_main(move |weecord| {
-                    let ctx = match crate::discord::get_ctx() {
+                    let ctx = &match crate::discord::get_ctx() {
                         Some(ctx) => ctx,
                         _ => return,
                     };
@@ -554,6 +654,7 @@ pub fn handle_query(args: &Args) -> ReturnCode {
 
 // TODO: Handle command options
 fn handle_nick(buffer: &Buffer, command: &str) -> ReturnCode {
+    let _account = account::enter(&account::for_buffer(buffer));
     let guild = if let Some(id) = buffer.guild_id() {
         id
     } else {
@@ -563,7 +664,7 @@ fn handle_nick(buffer: &Buffer, command: &str) -> ReturnCode {
     let guilds;
     let mut substr;
     {
-        let ctx = match crate::discord::get_ctx() {
+        let ctx = &match crate::discord::get_ctx() {
             Some(ctx) => ctx,
             _ => return ReturnCode::Error,
         };
@@ -588,9 +689,9 @@ fn handle_nick(buffer: &Buffer, command: &str) -> ReturnCode {
         };
     }
 
-    thread::spawn(move || {
+    account::spawn(move || {
         {
-            let ctx = match crate::discord::get_ctx() {
+            let ctx = &match crate::discord::get_ctx() {
                 Some(ctx) => ctx,
                 _ => return,
             };
@@ -613,6 +714,7 @@ fn handle_nick(buffer: &Buffer, command: &str) -> ReturnCode {
 }
 
 fn handle_join(buffer: &Buffer, command: &str) -> ReturnCode {
+    let _account = account::enter(&account::for_buffer(buffer));
     let verbose = buffer.guild_id().is_some();
 
     crate::command::join(
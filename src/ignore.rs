@@ -0,0 +1,143 @@
+//! Client-side ignores, messages of ignored users are tagged `discord_ignored` so a filter can
+//! hide them, or collapsed to a placeholder
+//!
+//! Users blocked on Discord are treated as ignored everywhere.
+use crate::{config::Config, discord::relationships, sync, utils::BufferExt, Discord};
+use chrono::{DateTime, TimeZone, Utc};
+use serenity::model::id::{GuildId, UserId};
+use std::fmt;
+use weechat::{ConfigOption, Weechat};
+
+/// Tag of lines printed for messages of ignored users
+pub const IGNORED_TAG: &str = "discord_ignored";
+
+/// Add the filter hiding messages of ignored users the first time the plugin is loaded, so a
+/// filter the user changed or deleted is left alone
+pub fn init(weechat: &Weechat, config: &Config) {
+    if config.ignored_filter_created.value() {
+        return;
+    }
+    if let Some(core) = weechat.buffer_search("core", "weechat") {
+        // Adding a filter that exists fails, which /mute keeps quiet
+        let _ = core.run_command(&format!(
+            "/mute /filter add {} * {} *",
+            IGNORED_TAG, IGNORED_TAG
+        ));
+        config.ignored_filter_created.set(true);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ignore {
+    pub user_id: UserId,
+    /// Only ignore the user in this guild
+    pub guild_id: Option<GuildId>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Ignore {
+    /// Parse an entry of `weecord.main.ignored_users`, `user_id[:guild_id[:expires]]` where the
+    /// expiry is a unix timestamp
+    pub fn parse(entry: &str) -> Option<Ignore> {
+        let mut split = entry.trim().split(':');
+        let user_id = UserId(split.next()?.parse().ok()?);
+        let guild_id = match split.next() {
+            Some(id) if !id.is_empty() => Some(GuildId(id.parse().ok()?)),
+            _ => None,
+        };
+        let expires_at = match split.next() {
+            Some(expires) if !expires.is_empty() => {
+                Some(Utc.timestamp_opt(expires.parse().ok()?, 0).single()?)
+            },
+            _ => None,
+        };
+        Some(Ignore {
+            user_id,
+            guild_id,
+            expires_at,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at <= Utc::now())
+            .unwrap_or(false)
+    }
+
+    fn applies(&self, user_id: UserId, guild_id: Option<GuildId>) -> bool {
+        self.user_id == user_id
+            && (self.guild_id.is_none() || self.guild_id == guild_id)
+            && !self.is_expired()
+    }
+}
+
+impl fmt::Display for Ignore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.user_id.0)?;
+        match (self.guild_id, self.expires_at) {
+            (Some(guild_id), Some(expires_at)) => {
+                write!(f, ":{}:{}", guild_id.0, expires_at.timestamp())
+            },
+            (Some(guild_id), None) => write!(f, ":{}", guild_id.0),
+            (None, Some(expires_at)) => write!(f, "::{}", expires_at.timestamp()),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Whether messages of a user should be hidden, in a guild or in DMs when `guild_id` is `None`
+///
+/// Only knows about local ignores on the main thread, where the config can be read
+pub fn is_ignored(user_id: UserId, guild_id: Option<GuildId>) -> bool {
+    if relationships::is_blocked(user_id) {
+        return true;
+    }
+    sync::plugin()
+        .map(|weecord| {
+            weecord
+                .config
+                .ignored_users()
+                .iter()
+                .any(|ignore| ignore.applies(user_id, guild_id))
+        })
+        .unwrap_or(false)
+}
+
+/// Whether messages of ignored users are collapsed to a placeholder instead of being left to
+/// the `discord_ignored` filter
+pub fn use_placeholder() -> bool {
+    sync::plugin()
+        .map(|weecord| weecord.config.ignored_placeholder.value())
+        .unwrap_or(false)
+}
+
+/// Ignore a user, replacing any previous ignore of them in the same guild
+pub fn add(weecord: &Discord, ignore: Ignore) {
+    let mut ignores = weecord.config.ignored_users();
+    ignores.retain(|it| !(it.user_id == ignore.user_id && it.guild_id == ignore.guild_id));
+    ignores.push(ignore);
+    weecord.config.set_ignored_users(&ignores);
+}
+
+/// Stop ignoring a user everywhere, returning whether they were ignored
+pub fn remove(weecord: &Discord, user_id: UserId) -> bool {
+    let mut ignores = weecord.config.ignored_users();
+    let before = ignores.len();
+    ignores.retain(|it| it.user_id != user_id);
+    weecord.config.set_ignored_users(&ignores);
+    ignores.len() != before
+}
+
+/// Reprint every loaded buffer of the current account so ignores apply to existing messages
+pub fn redraw_buffers(weecord: &Discord) {
+    let ctx = &match crate::discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+    for buffer in weecord.buffer_manager.buffers() {
+        // List buffers have no channel and nothing to redraw
+        if buffer.channel_id().is_some() && buffer.history_loaded() {
+            buffer.redraw_buffer(&ctx.cache);
+        }
+    }
+}
@@ -1,32 +1,45 @@
 #![allow(clippy::let_unit_value)]
 
+mod audit_log;
 mod bar_items;
+mod bookmarks;
 mod buffers;
 mod command;
 mod config;
 mod discord;
+mod dms;
+mod forum;
+mod friends;
 mod hook;
+mod ignore;
+mod moderation;
+mod scheduled_events;
 mod sync;
 mod utils;
+mod voice;
 mod weechat_utils;
 
-use crate::weechat_utils::BufferManager;
+use crate::{discord::session::Session, weechat_utils::BufferManager};
 pub use sync::{on_main, on_main_blocking, upgrade_plugin};
 
-use std::borrow::Cow;
+use std::{borrow::Cow, cell::RefCell, collections::HashMap, rc::Rc, sync::Arc};
 use weechat::{weechat_plugin, ArgsWeechat, ConfigOption, Weechat, WeechatPlugin, WeechatResult};
 
 pub struct Discord {
     weechat: Weechat,
     config: config::Config,
     buffer_manager: BufferManager,
+    /// Sessions of the connected accounts, only touched on the main thread
+    sessions: RefCell<HashMap<String, Arc<Session>>>,
     _sync_handle: sync::SyncHandle,
     _hook_handles: hook::HookHandles,
     _bar_handles: bar_items::BarHandles,
 }
 
-impl WeechatPlugin for Discord {
-    // Note: We cannot use on_main (or plugin_print)
+/// The plugin registered with weechat, which shares the state with jobs and callbacks
+pub struct Weecord(Rc<Discord>);
+
+impl WeechatPlugin for Weecord {
     fn init(weechat: Weechat, args: ArgsWeechat) -> WeechatResult<Self> {
         let args: Vec<_> = args.collect();
 
@@ -45,35 +58,49 @@ impl WeechatPlugin for Discord {
         let _hook_handles = hook::init(&weechat);
         let _bar_handles = bar_items::init(&weechat);
         let config = config::init(&weechat);
+        ignore::init(&weechat, &config);
         let buffer_manager = buffers::init(&weechat);
 
-        let autostart = config.autostart.value();
-
-        let weecord = Discord {
+        let weecord = Rc::new(Discord {
             weechat,
             config,
             buffer_manager,
+            sessions: RefCell::new(HashMap::new()),
             _sync_handle,
             _hook_handles,
             _bar_handles,
-        };
+        });
+        sync::set_plugin(Rc::clone(&weecord));
 
-        if !args.contains(&"-a".to_owned()) && autostart {
-            weecord.connect();
+        if !args.contains(&"-a".to_owned()) {
+            weecord.connect_autostart();
         }
 
-        Ok(weecord)
+        Ok(Weecord(weecord))
+    }
+}
+
+impl Drop for Weecord {
+    fn drop(&mut self) {
+        // Drops the plugin once the last callback using it returns
+        sync::clear_plugin();
     }
 }
 
 impl Discord {
-    fn connect(&self) {
-        if crate::discord::DISCORD.lock().is_some() {
-            plugin_print("Already connected");
+    fn connect(&self, account: &str) {
+        if self.is_connected(account) {
+            plugin_print(&format!("Account {} is already connected", account));
             return;
         }
 
-        let token = self.config.token.value().into_owned();
+        let token = match self.config.account_token(account) {
+            Some(token) => token,
+            None => {
+                plugin_print(&format!("Unknown account {}", account));
+                return;
+            },
+        };
 
         let token = if token.starts_with("${sec.data") {
             self.eval_string_expression(&token).map(Cow::into_owned)
@@ -82,19 +109,79 @@ impl Discord {
         };
         if let Some(t) = token {
             if !t.is_empty() {
-                discord::init(&self, &t, self.config.irc_mode.value());
-            } else {
+                let session = Arc::new(Session::new(account));
+                self.sessions
+                    .borrow_mut()
+                    .insert(account.to_owned(), Arc::clone(&session));
+                discord::init(&self, &session, &t, self.config.irc_mode.value());
+            } else if account == discord::account::DEFAULT_ACCOUNT {
                 self.print("Error: weecord.main.token is not set. To set it, run:");
                 self.print("/discord token 123456789ABCDEF");
+            } else {
+                self.print(&format!(
+                    "Error: weecord.account.{}.token is not set. To set it, run:",
+                    account
+                ));
+                self.print(&format!("/discord account {} 123456789ABCDEF", account));
             }
         } else {
             self.print("Error: failed to evaluate token option, expected valid ${sec.data...}");
         }
     }
+
+    /// Connect every account that has autostart enabled
+    fn connect_autostart(&self) {
+        for account in self.config.account_names() {
+            if self.config.account_autostart(&account) {
+                self.connect(&account);
+            }
+        }
+    }
+
+    /// The session of an account, if it is connected
+    fn session(&self, account: &str) -> Option<Arc<Session>> {
+        self.sessions
+            .borrow()
+            .get(account)
+            .filter(|session| !session.is_closed())
+            .cloned()
+    }
+
+    fn is_connected(&self, account: &str) -> bool {
+        self.session(account).is_some()
+    }
+
+    fn connected_accounts(&self) -> Vec<String> {
+        self.sessions
+            .borrow()
+            .values()
+            .filter(|session| !session.is_closed())
+            .map(|session| session.account().to_owned())
+            .collect()
+    }
+
+    /// Disconnect an account, returning whether it was connected
+    fn disconnect(&self, account: &str) -> bool {
+        let session = self.sessions.borrow_mut().remove(account);
+        match session {
+            Some(session) if !session.is_closed() => {
+                let _account = discord::account::enter_session(&session);
+                session.teardown();
+                true
+            },
+            _ => false,
+        }
+    }
 }
 
 impl Drop for Discord {
     fn drop(&mut self) {
+        let sessions: Vec<_> = self.sessions.borrow_mut().drain().collect();
+        for (_, session) in sessions {
+            let _account = discord::account::enter_session(&session);
+            session.teardown();
+        }
+
         // TODO: Why is the config file not saved on quit?
         self.config.config.write()
     }
@@ -109,7 +196,7 @@ impl std::ops::Deref for Discord {
 }
 
 weechat_plugin!(
-    Discord,
+    Weecord,
     name: "weecord",
     author: "Noskcaj19",
     description: "Discord integration for weechat",
@@ -0,0 +1,335 @@
+//! Moderation commands for guild channels
+//!
+//! Actions go through the api directly so the reason is recorded in the audit log, results are
+//! printed in the channel buffer the command was run in.
+use crate::{
+    discord::{self, account, rest},
+    on_main, plugin_print, utils,
+    utils::BufferExt,
+    Discord,
+};
+use chrono::{Duration, Utc};
+use json::JsonValue;
+use reqwest::Method;
+use serenity::{model::prelude::*, prelude::Context};
+use std::{
+    thread,
+    time::{self, Instant},
+};
+use weechat::Buffer;
+
+/// Most messages a single purge can delete
+pub const MAX_PURGE: usize = 100;
+/// Messages searched for ones by a specific user when purging
+const PURGE_SEARCH_LIMIT: usize = 500;
+/// Longest timeout Discord allows
+const MAX_TIMEOUT_DAYS: i64 = 28;
+/// Moderate members permission, which serenity does not have a constant for
+const MODERATE_MEMBERS: u64 = 1 << 40;
+/// How long a purge can wait for confirmation before it has to be run again
+const PURGE_EXPIRY: time::Duration = time::Duration::from_secs(5 * 60);
+/// Pause between deleting messages of a purge, to stay clear of the rate limit
+const PURGE_DELETE_DELAY: time::Duration = time::Duration::from_millis(250);
+
+/// Purge waiting to be confirmed, kept on the buffer it was run in
+struct PendingPurge {
+    channel_id: ChannelId,
+    messages: Vec<MessageId>,
+    reason: Option<String>,
+    created: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub enum Action {
+    Kick,
+    Ban,
+    Unban,
+    /// Time a member out for a while, or lift the timeout
+    Timeout(Option<Duration>),
+}
+
+impl Action {
+    fn permission(&self) -> Option<Permissions> {
+        match self {
+            Action::Kick => Some(Permissions::KICK_MEMBERS),
+            Action::Ban | Action::Unban => Some(Permissions::BAN_MEMBERS),
+            Action::Timeout(_) => Some(Permissions::from_bits_truncate(MODERATE_MEMBERS)),
+        }
+    }
+
+    fn describe(&self, user: &str) -> String {
+        match self {
+            Action::Kick => format!("Kicked {}", user),
+            Action::Ban => format!("Banned {}", user),
+            Action::Unban => format!("Unbanned {}", user),
+            Action::Timeout(Some(duration)) => format!(
+                "Timed out {} until {}",
+                user,
+                (Utc::now() + *duration)
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+            ),
+            Action::Timeout(None) => format!("Removed the timeout of {}", user),
+        }
+    }
+}
+
+fn format_reason(reason: &Option<String>) -> String {
+    match reason {
+        Some(reason) => format!(" (reason: {})", reason),
+        None => String::new(),
+    }
+}
+
+/// Find a banned user, who is usually not in the cache, by id, name or tag
+fn search_banned_user(ctx: &Context, guild_id: GuildId, name: &str) -> Option<User> {
+    let bans = guild_id.bans(ctx).ok()?;
+    let name = name.trim_start_matches('@').to_lowercase();
+    bans.into_iter().map(|ban| ban.user).find(|user| {
+        user.id.0.to_string() == name
+            || user.name.to_lowercase() == name
+            || user.tag().to_lowercase() == name
+    })
+}
+
+/// Kick, ban, unban or time out a user of the guild of a channel buffer
+pub fn moderate(buffer: &Buffer, action: Action, target: String, reason: Option<String>) {
+    let guild_id = match buffer.guild_id() {
+        Some(guild_id) => guild_id,
+        None => {
+            plugin_print("Moderation commands must be run in a guild channel");
+            return;
+        },
+    };
+    if let Some(permission) = action.permission() {
+        if !buffer.has_permission(permission) {
+            buffer.print(&format!(
+                "{}\tYou do not have permission to do that in this guild",
+                buffer.get_weechat().get_prefix("error"),
+            ));
+            return;
+        }
+    }
+    let sealed_buffer = buffer.seal();
+
+    account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let user = match action {
+            Action::Unban => search_banned_user(ctx, guild_id, &target),
+            _ => utils::search_user(ctx, Some(guild_id), &target),
+        };
+        let user = match user {
+            Some(user) => user,
+            None => {
+                plugin_print(&format!("Could not find user {:?}", target));
+                return;
+            },
+        };
+
+        let (method, path, body) = match action {
+            Action::Kick => (
+                Method::DELETE,
+                format!("/guilds/{}/members/{}", guild_id.0, user.id.0),
+                None,
+            ),
+            Action::Ban => (
+                Method::PUT,
+                format!("/guilds/{}/bans/{}", guild_id.0, user.id.0),
+                Some(JsonValue::new_object()),
+            ),
+            Action::Unban => (
+                Method::DELETE,
+                format!("/guilds/{}/bans/{}", guild_id.0, user.id.0),
+                None,
+            ),
+            Action::Timeout(duration) => (
+                Method::PATCH,
+                format!("/guilds/{}/members/{}", guild_id.0, user.id.0),
+                Some(json::object! {
+                    "communication_disabled_until" => duration
+                        .map(|duration| (Utc::now() + duration).to_rfc3339()),
+                }),
+            ),
+        };
+
+        let line = match rest::request_with_reason(ctx, method, &path, body, reason.as_deref()) {
+            Ok(_) => format!("{}{}", action.describe(&user.tag()), format_reason(&reason)),
+            Err(e) => format!("Unable to moderate {}: {}", user.tag(), e),
+        };
+        on_main(move |weecord| {
+            let buffer = sealed_buffer.unseal(weecord);
+            let prefix = weecord.get_prefix("network").into_owned();
+            buffer.print(&format!("{}\t{}", prefix, line));
+        });
+    });
+}
+
+/// Parse a timeout duration, `clear` lifts the timeout
+pub fn parse_timeout(input: &str) -> Result<Option<Duration>, String> {
+    if input == "clear" {
+        return Ok(None);
+    }
+    match utils::parse_duration(input) {
+        Some(duration) if duration > Duration::days(MAX_TIMEOUT_DAYS) => Err(format!(
+            "Timeouts can last at most {} days",
+            MAX_TIMEOUT_DAYS
+        )),
+        Some(duration) => Ok(Some(duration)),
+        None => Err("timeout requires a duration such as 10m, 1h or 7d, or clear".to_owned()),
+    }
+}
+
+/// Find the last `count` messages of a channel, optionally only those of one user, and ask for
+/// confirmation before deleting them
+pub fn purge(buffer: &Buffer, count: usize, target: Option<String>, reason: Option<String>) {
+    let (guild_id, channel_id) = match (buffer.guild_id(), buffer.channel_id()) {
+        (Some(guild_id), Some(channel_id)) => (guild_id, channel_id),
+        _ => {
+            plugin_print("Moderation commands must be run in a guild channel");
+            return;
+        },
+    };
+    if !buffer.has_permission(Permissions::MANAGE_MESSAGES) {
+        buffer.print(&format!(
+            "{}\tYou do not have permission to delete messages in this channel",
+            buffer.get_weechat().get_prefix("error"),
+        ));
+        return;
+    }
+    let buffer_name = buffer.get_name().into_owned();
+    let sealed_buffer = buffer.seal();
+
+    account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let user = match &target {
+            Some(target) => match utils::search_user(ctx, Some(guild_id), target) {
+                Some(user) => Some(user),
+                None => {
+                    plugin_print(&format!("Could not find user {:?}", target));
+                    return;
+                },
+            },
+            None => None,
+        };
+
+        let mut messages = Vec::new();
+        let mut before = None;
+        let mut searched = 0;
+        while messages.len() < count && searched < PURGE_SEARCH_LIMIT {
+            let page = channel_id.messages(ctx, |retriever| match before {
+                Some(before) => retriever.before(before).limit(100),
+                None => retriever.limit(100),
+            });
+            let page = match page {
+                Ok(page) if !page.is_empty() => page,
+                _ => break,
+            };
+            searched += page.len();
+            before = page.last().map(|msg| msg.id);
+            messages.extend(
+                page.into_iter()
+                    .filter(|msg| user.as_ref().map(|u| u.id == msg.author.id).unwrap_or(true))
+                    .map(|msg| msg.id),
+            );
+        }
+        messages.truncate(count);
+
+        let line = if messages.is_empty() {
+            "No messages to purge".to_owned()
+        } else {
+            format!(
+                "About to delete {} messages{}{}, run /discord purge confirm to delete them",
+                messages.len(),
+                user.as_ref()
+                    .map(|user| format!(" by {}", user.tag()))
+                    .unwrap_or_default(),
+                format_reason(&reason)
+            )
+        };
+
+        on_main(move |weecord| {
+            if !messages.is_empty() {
+                if let Some(buffer) = weecord.buffer_manager.get_buffer(&buffer_name) {
+                    buffer.set_list(PendingPurge {
+                        channel_id,
+                        messages,
+                        reason,
+                        created: Instant::now(),
+                    });
+                }
+            }
+            let buffer = sealed_buffer.unseal(weecord);
+            let prefix = weecord.get_prefix("network").into_owned();
+            buffer.print(&format!("{}\t{}", prefix, line));
+        });
+    });
+}
+
+/// Delete the messages of the purge waiting for confirmation in a buffer
+pub fn confirm_purge(weecord: &Discord, buffer: &Buffer) {
+    let purge = weecord
+        .buffer_manager
+        .get_buffer(&buffer.get_name())
+        .and_then(|buffer| buffer.take_list::<PendingPurge>());
+    let purge = match purge {
+        Some(purge) if purge.created.elapsed() < PURGE_EXPIRY => purge,
+        Some(_) => {
+            buffer.print(&format!(
+                "{}\tThe purge expired, run it again to delete the messages",
+                weecord.get_prefix("error"),
+            ));
+            return;
+        },
+        None => {
+            buffer.print(&format!(
+                "{}\tThere is no purge to confirm in this buffer",
+                weecord.get_prefix("error"),
+            ));
+            return;
+        },
+    };
+    let sealed_buffer = buffer.seal();
+
+    account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+
+        let mut deleted = 0;
+        for (i, message_id) in purge.messages.iter().enumerate() {
+            if i > 0 {
+                thread::sleep(PURGE_DELETE_DELAY);
+            }
+            let path = format!("/channels/{}/messages/{}", purge.channel_id.0, message_id.0);
+            let result = rest::request_with_reason(
+                ctx,
+                Method::DELETE,
+                &path,
+                None,
+                purge.reason.as_deref(),
+            );
+            if result.is_ok() {
+                deleted += 1;
+            }
+        }
+
+        let line = format!(
+            "Deleted {} of {} messages{}",
+            deleted,
+            purge.messages.len(),
+            format_reason(&purge.reason)
+        );
+        on_main(move |weecord| {
+            let buffer = sealed_buffer.unseal(weecord);
+            let prefix = weecord.get_prefix("network").into_owned();
+            buffer.print(&format!("{}\t{}", prefix, line));
+        });
+    });
+}
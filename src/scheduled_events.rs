@@ -0,0 +1,286 @@
+//! Guild scheduled events, fetched with a plain api request as serenity predates them
+//!
+//! An events buffer lists the upcoming events of a guild, entering the number of an event
+//! toggles whether the current user is interested in it.
+use crate::{
+    buffers,
+    discord::{self, account, rest},
+    on_main, plugin_print, utils,
+    utils::BufferExt,
+    Discord,
+};
+use chrono::{DateTime, Local};
+use json::JsonValue;
+use serenity::{model::prelude::*, prelude::Context};
+use std::collections::HashSet;
+use weechat::Weechat;
+
+/// Entity type of events that take place outside of Discord
+const EXTERNAL_ENTITY_TYPE: u64 = 3;
+/// Status of events that have started
+const ACTIVE_STATUS: u64 = 2;
+
+#[derive(Debug, Clone)]
+pub struct ScheduledEvent {
+    pub id: u64,
+    pub name: String,
+    pub description: Option<String>,
+    pub start: Option<DateTime<Local>>,
+    pub end: Option<DateTime<Local>>,
+    pub location: String,
+    pub interested_count: u64,
+    pub interested: bool,
+    pub active: bool,
+}
+
+impl ScheduledEvent {
+    fn from_json(
+        ctx: &Context,
+        event: &JsonValue,
+        interested: &HashSet<u64>,
+    ) -> Option<ScheduledEvent> {
+        let id = event["id"].as_str()?.parse().ok()?;
+        let time = |field: &str| {
+            event[field]
+                .as_str()
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                .map(|time| time.with_timezone(&Local))
+        };
+
+        let location = if event["entity_type"].as_u64() == Some(EXTERNAL_ENTITY_TYPE) {
+            event["entity_metadata"]["location"]
+                .as_str()
+                .unwrap_or_default()
+                .to_owned()
+        } else {
+            event["channel_id"]
+                .as_str()
+                .and_then(|id| id.parse().ok())
+                .map(|id| {
+                    let channel_id = ChannelId(id);
+                    match channel_id.to_channel_cached(&ctx.cache) {
+                        Some(Channel::Guild(channel)) => format!("🔊{}", channel.read().name),
+                        _ => channel_id.0.to_string(),
+                    }
+                })
+                .unwrap_or_default()
+        };
+
+        Some(ScheduledEvent {
+            id,
+            name: event["name"].as_str()?.to_owned(),
+            description: event["description"]
+                .as_str()
+                .filter(|description| !description.is_empty())
+                .map(ToOwned::to_owned),
+            start: time("scheduled_start_time"),
+            end: time("scheduled_end_time"),
+            location,
+            interested_count: event["user_count"].as_u64().unwrap_or(0),
+            interested: interested.contains(&id),
+            active: event["status"].as_u64() == Some(ACTIVE_STATUS),
+        })
+    }
+}
+
+pub fn buffer_id_for_events(guild_id: GuildId) -> String {
+    account::namespaced(format!("Events.{}", guild_id.0))
+}
+
+/// Fetch the upcoming and running events of a guild
+pub fn fetch_events(ctx: &Context, guild_id: GuildId) -> rest::Result<Vec<ScheduledEvent>> {
+    let events = rest::get(
+        ctx,
+        &format!(
+            "/guilds/{}/scheduled-events?with_user_count=true",
+            guild_id.0
+        ),
+    )?;
+    // Not knowing which events the user is interested in only affects the marker
+    let interested: HashSet<u64> = rest::get(
+        ctx,
+        &format!("/users/@me/scheduled-events?guild_ids={}", guild_id.0),
+    )
+    .map(|interested| {
+        interested
+            .members()
+            .filter_map(|entry| entry["guild_scheduled_event_id"].as_str())
+            .filter_map(|id| id.parse().ok())
+            .collect()
+    })
+    .unwrap_or_default();
+
+    let mut events: Vec<_> = events
+        .members()
+        .filter_map(|event| ScheduledEvent::from_json(ctx, event, &interested))
+        .collect();
+    events.sort_by_key(|event| event.start);
+    Ok(events)
+}
+
+/// Open a buffer listing the events of a guild
+pub fn open_events(guild_id: GuildId) {
+    account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let events = match fetch_events(ctx, guild_id) {
+            Ok(events) => events,
+            Err(e) => {
+                plugin_print(&format!("Unable to fetch events: {}", e));
+                return;
+            },
+        };
+        let guild_name = guild_id
+            .to_guild_cached(&ctx.cache)
+            .map(|guild| guild.read().name.clone())
+            .unwrap_or_default();
+
+        on_main(move |weecord| {
+            create_events_buffer(weecord, guild_id, &guild_name, events);
+        });
+    });
+}
+
+fn create_events_buffer(
+    weecord: &Discord,
+    guild_id: GuildId,
+    guild_name: &str,
+    events: Vec<ScheduledEvent>,
+) {
+    let buffer = buffers::create_list_buffer(
+        weecord,
+        &buffer_id_for_events(guild_id),
+        "events",
+        &format!("{} events", guild_name),
+        &format!("Events in {}", guild_name),
+        &[
+            ("guildid", guild_id.0.to_string().as_str()),
+            ("guild_name", guild_name),
+            ("server", guild_name),
+        ],
+    );
+
+    draw_events(weecord, &buffer, &events);
+    buffer.set_list(events);
+}
+
+fn draw_events(weechat: &Weechat, buffer: &weechat::Buffer, events: &[ScheduledEvent]) {
+    buffer.clear();
+    if events.is_empty() {
+        buffer.print("There are no upcoming events");
+        return;
+    }
+
+    for (i, event) in events.iter().enumerate() {
+        for line in format_event(weechat, i + 1, event) {
+            buffer.print(&line);
+        }
+    }
+    buffer.print("");
+    buffer.print("Enter the number of an event to toggle whether you are interested");
+}
+
+fn format_event(weechat: &Weechat, number: usize, event: &ScheduledEvent) -> Vec<String> {
+    let mut title = format!(
+        "{}\t{}",
+        utils::colorize_string(weechat, "chat_delimiters", &format!("[{}]", number)),
+        utils::colorize_string(weechat, "bold", &event.name)
+    );
+    if event.active {
+        title.push(' ');
+        title.push_str(&utils::colorize_string(weechat, "green", "[happening now]"));
+    }
+    if event.interested {
+        title.push(' ');
+        title.push_str(&utils::colorize_string(weechat, "yellow", "[interested]"));
+    }
+
+    let mut time = event
+        .start
+        .map(|start| start.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default();
+    if let Some(end) = event.end {
+        time.push_str(&format!(" - {}", end.format("%Y-%m-%d %H:%M")));
+    }
+
+    let mut lines = vec![
+        title,
+        format!("\t  {} | {}", time, event.location),
+        format!("\t  {} interested", event.interested_count),
+    ];
+    if let Some(description) = &event.description {
+        lines.extend(description.lines().map(|line| format!("\t  {}", line)));
+    }
+    lines
+}
+
+/// Toggle interest in an event, by its number in an events buffer
+pub fn toggle_interest(weecord: &Discord, events_buffer: &weechat::Buffer, arg: &str) {
+    let buffer_name = events_buffer.get_name().into_owned();
+    let events = weecord
+        .buffer_manager
+        .get_buffer(&buffer_name)
+        .and_then(|buffer| buffer.with_list(|events: &mut Vec<ScheduledEvent>| events.clone()));
+    let events = match events {
+        Some(events) => events,
+        None => return,
+    };
+    let event = match arg.parse::<usize>() {
+        Ok(n) if n >= 1 && n <= events.len() => events[n - 1].clone(),
+        _ => {
+            events_buffer.print(&format!(
+                "{}\tNo event {:?}, enter the number of an event",
+                weecord.get_prefix("error"),
+                arg
+            ));
+            return;
+        },
+    };
+    let guild_id = match events_buffer.guild_id() {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
+
+    account::spawn(move || {
+        let ctx = &match discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let path = format!(
+            "/guilds/{}/scheduled-events/{}/users/@me",
+            guild_id.0, event.id
+        );
+        let result = if event.interested {
+            rest::delete(ctx, &path)
+        } else {
+            rest::put(ctx, &path, JsonValue::new_object())
+        };
+        if let Err(e) = result {
+            plugin_print(&format!(
+                "Unable to change interest in {}: {}",
+                event.name, e
+            ));
+            return;
+        }
+
+        on_main(move |weecord| {
+            let buffer = match weecord.buffer_manager.get_buffer(&buffer_name) {
+                Some(buffer) => buffer,
+                None => return,
+            };
+            buffer.with_list(|events: &mut Vec<ScheduledEvent>| {
+                if let Some(changed) = events.iter_mut().find(|it| it.id == event.id) {
+                    changed.interested = !event.interested;
+                    if changed.interested {
+                        changed.interested_count += 1;
+                    } else {
+                        changed.interested_count = changed.interested_count.saturating_sub(1);
+                    }
+                }
+                draw_events(weecord, &buffer, events);
+            });
+        });
+    });
+}
@@ -1,15 +1,31 @@
-use crate::Discord;
+use crate::{discord::account, Discord};
 use crossbeam_channel::{unbounded, Sender};
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
 use std::{
-    any::Any, cell::RefCell, collections::VecDeque, mem::transmute, sync::Arc, thread,
-    time::Duration,
+    any::Any,
+    cell::RefCell,
+    collections::VecDeque,
+    fs::File,
+    io::{self, Read, Write},
+    mem::transmute,
+    os::unix::io::FromRawFd,
+    rc::Rc,
+    sync::Arc,
+    thread,
 };
-use weechat::Weechat;
+use weechat::{FdHookMode, Weechat};
 
 /// Created upon sync initialization, must not be dropped while the plugin is running
-pub struct SyncHandle(weechat::TimerHook<()>);
+pub struct SyncHandle(weechat::FdHook<(), File>);
+
+impl Drop for SyncHandle {
+    fn drop(&mut self) {
+        // Jobs queued after this are never run, dropping them lets blocked threads give up
+        *WAKER.lock() = None;
+        JOB_QUEUE.lock().borrow_mut().clear();
+    }
+}
 
 enum Job {
     Nonblocking(Box<dyn FnOnce(&Discord) + Send>),
@@ -22,30 +38,78 @@ enum Job {
 lazy_static! {
     static ref JOB_QUEUE: Mutex<RefCell<VecDeque<Job>>> = Mutex::new(RefCell::new(VecDeque::new()));
     static ref MAIN_THREAD: Arc<Mutex<Option<thread::ThreadId>>> = Arc::new(Mutex::new(None));
+    /// The write end of the pipe weechat watches, a byte is written for every queued job
+    static ref WAKER: Mutex<Option<File>> = Mutex::new(None);
+}
+
+thread_local! {
+    // Only ever set on the main thread, so every other thread sees `None`
+    static PLUGIN: RefCell<Option<Rc<Discord>>> = RefCell::new(None);
 }
 
 /// Initialize thread synchronization, this function must be called on the main thread
 pub fn init(weechat: &weechat::Weechat) -> SyncHandle {
     *MAIN_THREAD.lock() = Some(thread::current().id());
 
-    // TODO: Dynamic delay
-    SyncHandle(weechat.hook_timer(Duration::from_millis(25), 0, 0, |_, _, _| tick(), None))
+    let (reader, writer) = pipe().expect("Unable to create job pipe");
+    *WAKER.lock() = Some(writer);
+
+    SyncHandle(weechat.hook_fd(reader, FdHookMode::Read, |_, _, reader| drain(reader), None))
+}
+
+/// A pipe with both ends non-blocking, so neither waking nor draining can stall a thread
+fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for &fd in &fds {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+        Ok((File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])))
+    }
+}
+
+fn push_job(job: Job) {
+    let mut waker = WAKER.lock();
+    // The plugin is unloading, the job would never run
+    let waker = match waker.as_mut() {
+        Some(waker) => waker,
+        None => return,
+    };
+    JOB_QUEUE.lock().borrow_mut().push_back(job);
+    // A full pipe already guarantees a wake up, and every wake up drains the whole queue
+    let _ = waker.write(&[0]);
+}
+
+fn is_main_thread() -> bool {
+    *MAIN_THREAD.lock() == Some(thread::current().id())
 }
 
 pub fn on_main<F: 'static + FnOnce(&Discord) + Send>(cb: F) {
-    if std::thread::current().id() == MAIN_THREAD.lock().unwrap() {
+    match plugin() {
         // already on the main thread, run closure now
-        cb(unsafe { &crate::__PLUGIN.as_ref().unwrap() });
-    } else {
-        // queue closure for later
-        JOB_QUEUE
-            .lock()
-            .borrow_mut()
-            .push_back(Job::Nonblocking(Box::new(cb)));
+        Some(weecord) => cb(&weecord),
+        // queue closure for later, acting for the same account once it runs
+        None => {
+            let account = account::capture();
+            push_job(Job::Nonblocking(Box::new(move |weecord| {
+                let _account = account.enter();
+                cb(weecord)
+            })));
+        },
     }
 }
 
-pub fn on_main_blocking<R: Send, F: FnOnce(&Discord) -> R + Send, ER: 'static + Send>(cb: F) -> ER {
+/// Run a closure on the main thread and wait for its result
+///
+/// Returns `None` if the plugin is not loaded, as the closure can then never run
+pub fn on_main_blocking<R: Send, F: FnOnce(&Discord) -> R + Send, ER: 'static + Send>(
+    cb: F,
+) -> Option<ER> {
     let cb = unsafe {
         // This should be ok because the lifetime does not actually
         // have to be valid for 'static, just until the function returns
@@ -55,33 +119,80 @@ pub fn on_main_blocking<R: Send, F: FnOnce(&Discord) -> R + Send, ER: 'static +
         >(Box::new(cb))
     };
 
-    if std::thread::current().id() == MAIN_THREAD.lock().unwrap() {
-        cb(unsafe { &crate::__PLUGIN.as_ref().unwrap() })
+    if let Some(weecord) = plugin() {
+        Some(cb(&weecord))
+    } else if is_main_thread() {
+        // Still loading or already unloading, queueing the job would block weechat forever
+        None
     } else {
         let (tx, rx) = unbounded();
-        let job = Job::Blocking(Box::new(move |data| Box::new(cb(data))), tx);
-        JOB_QUEUE.lock().borrow_mut().push_back(job);
+        let account = account::capture();
+        let job = Job::Blocking(
+            Box::new(move |data| {
+                let _account = account.enter();
+                Box::new(cb(data)) as Box<dyn Any + Send>
+            }),
+            tx,
+        );
+        push_job(job);
 
-        let rcv: Box<dyn Any + Send> = rx.recv().expect("rx can't fail");
-        *rcv.downcast::<ER>().expect("downcast can't fail")
+        // The job is dropped without running if the plugin is unloaded first
+        let rcv: Box<dyn Any + Send> = rx.recv().ok()?;
+        Some(*rcv.downcast::<ER>().expect("downcast can't fail"))
     }
 }
 
-fn tick() {
-    match JOB_QUEUE.lock().borrow_mut().pop_front() {
-        Some(Job::Nonblocking(cb)) => {
-            (cb)(unsafe { &crate::__PLUGIN.as_ref().unwrap() });
-        },
-        Some(Job::Blocking(cb, tx)) => {
-            let result = (cb)(unsafe { &crate::__PLUGIN.as_ref().unwrap() });
-            let _ = tx.send(result);
-        },
-        None => {},
+/// Run every queued job, in the order they were queued
+fn drain(reader: &mut File) {
+    let weecord = match plugin() {
+        Some(weecord) => weecord,
+        // Still initializing, the pipe stays readable until the plugin is stored
+        None => return,
+    };
+
+    let mut buf = [0; 256];
+    while let Ok(n) = reader.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+    }
+
+    // Take the jobs out first so threads are not blocked from queueing while they run, jobs
+    // queued in the meantime wrote to the pipe and are run on the next wake up
+    let jobs = std::mem::replace(&mut *JOB_QUEUE.lock().borrow_mut(), VecDeque::new());
+    for job in jobs {
+        match job {
+            Job::Nonblocking(cb) => {
+                (cb)(&weecord);
+            },
+            Job::Blocking(cb, tx) => {
+                let result = (cb)(&weecord);
+                let _ = tx.send(result);
+            },
+        }
     }
 }
 
+/// Make the plugin available to jobs and callbacks, must be called on the main thread
+pub fn set_plugin(weecord: Rc<Discord>) {
+    PLUGIN.with(|plugin| *plugin.borrow_mut() = Some(weecord));
+}
+
+/// Forget the plugin when it is unloaded, so it can be dropped
+pub fn clear_plugin() -> Option<Rc<Discord>> {
+    PLUGIN.with(|plugin| plugin.borrow_mut().take())
+}
+
+/// The plugin, if called on the main thread while it is loaded
+pub fn plugin() -> Option<Rc<Discord>> {
+    PLUGIN.with(|plugin| plugin.borrow().clone())
+}
+
+/// Get the plugin from a callback, the reference to weechat ensures this runs on the main thread
+///
+/// Returns `None` for callbacks run while the plugin is being loaded or unloaded
 #[must_use]
-pub fn upgrade_plugin(weechat: &Weechat) -> &Discord {
+pub fn upgrade_plugin(weechat: &Weechat) -> Option<Rc<Discord>> {
     let _ = weechat;
-    unsafe { crate::__PLUGIN.as_ref().unwrap() }
+    plugin()
 }
@@ -7,7 +7,7 @@ use serenity::{
     model::{id::ChannelId, prelude::*},
     prelude::*,
 };
-use std::{borrow::Cow, sync::Arc};
+use std::{borrow::Cow, collections::HashMap, sync::Arc};
 use weechat::{Buffer, ConfigOption, Weechat};
 
 #[derive(Debug, Clone, Copy)]
@@ -74,6 +74,70 @@ pub fn format_user_status_prefix(weechat: &Weechat, status: Option<OnlineStatus>
     )
 }
 
+pub fn status_name(status: OnlineStatus) -> &'static str {
+    use OnlineStatus::*;
+    match status {
+        Online => "online",
+        Idle => "idle",
+        DoNotDisturb => "do not disturb",
+        Invisible => "invisible",
+        Offline => "offline",
+        __Nonexhaustive => unreachable!(),
+    }
+}
+
+/// Format a custom status as it would be shown in the client, eg "🙂 Out to lunch"
+pub fn format_custom_status(activity: &Activity) -> String {
+    let emoji = activity.emoji.as_ref().map(|emoji| {
+        if emoji.id.is_some() {
+            format!(":{}:", emoji.name)
+        } else {
+            emoji.name.clone()
+        }
+    });
+
+    match (emoji, &activity.state) {
+        (Some(emoji), Some(state)) => format!("{} {}", emoji, state),
+        (Some(emoji), None) => emoji,
+        (None, Some(state)) => state.clone(),
+        (None, None) => String::new(),
+    }
+}
+
+pub fn format_activity(activity: &Activity) -> String {
+    match activity.kind {
+        ActivityType::Playing => format!("Playing {}", activity.name),
+        ActivityType::Streaming => match &activity.url {
+            Some(url) => format!("Streaming {} ({})", activity.name, url),
+            None => format!("Streaming {}", activity.name),
+        },
+        ActivityType::Listening => match (&activity.details, &activity.state) {
+            (Some(details), Some(state)) => {
+                format!("Listening to {} by {} on {}", details, state, activity.name)
+            },
+            _ => format!("Listening to {}", activity.name),
+        },
+        ActivityType::Watching => format!("Watching {}", activity.name),
+        ActivityType::Custom => format_custom_status(activity),
+        _ => activity.name.clone(),
+    }
+}
+
+/// A one line summary of a users presence, eg "• idle | 🙂 Out to lunch"
+pub fn format_presence(weechat: &Weechat, presence: Option<&Presence>) -> String {
+    let status = presence.map(|p| p.status).unwrap_or(OnlineStatus::Offline);
+    let mut summary = format_user_status_prefix(weechat, Some(status)) + status_name(status);
+
+    if let Some(activity) = presence.and_then(|p| p.activity.as_ref()) {
+        let activity = format_activity(activity);
+        if !activity.is_empty() {
+            summary.push_str(" | ");
+            summary.push_str(&activity);
+        }
+    }
+    summary
+}
+
 pub fn nick_color(weechat: &Weechat, nick: &String) -> String {
     weechat
         .info_get("nick_color_name", nick)
@@ -146,6 +210,8 @@ pub trait BufferExt {
 
     fn nicks_loaded(&self) -> bool;
     fn set_nicks_loaded(&self);
+
+    fn has_permission(&self, permission: Permissions) -> bool;
 }
 
 impl BufferExt for Buffer {
@@ -176,6 +242,15 @@ impl BufferExt for Buffer {
     fn set_nicks_loaded(&self) {
         self.set_localvar("loaded_nicks", "true");
     }
+
+    /// Buffers without known permissions (eg private channels) allow everything
+    fn has_permission(&self, permission: Permissions) -> bool {
+        self.get_localvar("permissions")
+            .and_then(|bits| bits.parse::<u64>().ok())
+            .map(Permissions::from_bits_truncate)
+            .map(|permissions| permissions.contains(permission))
+            .unwrap_or(true)
+    }
 }
 
 pub fn pins_for_channel(buffer: &MessageManager) -> Option<ChannelId> {
@@ -189,15 +264,82 @@ pub fn set_pins_for_channel(buffer: &MessageManager, channel: ChannelId) {
     buffer.set_localvar("pins_for_channel", &channel.0.to_string());
 }
 
-/// Find the highest hoisted role (used for the user group) and the highest role (used for user coloring)
-pub fn find_highest_roles(cache: &CacheRwLock, member: &Member) -> Option<(Role, Role)> {
-    let mut roles = member.roles(cache)?;
+/// Compute the permissions of a member in a guild channel from the roles of the member and the
+/// overwrites of the channel
+pub fn channel_permissions(guild: &Guild, member: &Member, channel: &GuildChannel) -> Permissions {
+    let user_id = member.user.read().id;
+    if guild.owner_id == user_id {
+        return Permissions::all();
+    }
+
+    let everyone = RoleId(guild.id.0);
+    let mut permissions = guild
+        .roles
+        .get(&everyone)
+        .map(|role| role.permissions)
+        .unwrap_or_else(Permissions::empty);
+    for role in member.roles.iter().filter_map(|id| guild.roles.get(id)) {
+        permissions |= role.permissions;
+    }
+
+    if permissions.contains(Permissions::ADMINISTRATOR) {
+        return Permissions::all();
+    }
+
+    // Overwrites are applied in order: @everyone, all roles of the member at once, the member
+    let mut everyone_overwrite = (Permissions::empty(), Permissions::empty());
+    let mut role_overwrite = (Permissions::empty(), Permissions::empty());
+    let mut member_overwrite = (Permissions::empty(), Permissions::empty());
+    for overwrite in &channel.permission_overwrites {
+        let target = match overwrite.kind {
+            PermissionOverwriteType::Role(role) if role == everyone => &mut everyone_overwrite,
+            PermissionOverwriteType::Role(role) if member.roles.contains(&role) => {
+                &mut role_overwrite
+            },
+            PermissionOverwriteType::Member(id) if id == user_id => &mut member_overwrite,
+            _ => continue,
+        };
+        target.0 |= overwrite.allow;
+        target.1 |= overwrite.deny;
+    }
+    for (allow, deny) in &[everyone_overwrite, role_overwrite, member_overwrite] {
+        permissions = (permissions & !*deny) | *allow;
+    }
+
+    // Nothing else can be done in a channel that can't be seen
+    if !permissions.contains(Permissions::READ_MESSAGES) {
+        return Permissions::empty();
+    }
+
+    permissions
+}
+
+/// The permissions of the current user in a guild channel, using the cached guild and member
+pub fn current_user_permissions(
+    cache: &CacheRwLock,
+    channel: &GuildChannel,
+) -> Option<Permissions> {
+    let user_id = cache.read().user.id;
+    let guild = channel.guild_id.to_guild_cached(cache)?;
+    let guild = guild.read();
+    match guild.members.get(&user_id) {
+        Some(member) => Some(channel_permissions(&guild, member, channel)),
+        None => channel.permissions_for_user(cache, user_id).ok(),
+    }
+}
+
+/// Find the highest hoisted role (used for the user group) and the highest colored role (used for
+/// user coloring)
+pub fn find_highest_roles(cache: &CacheRwLock, member: &Member) -> (Option<Role>, Option<Role>) {
+    let mut roles = match member.roles(cache) {
+        Some(roles) => roles,
+        None => return (None, None),
+    };
     roles.sort();
-    let highest = roles.last();
 
-    let highest_hoisted = roles.iter().filter(|role| role.hoist).collect::<Vec<_>>();
-    let highest_hoisted = highest_hoisted.last().cloned();
-    Some((highest_hoisted?.clone(), highest?.clone()))
+    let highest_hoisted = roles.iter().rev().find(|role| role.hoist).cloned();
+    let highest_colored = roles.iter().rev().find(|role| role.colour.0 != 0).cloned();
+    (highest_hoisted, highest_colored)
 }
 
 pub fn unique_id(guild: Option<GuildId>, channel: ChannelId) -> String {
@@ -230,27 +372,48 @@ pub fn parse_id(id: &str) -> Option<GuildOrChannel> {
     }
 }
 
-pub fn get_irc_mode(weechat: &weechat::Weechat) -> bool {
-    crate::upgrade_plugin(weechat).config.irc_mode.value()
+/// Longest duration accepted by `parse_duration`, so adding it to the current time cannot overflow
+const MAX_DURATION_DAYS: i64 = 365 * 100;
+
+/// Parse a short duration such as "90s", "30m", "1h" or "7d"
+pub fn parse_duration(input: &str) -> Option<chrono::Duration> {
+    let input = input.trim();
+    let split = input.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = input.split_at(split);
+    let amount = amount.parse::<i64>().ok()?;
+
+    let unit_seconds = match unit {
+        "s" | "sec" | "secs" => 1,
+        "m" | "min" | "mins" => 60,
+        "h" | "hour" | "hours" => 60 * 60,
+        "d" | "day" | "days" => 24 * 60 * 60,
+        _ => return None,
+    };
+    let seconds = amount.checked_mul(unit_seconds)?;
+    if seconds > MAX_DURATION_DAYS * 24 * 60 * 60 {
+        return None;
+    }
+    Some(chrono::Duration::seconds(seconds))
+}
+
+pub fn get_irc_mode(weecord: &crate::Discord) -> bool {
+    weecord.config.irc_mode.value()
 }
 
 pub fn buffer_id_for_guild(id: GuildId) -> String {
-    format!("{}", id.0)
+    crate::discord::account::namespaced(format!("{}", id.0))
 }
 
 pub fn buffer_id_for_channel(guild_id: Option<GuildId>, channel_id: ChannelId) -> String {
-    if let Some(guild_id) = guild_id {
+    crate::discord::account::namespaced(if let Some(guild_id) = guild_id {
         format!("{}.{}", guild_id, channel_id.0)
     } else {
         format!("Private.{}", channel_id.0)
-    }
+    })
 }
 
-pub unsafe fn buffer_from_ptr(buffer_ptr: *mut std::ffi::c_void) -> Buffer {
-    Buffer::from_ptr(
-        crate::__PLUGIN.as_mut().unwrap().weechat.as_ptr(),
-        buffer_ptr as *mut _,
-    )
+pub unsafe fn buffer_from_ptr(weechat: &Weechat, buffer_ptr: *mut std::ffi::c_void) -> Buffer {
+    Buffer::from_ptr(weechat.as_ptr(), buffer_ptr as *mut _)
 }
 
 pub fn buffer_is_muted(buffer: &Buffer) -> bool {
@@ -310,8 +473,7 @@ pub fn flatten_guilds(
         match item {
             GuildOrChannel::Guild(guild_id) => {
                 let guild_channels = guild_id.channels(ctx).unwrap_or_default();
-                let mut guild_channels = guild_channels.values().collect::<Vec<_>>();
-                guild_channels.sort_by_key(|g| g.position);
+                let guild_channels = sort_guild_channels(&guild_channels);
                 channels
                     .entry(Some(*guild_id))
                     .or_default()
@@ -326,6 +488,96 @@ pub fn flatten_guilds(
     channels
 }
 
+/// Sort channels the way the client shows them, uncategorized channels first, then every
+/// category by position with its channels below it, voice channels after text channels
+pub fn sort_guild_channels(channels: &HashMap<ChannelId, GuildChannel>) -> Vec<&GuildChannel> {
+    let category_key = |channel: &GuildChannel| {
+        if channel.kind == ChannelType::Category {
+            Some((channel.position, channel.id))
+        } else {
+            channel
+                .category_id
+                .and_then(|id| channels.get(&id))
+                .map(|category| (category.position, category.id))
+        }
+    };
+
+    let mut sorted: Vec<_> = channels.values().collect();
+    sorted.sort_by_key(|channel| {
+        (
+            category_key(channel),
+            channel.kind != ChannelType::Category,
+            channel.kind == ChannelType::Voice || channel.kind == ChannelType::Stage,
+            channel.position,
+            channel.id,
+        )
+    });
+    sorted
+}
+
+/// The name of the category a channel is in
+pub fn category_name(cache: &CacheRwLock, channel: &GuildChannel) -> Option<String> {
+    channel
+        .category_id
+        .and_then(|id| id.to_channel_cached(cache))
+        .and_then(|category| match category {
+            Channel::Category(category) => Some(category.read().name.clone()),
+            Channel::Guild(category) => Some(category.read().name.clone()),
+            _ => None,
+        })
+}
+
+/// Find a user by id, mention, tag (name#1234), guild nickname or username
+pub fn search_user(ctx: &Context, guild_id: Option<GuildId>, name: &str) -> Option<User> {
+    let raw_id = name
+        .trim_start_matches("<@")
+        .trim_start_matches('!')
+        .trim_end_matches('>');
+    if let Ok(id) = raw_id.parse::<u64>() {
+        return UserId(id).to_user(ctx).ok();
+    }
+
+    let name = name.trim_start_matches('@').to_lowercase();
+    let user_matches =
+        |user: &User| user.name.to_lowercase() == name || user.tag().to_lowercase() == name;
+
+    if let Some(guild) = guild_id.and_then(|id| id.to_guild_cached(ctx)) {
+        for member in guild.read().members.values() {
+            let user = member.user.read();
+            let nick_matches = member
+                .nick
+                .as_ref()
+                .map(|nick| nick.to_lowercase() == name)
+                .unwrap_or(false);
+            if nick_matches || user_matches(&user) {
+                return Some(user.clone());
+            }
+        }
+    }
+
+    for user in ctx.cache.read().users.values() {
+        let user = user.read();
+        if user_matches(&user) {
+            return Some(user.clone());
+        }
+    }
+    None
+}
+
+/// Resolve a `[n|id]` argument to a message, `n` counts back from the newest message in the
+/// buffer and no argument is the newest message
+pub fn message_id_for_arg(buffer: &MessageManager, arg: Option<&str>) -> Option<MessageId> {
+    match arg {
+        None => buffer.nth_last_message(1).map(|msg| msg.id),
+        Some(arg) => match arg.parse::<u64>() {
+            // Snowflakes are far larger than the number of messages a buffer holds
+            Ok(n) if n < 1_000_000 => buffer.nth_last_message(n as usize).map(|msg| msg.id),
+            Ok(id) => Some(MessageId(id)),
+            Err(_) => None,
+        },
+    }
+}
+
 pub fn get_users_nth_message(
     ctx: &Context,
     channel: ChannelId,
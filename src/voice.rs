@@ -0,0 +1,367 @@
+//! Display of who is connected to voice channels, no audio is handled
+use crate::{discord::account, on_main, utils, utils::BufferExt, Discord};
+use serenity::{model::prelude::*, prelude::*};
+use std::sync::Arc;
+use weechat::{ConfigOption, Weechat};
+
+pub fn buffer_id_for_voice(channel_id: ChannelId) -> String {
+    crate::discord::account::namespaced(format!("Voice.{}", channel_id.0))
+}
+
+fn voice_channel_for_buffer(buffer: &weechat::Buffer) -> Option<ChannelId> {
+    buffer
+        .get_localvar("voice_channel")
+        .and_then(|id| id.parse().ok())
+        .map(ChannelId)
+}
+
+/// All voice channels of a guild, in the order shown by the client
+fn voice_channels(guild: &Guild) -> Vec<Arc<RwLock<GuildChannel>>> {
+    let mut channels: Vec<_> = guild
+        .channels
+        .values()
+        .filter(|channel| channel.read().kind == ChannelType::Voice)
+        .cloned()
+        .collect();
+    channels.sort_by_key(|channel| channel.read().position);
+    channels
+}
+
+/// All stage channels of a guild, in the order shown by the client
+fn stage_channels(guild: &Guild) -> Vec<Arc<RwLock<GuildChannel>>> {
+    let mut channels: Vec<_> = guild
+        .channels
+        .values()
+        .filter(|channel| channel.read().kind == ChannelType::Stage)
+        .cloned()
+        .collect();
+    channels.sort_by_key(|channel| channel.read().position);
+    channels
+}
+
+fn participants(guild: &Guild, channel_id: ChannelId) -> Vec<&VoiceState> {
+    let mut participants: Vec<_> = guild
+        .voice_states
+        .values()
+        .filter(|state| state.channel_id == Some(channel_id))
+        .collect();
+    participants.sort_by_key(|state| participant_name(guild, state.user_id).to_lowercase());
+    participants
+}
+
+fn participant_name(guild: &Guild, user_id: UserId) -> String {
+    guild
+        .members
+        .get(&user_id)
+        .map(|member| member.display_name().into_owned())
+        .unwrap_or_else(|| user_id.0.to_string())
+}
+
+fn format_participant(weechat: &Weechat, guild: &Guild, state: &VoiceState) -> String {
+    let name = participant_name(guild, state.user_id);
+    let mut line = utils::format_nick_color(weechat, &name);
+
+    if state.self_stream.unwrap_or(false) {
+        line.push(' ');
+        line.push_str(&utils::colorize_string(weechat, "red", "[live]"));
+    }
+    for flag in muted_flags(state) {
+        line.push_str(&format!(" [{}]", flag));
+    }
+    line
+}
+
+fn muted_flags(state: &VoiceState) -> Vec<&'static str> {
+    let mut flags = Vec::new();
+    if state.mute || state.self_mute {
+        flags.push("muted");
+    }
+    if state.deaf || state.self_deaf {
+        flags.push("deafened");
+    }
+    flags
+}
+
+/// List the voice and stage channels of a guild with their participants in the nicklist of a
+/// buffer, nicks are plain text so the flags are part of the name
+fn update_voice_nicklist(weechat: &Weechat, buffer: &weechat::Buffer, guild: &Guild) {
+    buffer.enable_nicklist();
+    buffer.nicklist_remove_all();
+
+    let channels = voice_channels(guild)
+        .into_iter()
+        .chain(stage_channels(guild));
+    for (position, channel) in channels.enumerate() {
+        let channel = channel.read();
+        // Prefixed with the position so weechat keeps the order of the client
+        let group = buffer.add_group(
+            &format!("{:05}|{}", position, channel.name),
+            "bold",
+            true,
+            None,
+        );
+        for state in participants(guild, channel.id) {
+            let name = participant_name(guild, state.user_id);
+            let mut nick = name.clone();
+            if state.self_stream.unwrap_or(false) {
+                nick.push_str(" [live]");
+            }
+            // Audience members of stage channels are suppressed until they are invited to speak
+            if channel.kind == ChannelType::Stage && state.suppress {
+                nick.push_str(" [listening]");
+            }
+            for flag in muted_flags(state) {
+                nick.push_str(&format!(" [{}]", flag));
+            }
+            buffer.add_nick(
+                weechat::NickArgs {
+                    name: &nick,
+                    color: &utils::nick_color(weechat, &name),
+                    ..Default::default()
+                },
+                Some(&group),
+            );
+        }
+    }
+}
+
+/// Lines describing every voice channel of a guild and who is connected to it
+fn overview_lines(weechat: &Weechat, guild: &Guild) -> Vec<String> {
+    let mut lines = Vec::new();
+    for channel in voice_channels(guild) {
+        let channel = channel.read();
+        let participants = participants(guild, channel.id);
+        if participants.is_empty() {
+            lines.push(format!("  {}", channel.name));
+        } else {
+            lines.push(format!(
+                "  {} ({})",
+                utils::colorize_string(weechat, "bold", &channel.name),
+                participants.len()
+            ));
+        }
+        for state in participants {
+            lines.push(format!(
+                "      {}",
+                format_participant(weechat, guild, state)
+            ));
+        }
+    }
+    lines
+}
+
+/// Lines describing every stage channel of a guild with its topic and speakers
+fn stage_lines(weechat: &Weechat, guild: &Guild) -> Vec<String> {
+    let mut lines = Vec::new();
+    for channel in stage_channels(guild) {
+        let channel = channel.read();
+        let mut line = format!(
+            "  {}",
+            utils::colorize_string(weechat, "bold", &channel.name)
+        );
+        if let Some(topic) = channel.topic.as_ref().filter(|topic| !topic.is_empty()) {
+            line.push_str(&format!(" | {}", topic));
+        }
+        lines.push(line);
+
+        // Audience members are suppressed until they are invited to speak
+        let (speakers, audience): (Vec<_>, Vec<_>) = participants(guild, channel.id)
+            .into_iter()
+            .partition(|state| !state.suppress);
+        if !speakers.is_empty() {
+            let speakers: Vec<_> = speakers
+                .iter()
+                .map(|state| format_participant(weechat, guild, state))
+                .collect();
+            lines.push(format!("      speaking: {}", speakers.join(", ")));
+        }
+        if !audience.is_empty() {
+            lines.push(format!("      {} listening", audience.len()));
+        }
+    }
+    lines
+}
+
+/// Print the voice channels of a guild and their participants to a buffer
+pub fn print_voice_overview(weecord: &Discord, buffer: &weechat::Buffer, guild_id: GuildId) {
+    let ctx = &match crate::discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+    let guild = match guild_id.to_guild_cached(&ctx.cache) {
+        Some(guild) => guild,
+        None => return,
+    };
+    let guild = guild.read();
+
+    // Remember who is where, so the first update after connecting can be described
+    if let Some(session) = account::session() {
+        let mut last_channels = session.voice_channels.lock();
+        for state in guild.voice_states.values() {
+            if let Some(channel_id) = state.channel_id {
+                last_channels.insert((guild_id, state.user_id), channel_id);
+            }
+        }
+    }
+
+    let prefix = weecord.get_prefix("network").into_owned();
+    let lines = overview_lines(weecord, &guild);
+    if !lines.is_empty() {
+        buffer.print(&format!("{}\tVoice channels:", prefix));
+        for line in lines {
+            buffer.print(&format!("{}\t{}", prefix, line));
+        }
+    }
+
+    let lines = stage_lines(weecord, &guild);
+    if !lines.is_empty() {
+        buffer.print(&format!("{}\tStage channels:", prefix));
+        for line in lines {
+            buffer.print(&format!("{}\t{}", prefix, line));
+        }
+    }
+}
+
+/// Print the voice overview to the buffer of a guild, its nicklist keeps showing who is
+/// connected afterwards
+pub fn print_guild_voice_overview(guild_id: GuildId) {
+    on_main(move |weecord| {
+        if let Some(buffer) =
+            weecord.buffer_search("weecord", &utils::buffer_id_for_guild(guild_id))
+        {
+            print_voice_overview(weecord, &buffer, guild_id);
+            if let Some(guild) =
+                crate::discord::get_ctx().and_then(|ctx| guild_id.to_guild_cached(&ctx.cache))
+            {
+                update_voice_nicklist(weecord, &buffer, &guild.read());
+            }
+        }
+    });
+}
+
+/// Open a read-only buffer listing the participants of a voice channel
+pub fn create_voice_buffer(weecord: &Discord, guild_id: GuildId, channel: &GuildChannel) {
+    let buffer = crate::buffers::create_list_buffer(
+        weecord,
+        &buffer_id_for_voice(channel.id),
+        "voice",
+        &format!("🔊{}", channel.name),
+        &format!("Voice channel {}", channel.name),
+        &[
+            ("guildid", guild_id.0.to_string().as_str()),
+            ("voice_channel", channel.id.0.to_string().as_str()),
+        ],
+    );
+
+    redraw_voice_buffer(weecord, &buffer);
+}
+
+fn redraw_voice_buffer(weechat: &Weechat, buffer: &weechat::Buffer) {
+    let ctx = &match crate::discord::get_ctx() {
+        Some(ctx) => ctx,
+        _ => return,
+    };
+    let (guild_id, channel_id) = match (buffer.guild_id(), voice_channel_for_buffer(buffer)) {
+        (Some(guild_id), Some(channel_id)) => (guild_id, channel_id),
+        _ => return,
+    };
+    let guild = match guild_id.to_guild_cached(&ctx.cache) {
+        Some(guild) => guild,
+        None => return,
+    };
+    let guild = guild.read();
+
+    buffer.clear();
+    let participants = participants(&guild, channel_id);
+    if participants.is_empty() {
+        buffer.print("Nobody is connected");
+        return;
+    }
+    buffer.print(&format!("{} connected:", participants.len()));
+    for state in participants {
+        buffer.print(&format!("  {}", format_participant(weechat, &guild, state)));
+    }
+}
+
+/// Update the voice nicklist of the guild buffer and any open voice buffers after a voice state
+/// change, describing joins, leaves and moves in the guild buffer if enabled
+pub fn handle_voice_state_update(guild_id: GuildId, state: VoiceState) {
+    let session = match account::session() {
+        Some(session) => session,
+        None => return,
+    };
+    let previous = {
+        let mut last_channels = session.voice_channels.lock();
+        match state.channel_id {
+            Some(channel_id) => last_channels.insert((guild_id, state.user_id), channel_id),
+            None => last_channels.remove(&(guild_id, state.user_id)),
+        }
+    };
+
+    on_main(move |weecord| {
+        let ctx = &match crate::discord::get_ctx() {
+            Some(ctx) => ctx,
+            _ => return,
+        };
+        let guild = match guild_id.to_guild_cached(&ctx.cache) {
+            Some(guild) => guild,
+            None => return,
+        };
+        let guild = guild.read();
+        let channel_name = |channel_id: ChannelId| {
+            guild
+                .channels
+                .get(&channel_id)
+                .map(|channel| channel.read().name.clone())
+                .unwrap_or_else(|| channel_id.0.to_string())
+        };
+
+        let msg = match (previous, state.channel_id) {
+            (None, Some(current)) => Some(format!(
+                "{} joined voice channel {}",
+                format_participant(weecord, &guild, &state),
+                channel_name(current)
+            )),
+            (Some(previous), None) => Some(format!(
+                "{} left voice channel {}",
+                utils::format_nick_color(weecord, &participant_name(&guild, state.user_id)),
+                channel_name(previous)
+            )),
+            (Some(previous), Some(current)) if previous != current => Some(format!(
+                "{} moved to voice channel {}",
+                format_participant(weecord, &guild, &state),
+                channel_name(current)
+            )),
+            // Only mute, deaf or streaming flags changed, the voice lists show those
+            _ => None,
+        };
+
+        // The voice lists are updated either way, only the guild buffer notice is optional
+        if let Some(buffer) =
+            weecord.buffer_search("weecord", &utils::buffer_id_for_guild(guild_id))
+        {
+            update_voice_nicklist(weecord, &buffer, &guild);
+            if let Some(msg) = msg.filter(|_| weecord.config.voice_events.value()) {
+                let prefix = weecord.get_prefix("network").into_owned();
+                buffer.print(&format!("{}\t{}", prefix, msg));
+            }
+        }
+
+        for channel_id in previous.iter().chain(state.channel_id.iter()) {
+            if let Some(buffer) =
+                weecord.buffer_search("weecord", &buffer_id_for_voice(*channel_id))
+            {
+                redraw_voice_buffer(weecord, &buffer);
+            }
+        }
+    });
+}
+
+/// Find a voice channel in a guild by name or id
+pub fn search_voice_channel(guild: &Guild, name: &str) -> Option<Arc<RwLock<GuildChannel>>> {
+    voice_channels(guild).into_iter().find(|channel| {
+        let channel = channel.read();
+        parsing::weechat_arg_strip(&channel.name).to_lowercase() == name.to_lowercase()
+            || channel.id.0.to_string() == name
+    })
+}
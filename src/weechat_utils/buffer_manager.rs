@@ -1,4 +1,4 @@
-use crate::weechat_utils::message_manager::MessageManager;
+use crate::{discord::account, weechat_utils::message_manager::MessageManager};
 use std::{cell::RefCell, collections::HashMap, sync::Arc};
 use weechat::Weechat;
 
@@ -22,11 +22,7 @@ impl BufferManager {
         }
 
         if let Some(buffer) = self.weechat.buffer_search("weecord", name) {
-            let msg_manager = MessageManager::new(buffer);
-            self.buffers
-                .borrow_mut()
-                .insert(name.into(), Arc::new(msg_manager));
-            Some(Arc::clone(self.buffers.borrow().get(name).unwrap()))
+            Some(self.track(name, MessageManager::new(buffer)))
         } else {
             None
         }
@@ -38,11 +34,7 @@ impl BufferManager {
         }
 
         if let Some(buffer) = self.weechat.buffer_search("weecord", name) {
-            let msg_manager = MessageManager::new(buffer);
-            self.buffers
-                .borrow_mut()
-                .insert(name.into(), Arc::new(msg_manager));
-            Arc::clone(self.buffers.borrow().get(name).unwrap())
+            self.track(name, MessageManager::new(buffer))
         } else {
             let msg_manager = MessageManager::new(self.weechat.buffer_new::<(), ()>(
                 name,
@@ -51,10 +43,57 @@ impl BufferManager {
                 None,
                 None,
             ));
-            self.buffers
-                .borrow_mut()
-                .insert(name.into(), Arc::new(msg_manager));
-            Arc::clone(self.buffers.borrow().get(name).unwrap())
+            msg_manager.set_localvar("account", &account::current());
+            self.track(name, msg_manager)
+        }
+    }
+
+    /// Keep a buffer, and register it with the session of its account
+    fn track(&self, name: &str, buffer: MessageManager) -> Arc<MessageManager> {
+        if account::for_buffer(&buffer) == account::current() {
+            if let Some(session) = account::session() {
+                session.add_buffer(name);
+            }
+        }
+        let buffer = Arc::new(buffer);
+        self.buffers
+            .borrow_mut()
+            .insert(name.into(), Arc::clone(&buffer));
+        buffer
+    }
+
+    /// The buffers of the current account, as registered with its session
+    pub fn buffers(&self) -> Vec<Arc<MessageManager>> {
+        let session = match account::session() {
+            Some(session) => session,
+            None => return Vec::new(),
+        };
+        session
+            .buffer_names()
+            .iter()
+            .filter_map(|name| self.buffers.borrow().get(name).cloned())
+            .collect()
+    }
+
+    /// Forget a buffer that is being closed by weechat
+    pub fn forget_buffer(&self, name: &str) {
+        let buffer = self.buffers.borrow_mut().remove(name);
+        if let Some(buffer) = buffer {
+            let _account = account::enter(&account::for_buffer(&buffer));
+            if let Some(session) = account::session() {
+                session.remove_buffer(name);
+            }
+        }
+    }
+
+    /// Close a buffer and forget about it
+    pub fn close_buffer(&self, name: &str) {
+        let buffer = self.buffers.borrow().get(name).cloned();
+        if let Some(buffer) = buffer {
+            self.forget_buffer(name);
+            buffer.close();
+        } else if let Some(buffer) = self.weechat.buffer_search("weecord", name) {
+            buffer.close();
         }
     }
 }
@@ -6,7 +6,7 @@ use serenity::{
         id::{MessageId, UserId},
     },
 };
-use std::{cell::RefCell, ops::Deref, sync::Arc};
+use std::{any::Any, cell::RefCell, ops::Deref, sync::Arc};
 use weechat::Buffer;
 
 /// MessageRenderer wraps a weechat buffer and facilitates editing the buffer and drawing the
@@ -14,6 +14,9 @@ use weechat::Buffer;
 pub struct MessageManager {
     buffer: Buffer,
     messages: Arc<RefCell<Vec<Message>>>,
+    /// What a list buffer lists, so input can refer to the entries by number, dropped with the
+    /// buffer
+    list: RefCell<Option<Box<dyn Any>>>,
 }
 
 impl MessageManager {
@@ -22,6 +25,33 @@ impl MessageManager {
         MessageManager {
             buffer,
             messages: Arc::new(RefCell::new(Vec::new())),
+            list: RefCell::new(None),
+        }
+    }
+
+    /// Keep the entries listed in a list buffer, replacing the previous ones
+    pub fn set_list<T: 'static>(&self, list: T) {
+        *self.list.borrow_mut() = Some(Box::new(list));
+    }
+
+    /// Run a function with the listed entries, if the buffer lists entries of this type
+    pub fn with_list<T: 'static, R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.list
+            .borrow_mut()
+            .as_mut()
+            .and_then(|list| list.downcast_mut::<T>())
+            .map(f)
+    }
+
+    /// Remove the listed entries, if the buffer lists entries of this type
+    pub fn take_list<T: 'static>(&self) -> Option<T> {
+        let mut list = self.list.borrow_mut();
+        if list.as_ref().map_or(false, |list| list.is::<T>()) {
+            list.take()
+                .and_then(|list| list.downcast::<T>().ok())
+                .map(|list| *list)
+        } else {
+            None
         }
     }
 
@@ -33,7 +63,7 @@ impl MessageManager {
             formatting_utils::render_msg(cache, &weechat, msg, maybe_guild);
         self.buffer.print_tags_dated(
             msg.timestamp.timestamp(),
-            &formatting_utils::msg_tags(cache, msg, notify).join(","),
+            &formatting_utils::msg_tags(cache, msg, maybe_guild, notify).join(","),
             &format!("{}\t{}", prefix, content),
         );
         unknown_users
@@ -64,6 +94,94 @@ impl MessageManager {
         unknown_users
     }
 
+    /// The nth newest message in the buffer, starting at 1
+    pub fn nth_last_message(&self, n: usize) -> Option<Message> {
+        if n == 0 {
+            return None;
+        }
+        self.messages.borrow().iter().rev().nth(n - 1).cloned()
+    }
+
+    /// The id of the newest message in the buffer
+    pub fn last_message_id(&self) -> Option<MessageId> {
+        self.messages.borrow().iter().map(|msg| msg.id).max()
+    }
+
+    /// Whether a message is in the buffer
+    pub fn has_message(&self, id: MessageId) -> bool {
+        self.messages.borrow().iter().any(|msg| msg.id == id)
+    }
+
+    /// Scroll the current window so a message is at its top, returns false if the message is not
+    /// in the buffer
+    ///
+    /// Weechat can only scroll by line count or time, so this scrolls from the first message by
+    /// the time between the two, which lands on the first line sent in the same second
+    pub fn scroll_to_message(&self, id: MessageId) -> bool {
+        let messages = self.messages.borrow();
+        let (first, msg) = match (messages.first(), messages.iter().find(|msg| msg.id == id)) {
+            (Some(first), Some(msg)) => (first, msg),
+            _ => return false,
+        };
+        let offset = msg.timestamp.timestamp() - first.timestamp.timestamp();
+        let _ = self.buffer.run_command("/window scroll_top");
+        if offset > 0 {
+            let _ = self
+                .buffer
+                .run_command(&format!("/window scroll +{}s", offset));
+        }
+        true
+    }
+
+    /// Add messages that may be older than already printed ones, skipping any that were already
+    /// added and keeping the buffer in chronological order
+    pub fn merge_messages(
+        &self,
+        cache: &CacheRwLock,
+        msgs: Vec<Message>,
+        notify: bool,
+    ) -> Vec<UserId> {
+        let mut msgs: Vec<_> = {
+            let existing = self.messages.borrow();
+            msgs.into_iter()
+                .filter(|msg| !existing.iter().any(|it| it.id == msg.id))
+                .collect()
+        };
+        msgs.sort_by_key(|msg| msg.id);
+
+        let in_order = match (self.last_message_id(), msgs.first()) {
+            (Some(last), Some(first)) => first.id > last,
+            _ => true,
+        };
+        if in_order {
+            return msgs
+                .iter()
+                .flat_map(|msg| self.add_message(cache, msg, notify))
+                .collect();
+        }
+
+        // Messages arrived live while these were fetched, so everything needs to be reprinted
+        let mut unknown_users = Vec::new();
+        for mut msg in msgs {
+            if msg.referenced_message.is_some() && msg.message_reference.is_some() {
+                msg.kind = MessageType::InlineReply;
+            }
+            unknown_users.extend(
+                formatting_utils::render_msg(
+                    cache,
+                    &self.buffer.get_weechat(),
+                    &msg,
+                    self.buffer.guild_id(),
+                )
+                .2,
+            );
+            self.messages.borrow_mut().push(msg);
+        }
+        self.messages.borrow_mut().sort_by_key(|msg| msg.id);
+        self.redraw_buffer(cache);
+        unknown_users
+    }
+
     // Overwrite a previously printed message, has no effect if the message does not exist
     pub fn replace_message(
         &self,
@@ -122,6 +240,7 @@ impl Deref for MessageManager {
 mod formatting_utils {
     use crate::{
         discord::formatting,
+        ignore,
         utils::{colorize_string, format_nick_color},
     };
     use serenity::{
@@ -134,7 +253,14 @@ mod formatting_utils {
     use std::str::FromStr;
     use weechat::{ConfigOption, Weechat};
 
-    pub fn msg_tags(cache: &CacheRwLock, msg: &Message, notify: bool) -> Vec<String> {
+    /// Tags of a message printed in a buffer of `guild`, messages fetched from the api do not
+    /// have a guild id themselves
+    pub fn msg_tags(
+        cache: &CacheRwLock,
+        msg: &Message,
+        guild: Option<GuildId>,
+        notify: bool,
+    ) -> Vec<String> {
         let is_private = if let Some(channel) = msg.channel(cache) {
             if let Channel::Private(_) = channel {
                 true
@@ -146,9 +272,13 @@ mod formatting_utils {
         };
 
         let self_mentioned = msg.mentions_user_id(cache.read().user.id);
+        let ignored = ignore::is_ignored(msg.author.id, guild);
 
         let mut tags = Vec::new();
-        if notify {
+        if ignored {
+            tags.push(ignore::IGNORED_TAG);
+        }
+        if notify && !ignored {
             if self_mentioned {
                 tags.push("notify_highlight");
             } else if is_private {
@@ -174,6 +304,14 @@ mod formatting_utils {
             .clean_everyone(false)
             .clean_user(false);
 
+        if ignore::is_ignored(msg.author.id, guild) && ignore::use_placeholder() {
+            return (
+                format_nick_color(weechat, &author_display_name(cache, &msg, guild)),
+                ignored_placeholder(weechat),
+                Vec::new(),
+            );
+        }
+
         let mut msg_content = serenity::utils::content_safe(&cache, &msg.content, &opts);
         msg_content = crate::utils::clean_emojis(&msg_content);
         let unknown_users = clean_users(cache, &mut msg_content, true, guild);
@@ -299,6 +437,21 @@ mod formatting_utils {
                 unknown_users,
             ),
             InlineReply => match msg.referenced_message.as_ref() {
+                // A filter can only hide whole lines, so quotes are always collapsed
+                Some(ref_msg) if ignore::is_ignored(ref_msg.author.id, guild) => {
+                    let ref_prefix =
+                        format_nick_color(weechat, &author_display_name(cache, &ref_msg, guild));
+                    (
+                        prefix,
+                        format!(
+                            "{}:\n▎{}\n{}",
+                            ref_prefix,
+                            ignored_placeholder(weechat),
+                            msg_content
+                        ),
+                        unknown_users,
+                    )
+                },
                 Some(ref_msg) => {
                     let (ref_prefix, ref_msg_content, mut ref_unknown_users) =
                         render_msg(cache, weechat, &ref_msg, guild);
@@ -357,6 +510,10 @@ mod formatting_utils {
         }
     }
 
+    fn ignored_placeholder(weechat: &Weechat) -> String {
+        colorize_string(weechat, "8", "[message from ignored user]")
+    }
+
     pub fn author_display_name(
         cache: &CacheRwLock,
         msg: &Message,